@@ -0,0 +1,28 @@
+//! Packs images into an atlas and saves it as a PNG with a JSON manifest.
+//!
+//! Usage: `cargo run --example bake_atlas -- <output.png> <images...>`
+
+use game_engine::atlas::AtlasBuilder;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let output = args
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("usage: bake_atlas <output.png> <images...>"))?;
+
+    let mut builder = AtlasBuilder::new();
+    for path in args {
+        builder = builder.add_file(path)?;
+    }
+    let atlas = builder.build()?;
+    atlas.save(&output)?;
+
+    println!(
+        "Packed {} images into a {}x{} atlas at {}",
+        atlas.regions().count(),
+        atlas.width(),
+        atlas.height(),
+        output
+    );
+    Ok(())
+}
@@ -0,0 +1,782 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::atlas::{Atlas, UvRect};
+use crate::ecs::{system, Access, FnSystem, World};
+use crate::quad::Instance;
+use crate::scene::Sprite;
+use crate::time::FrameTime;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AnimationError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid Aseprite file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("tag `{name}` spans frames {from}..={to}, but there are {count} frames")]
+    InvalidTag {
+        name: String,
+        from: usize,
+        to: usize,
+        count: usize,
+    },
+    #[error("the sprite sheet has no frame named `{name}`")]
+    UnknownFrame { name: String },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PlayMode {
+    /// Starts over after the last frame.
+    #[default]
+    Loop,
+    /// Plays back and forth, without repeating the first and last frames.
+    PingPong,
+    /// Stops on the last frame.
+    Once,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipFrame {
+    /// The frame of the sprite sheet.
+    pub index: usize,
+    /// In seconds.
+    pub duration: f32,
+}
+
+/// A sequence of sprite sheet frames, and the events fired when playback
+/// reaches some of them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Clip {
+    /// Added with `frame`, which keeps the durations positive.
+    frames: Vec<ClipFrame>,
+    pub mode: PlayMode,
+    /// Event names by position in `frames`.
+    pub events: Vec<(usize, String)>,
+}
+
+impl Clip {
+    pub fn new(mode: PlayMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    /// The sheet frames in `indices`, each shown for `duration` seconds.
+    pub fn uniform(
+        indices: impl IntoIterator<Item = usize>,
+        duration: f32,
+        mode: PlayMode,
+    ) -> Self {
+        indices
+            .into_iter()
+            .fold(Self::new(mode), |clip, index| clip.frame(index, duration))
+    }
+
+    /// Panics if `duration` is not positive.
+    pub fn frame(mut self, index: usize, duration: f32) -> Self {
+        assert!(duration > 0.0, "frame durations must be positive");
+        self.frames.push(ClipFrame { index, duration });
+        self
+    }
+
+    pub fn frames(&self) -> &[ClipFrame] {
+        &self.frames
+    }
+
+    /// Fires `name` whenever playback reaches the clip's `frame`th frame.
+    pub fn with_event(mut self, frame: usize, name: impl Into<String>) -> Self {
+        self.events.push((frame, name.into()));
+        self
+    }
+
+    /// Seconds one pass through the frames takes.
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|frame| frame.duration).sum()
+    }
+}
+
+/// The frames of one texture, as UV rectangles, and the clips playing them.
+#[derive(Debug, Clone, Default)]
+pub struct SpriteSheet {
+    pub texture: PathBuf,
+    frames: Vec<UvRect>,
+    names: HashMap<String, usize>,
+    clips: HashMap<String, Clip>,
+}
+
+impl SpriteSheet {
+    pub fn new(texture: impl Into<PathBuf>) -> Self {
+        Self {
+            texture: texture.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Splits the texture into `columns` by `rows` equally sized frames,
+    /// numbered row by row from the top left.
+    pub fn grid(texture: impl Into<PathBuf>, columns: u32, rows: u32) -> Self {
+        let mut sheet = Self::new(texture);
+        let (width, height) = (1.0 / columns as f32, 1.0 / rows as f32);
+        for row in 0..rows {
+            for column in 0..columns {
+                let min = [column as f32 * width, row as f32 * height];
+                sheet.add_frame(
+                    None,
+                    UvRect {
+                        min,
+                        max: [min[0] + width, min[1] + height],
+                    },
+                );
+            }
+        }
+        sheet
+    }
+
+    /// One frame per region of `atlas`, named after it. `texture` is where the
+    /// atlas image is, or the path it was given with `Renderer::insert_texture`.
+    pub fn from_atlas(texture: impl Into<PathBuf>, atlas: &Atlas) -> Self {
+        let mut sheet = Self::new(texture);
+        for (name, region) in atlas.regions() {
+            sheet.add_frame(Some(name), region.uv);
+        }
+        sheet
+    }
+
+    /// Loads an Aseprite JSON export, in either the array or the hash layout.
+    /// Every tag becomes a clip, or the whole animation a looping clip named
+    /// `default` if there are none. Tags with a repeat count of 1 play once,
+    /// other repeat counts loop.
+    pub fn load_aseprite(path: impl AsRef<Path>) -> Result<Self, AnimationError> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).map_err(|source| AnimationError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let file: AsepriteFile =
+            serde_json::from_str(&json).map_err(|source| AnimationError::Parse {
+                path: path.to_owned(),
+                source,
+            })?;
+        let texture = path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&file.meta.image);
+        file.into_sheet(texture)
+    }
+
+    /// Adds a frame, returning its index.
+    pub fn add_frame(&mut self, name: Option<&str>, uv: UvRect) -> usize {
+        let index = self.frames.len();
+        self.frames.push(uv);
+        if let Some(name) = name {
+            self.names.insert(name.to_owned(), index);
+        }
+        index
+    }
+
+    pub fn add_clip(&mut self, name: impl Into<String>, clip: Clip) {
+        self.clips.insert(name.into(), clip);
+    }
+
+    pub fn with_clip(mut self, name: impl Into<String>, clip: Clip) -> Self {
+        self.add_clip(name, clip);
+        self
+    }
+
+    /// A clip showing the frames called `names`, each for `duration` seconds.
+    pub fn clip_from_names(
+        &self,
+        names: &[&str],
+        duration: f32,
+        mode: PlayMode,
+    ) -> Result<Clip, AnimationError> {
+        let indices = names
+            .iter()
+            .map(|&name| {
+                self.frame_index(name)
+                    .ok_or_else(|| AnimationError::UnknownFrame {
+                        name: name.to_owned(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Clip::uniform(indices, duration, mode))
+    }
+
+    pub fn frame(&self, index: usize) -> Option<UvRect> {
+        self.frames.get(index).copied()
+    }
+
+    pub fn frame_index(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clip(&self, name: &str) -> Option<&Clip> {
+        self.clips.get(name)
+    }
+
+    pub fn clip_names(&self) -> impl Iterator<Item = &str> {
+        self.clips.keys().map(String::as_str)
+    }
+}
+
+/// Plays a clip of a `SpriteSheet` on an entity's `Instance`, setting its UV
+/// rectangle and its `Sprite`'s texture. Advanced by `animate_sprites`.
+#[derive(Debug, Clone)]
+pub struct SpriteAnimation {
+    sheet: Rc<SpriteSheet>,
+    clip: String,
+    /// Playback rate, `2.0` plays twice as fast. Negative speeds pause.
+    pub speed: f32,
+    position: usize,
+    elapsed: f32,
+    backwards: bool,
+    started: bool,
+    finished: bool,
+    events: Vec<String>,
+}
+
+impl SpriteAnimation {
+    pub fn new(sheet: Rc<SpriteSheet>, clip: impl Into<String>) -> Self {
+        Self {
+            sheet,
+            clip: clip.into(),
+            speed: 1.0,
+            position: 0,
+            elapsed: 0.0,
+            backwards: false,
+            started: false,
+            finished: false,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn sheet(&self) -> &Rc<SpriteSheet> {
+        &self.sheet
+    }
+
+    pub fn clip_name(&self) -> &str {
+        &self.clip
+    }
+
+    pub fn clip(&self) -> Option<&Clip> {
+        self.sheet.clip(&self.clip)
+    }
+
+    /// Switches to `clip` from its first frame, unless it is already playing.
+    pub fn play(&mut self, clip: &str) {
+        if self.clip != clip {
+            self.clip = clip.to_owned();
+            self.restart();
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.position = 0;
+        self.elapsed = 0.0;
+        self.backwards = false;
+        self.started = false;
+        self.finished = false;
+    }
+
+    /// Whether a `PlayMode::Once` clip reached its end.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// The sheet frame being shown, or `None` if the clip does not exist or
+    /// has no frames.
+    pub fn frame(&self) -> Option<usize> {
+        Some(self.clip()?.frames.get(self.position)?.index)
+    }
+
+    pub fn uv(&self) -> Option<UvRect> {
+        self.sheet.frame(self.frame()?)
+    }
+
+    /// The events of the frames reached since they were last taken, in order.
+    pub fn events(&self) -> &[String] {
+        &self.events
+    }
+
+    /// Returns the pending events and clears them.
+    pub fn take_events(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Moves playback `dt` seconds forward, scaled by `speed`.
+    pub fn advance(&mut self, dt: f32) {
+        let sheet = self.sheet.clone();
+        let clip = match sheet.clip(&self.clip) {
+            Some(clip) if !clip.frames.is_empty() => clip,
+            _ => return,
+        };
+        if !self.started {
+            self.started = true;
+            self.fire_events(clip);
+        }
+        if self.finished {
+            return;
+        }
+
+        self.elapsed += dt * self.speed.max(0.0);
+        let last = clip.frames.len() - 1;
+        while self.elapsed >= clip.frames[self.position].duration {
+            self.elapsed -= clip.frames[self.position].duration;
+            self.position = match clip.mode {
+                PlayMode::Loop if self.position == last => 0,
+                PlayMode::Once if self.position == last => {
+                    self.finished = true;
+                    self.elapsed = 0.0;
+                    return;
+                }
+                PlayMode::Loop | PlayMode::Once => self.position + 1,
+                PlayMode::PingPong if last == 0 => 0,
+                PlayMode::PingPong => {
+                    if self.position == last {
+                        self.backwards = true;
+                    } else if self.position == 0 {
+                        self.backwards = false;
+                    }
+                    if self.backwards {
+                        self.position - 1
+                    } else {
+                        self.position + 1
+                    }
+                }
+            };
+            self.fire_events(clip);
+        }
+    }
+
+    fn fire_events(&mut self, clip: &Clip) {
+        self.events.extend(
+            clip.events
+                .iter()
+                .filter(|(frame, _)| *frame == self.position)
+                .map(|(_, name)| name.clone()),
+        );
+    }
+}
+
+/// Advances every `SpriteAnimation` by the frame's `dt` and shows its current
+/// frame. Entities without a `Sprite` get one for the sheet's texture once the
+/// system finishes.
+pub fn animate_sprites() -> FnSystem<impl FnMut(&World)> {
+    system(
+        "animate_sprites",
+        Access::new()
+            .write::<SpriteAnimation>()
+            .write::<Instance>()
+            .write::<Sprite>()
+            .read::<FrameTime>(),
+        animate,
+    )
+}
+
+fn animate(world: &World) {
+    let dt = world.resource::<FrameTime>().dt;
+    let mut sprites = world.write::<Sprite>();
+    let mut missing = Vec::new();
+    for (entity, (animation, instance)) in world
+        .query::<(&mut SpriteAnimation, &mut Instance)>()
+        .iter()
+    {
+        animation.advance(dt);
+        if let Some(uv) = animation.uv() {
+            instance.uv = uv;
+        }
+        let texture = &animation.sheet.texture;
+        match sprites.as_mut().and_then(|sprites| sprites.get_mut(entity)) {
+            Some(sprite) => {
+                if sprite.texture.as_ref() != Some(texture) {
+                    sprite.texture = Some(texture.clone());
+                }
+            }
+            None => missing.push((entity, Sprite::new(texture.clone()))),
+        }
+    }
+    if !missing.is_empty() {
+        world.defer(move |world| {
+            for (entity, sprite) in missing {
+                if world.is_alive(entity) {
+                    world.insert(entity, sprite);
+                }
+            }
+        });
+    }
+}
+
+#[derive(Deserialize)]
+struct AsepriteFile {
+    frames: AsepriteFrames,
+    meta: AsepriteMeta,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AsepriteFrames {
+    Array(Vec<AsepriteFrame>),
+    Hash(FramesByName),
+}
+
+#[derive(Deserialize)]
+struct AsepriteFrame {
+    #[serde(default)]
+    filename: String,
+    frame: AsepriteRect,
+    /// In milliseconds.
+    duration: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteSize {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteMeta {
+    image: PathBuf,
+    size: AsepriteSize,
+    #[serde(default, rename = "frameTags")]
+    frame_tags: Vec<AsepriteTag>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+    #[serde(default)]
+    repeat: Option<String>,
+}
+
+/// The hash layout's frames in file order, which is the animation's order.
+struct FramesByName(Vec<AsepriteFrame>);
+
+impl<'de> Deserialize<'de> for FramesByName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FramesVisitor;
+
+        impl<'de> Visitor<'de> for FramesVisitor {
+            type Value = FramesByName;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of frame names to frames")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut frames = Vec::new();
+                while let Some((filename, frame)) = map.next_entry::<String, AsepriteFrame>()? {
+                    frames.push(AsepriteFrame { filename, ..frame });
+                }
+                Ok(FramesByName(frames))
+            }
+        }
+
+        deserializer.deserialize_map(FramesVisitor)
+    }
+}
+
+impl AsepriteFile {
+    fn into_sheet(self, texture: PathBuf) -> Result<SpriteSheet, AnimationError> {
+        let frames = match self.frames {
+            AsepriteFrames::Array(frames) => frames,
+            AsepriteFrames::Hash(FramesByName(frames)) => frames,
+        };
+        let (width, height) = (self.meta.size.w as f32, self.meta.size.h as f32);
+        let mut sheet = SpriteSheet::new(texture);
+        for frame in &frames {
+            let rect = &frame.frame;
+            let uv = UvRect {
+                min: [rect.x as f32 / width, rect.y as f32 / height],
+                max: [
+                    (rect.x + rect.w) as f32 / width,
+                    (rect.y + rect.h) as f32 / height,
+                ],
+            };
+            let name = (!frame.filename.is_empty()).then_some(frame.filename.as_str());
+            sheet.add_frame(name, uv);
+        }
+        let clip_frame = |index: usize| ClipFrame {
+            index,
+            duration: frames[index].duration.max(1) as f32 / 1000.0,
+        };
+
+        if self.meta.frame_tags.is_empty() {
+            sheet.add_clip(
+                "default",
+                Clip {
+                    frames: (0..frames.len()).map(clip_frame).collect(),
+                    ..Clip::default()
+                },
+            );
+        }
+        for tag in self.meta.frame_tags {
+            if tag.from > tag.to || tag.to >= frames.len() {
+                return Err(AnimationError::InvalidTag {
+                    name: tag.name,
+                    from: tag.from,
+                    to: tag.to,
+                    count: frames.len(),
+                });
+            }
+            let mut clip_frames = (tag.from..=tag.to).map(clip_frame).collect::<Vec<_>>();
+            if tag.direction.ends_with("reverse") {
+                clip_frames.reverse();
+            }
+            let mode = if tag.direction.starts_with("pingpong") {
+                PlayMode::PingPong
+            } else if tag.repeat.as_deref() == Some("1") {
+                PlayMode::Once
+            } else {
+                PlayMode::Loop
+            };
+            sheet.add_clip(
+                tag.name,
+                Clip {
+                    frames: clip_frames,
+                    mode,
+                    events: Vec::new(),
+                },
+            );
+        }
+        Ok(sheet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn animation(clip: Clip) -> SpriteAnimation {
+        let sheet = SpriteSheet::grid("sheet.png", 4, 1).with_clip("clip", clip);
+        SpriteAnimation::new(Rc::new(sheet), "clip")
+    }
+
+    /// The frame shown after each of `steps` advances of `dt`.
+    fn frames(animation: &mut SpriteAnimation, dt: f32, steps: usize) -> Vec<usize> {
+        (0..steps)
+            .map(|_| {
+                animation.advance(dt);
+                animation.frame().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_starts_over() {
+        let mut animation = animation(Clip::uniform(0..3, 0.25, PlayMode::Loop));
+        assert_eq!(frames(&mut animation, 0.25, 7), [1, 2, 0, 1, 2, 0, 1]);
+        assert!(!animation.is_finished());
+    }
+
+    #[test]
+    fn ping_pong_skips_the_ends() {
+        let mut animation = animation(Clip::uniform(0..3, 0.25, PlayMode::PingPong));
+        assert_eq!(frames(&mut animation, 0.25, 7), [1, 2, 1, 0, 1, 2, 1]);
+
+        let mut single = self::animation(Clip::uniform([3], 0.25, PlayMode::PingPong));
+        assert_eq!(frames(&mut single, 0.25, 3), [3, 3, 3]);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut animation = animation(Clip::uniform(0..3, 0.25, PlayMode::Once));
+        assert_eq!(frames(&mut animation, 0.25, 2), [1, 2]);
+        assert!(!animation.is_finished());
+        assert_eq!(frames(&mut animation, 0.25, 3), [2, 2, 2]);
+        assert!(animation.is_finished());
+
+        animation.restart();
+        assert_eq!(frames(&mut animation, 0.0, 1), [0]);
+    }
+
+    #[test]
+    fn speed_scales_time() {
+        let clip = Clip::uniform(0..3, 0.25, PlayMode::Loop);
+        let mut fast = animation(clip.clone()).with_speed(2.0);
+        assert_eq!(frames(&mut fast, 0.25, 2), [2, 1]);
+        let mut paused = animation(clip).with_speed(-1.0);
+        assert_eq!(frames(&mut paused, 0.25, 2), [0, 0]);
+    }
+
+    #[test]
+    fn events_fire_at_wrap_boundaries() {
+        let clip = Clip::uniform(0..3, 0.25, PlayMode::Loop)
+            .with_event(0, "start")
+            .with_event(2, "end");
+        let mut animation = animation(clip);
+
+        animation.advance(0.0);
+        assert_eq!(animation.take_events(), ["start"]);
+        animation.advance(0.5);
+        assert_eq!(animation.take_events(), ["end"]);
+        animation.advance(0.25);
+        assert_eq!(animation.take_events(), ["start"]);
+        assert!(animation.events().is_empty());
+    }
+
+    #[test]
+    fn events_accumulate_until_taken() {
+        let clip = Clip::uniform(0..3, 0.25, PlayMode::Loop)
+            .with_event(0, "start")
+            .with_event(2, "end");
+        let mut animation = animation(clip);
+
+        // Three full passes and one frame in a single step
+        animation.advance(2.5);
+        assert_eq!(
+            animation.events(),
+            ["start", "end", "start", "end", "start", "end", "start"]
+        );
+        assert_eq!(animation.frame(), Some(1));
+        animation.advance(0.25);
+        animation.advance(0.25);
+        assert_eq!(animation.take_events().len(), 9);
+        assert_eq!(animation.frame(), Some(0));
+    }
+
+    #[test]
+    fn once_fires_the_last_event_once() {
+        let clip = Clip::uniform(0..2, 0.25, PlayMode::Once).with_event(1, "done");
+        let mut animation = animation(clip);
+        animation.advance(10.0);
+        animation.advance(10.0);
+        assert_eq!(animation.take_events(), ["done"]);
+        assert!(animation.is_finished());
+    }
+
+    #[test]
+    #[should_panic(expected = "frame durations must be positive")]
+    fn rejects_zero_durations() {
+        Clip::new(PlayMode::Loop).frame(0, 0.0);
+    }
+
+    const ASEPRITE_ARRAY: &str = r#"{
+        "frames": [
+            { "filename": "walk 0", "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "duration": 100 },
+            { "filename": "walk 1", "frame": { "x": 16, "y": 0, "w": 16, "h": 16 }, "duration": 200 },
+            { "filename": "walk 2", "frame": { "x": 32, "y": 0, "w": 16, "h": 16 }, "duration": 0 },
+            { "filename": "jump", "frame": { "x": 0, "y": 16, "w": 16, "h": 16 }, "duration": 100 }
+        ],
+        "meta": {
+            "image": "hero.png",
+            "size": { "w": 64, "h": 32 },
+            "frameTags": [
+                { "name": "walk", "from": 0, "to": 2, "direction": "forward" },
+                { "name": "back", "from": 0, "to": 2, "direction": "reverse" },
+                { "name": "sway", "from": 0, "to": 2, "direction": "pingpong" },
+                { "name": "jump", "from": 3, "to": 3, "direction": "forward", "repeat": "1" }
+            ]
+        }
+    }"#;
+
+    const ASEPRITE_HASH: &str = r#"{
+        "frames": {
+            "b": { "frame": { "x": 16, "y": 0, "w": 16, "h": 16 }, "duration": 50 },
+            "a": { "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "duration": 150 }
+        },
+        "meta": { "image": "hash.png", "size": { "w": 32, "h": 16 } }
+    }"#;
+
+    fn parse(json: &str) -> Result<SpriteSheet, AnimationError> {
+        serde_json::from_str::<AsepriteFile>(json)
+            .unwrap()
+            .into_sheet(PathBuf::from("hero.png"))
+    }
+
+    #[test]
+    fn imports_aseprite_tags() {
+        let sheet = parse(ASEPRITE_ARRAY).unwrap();
+        assert_eq!(sheet.frame_count(), 4);
+        assert_eq!(sheet.frame_index("jump"), Some(3));
+        assert_eq!(
+            sheet.frame(1),
+            Some(UvRect {
+                min: [0.25, 0.0],
+                max: [0.5, 0.5]
+            })
+        );
+        assert!(sheet.clip("default").is_none());
+
+        let walk = sheet.clip("walk").unwrap();
+        assert_eq!(walk.mode, PlayMode::Loop);
+        let durations = walk.frames().iter().map(|frame| frame.duration);
+        assert_eq!(durations.collect::<Vec<_>>(), [0.1, 0.2, 0.001]);
+
+        let back = sheet.clip("back").unwrap();
+        let indices = back.frames().iter().map(|frame| frame.index);
+        assert_eq!(indices.collect::<Vec<_>>(), [2, 1, 0]);
+        assert_eq!(sheet.clip("sway").unwrap().mode, PlayMode::PingPong);
+        assert_eq!(sheet.clip("jump").unwrap().mode, PlayMode::Once);
+    }
+
+    #[test]
+    fn imports_the_aseprite_hash_layout_in_file_order() {
+        let sheet = parse(ASEPRITE_HASH).unwrap();
+        assert_eq!(sheet.frame_index("b"), Some(0));
+        assert_eq!(sheet.frame_index("a"), Some(1));
+        let clip = sheet.clip("default").unwrap();
+        assert_eq!(clip.mode, PlayMode::Loop);
+        assert_eq!(clip.duration(), 0.2);
+    }
+
+    #[test]
+    fn rejects_tags_past_the_last_frame() {
+        let json = ASEPRITE_HASH.replace(
+            r#""size""#,
+            r#""frameTags": [{ "name": "bad", "from": 1, "to": 2 }], "size""#,
+        );
+        assert!(matches!(
+            parse(&json),
+            Err(AnimationError::InvalidTag {
+                to: 2,
+                count: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn loads_aseprite_files_next_to_their_image() {
+        let dir = TempDir::new("aseprite");
+        let path = dir.write("hero.json", ASEPRITE_ARRAY);
+
+        let sheet = SpriteSheet::load_aseprite(&path).unwrap();
+        assert_eq!(sheet.texture, dir.join("hero.png"));
+        assert_eq!(sheet.clip_names().count(), 4);
+    }
+}
@@ -0,0 +1,250 @@
+use std::cell::{Ref, RefMut};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use winit::{
+    dpi::PhysicalSize,
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+
+use crate::camera::Camera;
+use crate::config::{CommandLine, Config, WindowState, DEFAULT_CONFIG_PATH, USAGE};
+use crate::ecs::{Schedule, World};
+use crate::input::InputHandler;
+use crate::renderer::{ContextOptions, Renderer};
+use crate::scene::{self, Scene};
+use crate::text::Fonts;
+use crate::time::{FrameClock, FrameLimiter, FrameTime};
+
+pub struct Engine {
+    pub config: Config,
+    pub renderer: Renderer,
+    /// Holds the `Scene` and `FrameTime` resources alongside the game's
+    /// entities.
+    pub world: World,
+    /// Runs after `App::render`, before the scene is drawn. Starts out with the
+    /// built-in systems that extract cameras, instances and lights.
+    pub render_schedule: Schedule,
+    pub input: InputHandler,
+    pub clock: FrameClock,
+    pub limiter: FrameLimiter,
+    pub time: FrameTime,
+}
+
+impl Engine {
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width > 0 && size.height > 0 {
+            self.renderer.resize(size);
+            self.scene_mut().resize(size.width, size.height);
+            for (_, camera) in self.world.query::<&mut Camera>().iter() {
+                camera.resize(size.width, size.height);
+            }
+        }
+    }
+
+    pub fn scene(&self) -> Ref<'_, Scene> {
+        self.world.resource()
+    }
+
+    pub fn scene_mut(&self) -> RefMut<'_, Scene> {
+        self.world.resource_mut()
+    }
+}
+
+pub trait App: Sized + 'static {
+    /// Chooses the graphics backends, adapter and device features to use.
+    fn context_options() -> ContextOptions {
+        ContextOptions::default()
+    }
+
+    fn init(engine: &mut Engine) -> Self;
+
+    /// Called zero or more times per frame with the fixed timestep of `engine.clock`.
+    fn update(&mut self, engine: &mut Engine, dt: f32);
+
+    /// Called once per frame. `alpha` is how far the current frame lies between
+    /// the previous and the latest fixed update, for interpolating state.
+    fn render(&mut self, _engine: &mut Engine, _alpha: f32) {}
+}
+
+/// Opens a window configured by the config file and command line, and runs
+/// `A` until the window is closed. The window's final size, position and mode
+/// are written back to the config file.
+pub fn run<A: App>() -> ! {
+    let command_line = match CommandLine::parse(std::env::args().skip(1)) {
+        Ok(command_line) => command_line,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if command_line.help {
+        println!("{}", USAGE);
+        std::process::exit(0);
+    }
+
+    let config_path = command_line
+        .config_path
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+    let mut stored_config = Config::load(&config_path).unwrap_or_else(|e| {
+        log::error!("Using the default config: {}", e);
+        Config::default()
+    });
+    let mut config = stored_config.clone();
+    command_line.apply(&mut config);
+
+    let event_loop = EventLoop::new();
+    // Stay hidden until positioned, since the monitor to center on is only
+    // known once the window exists.
+    let window = WindowBuilder::new()
+        .with_title(&config.window.title)
+        .with_inner_size(PhysicalSize::new(config.window.width, config.window.height))
+        .with_resizable(config.window.resizable)
+        .with_visible(false)
+        .build(&event_loop)
+        .unwrap();
+    let monitor = window
+        .current_monitor()
+        .or_else(|| event_loop.primary_monitor());
+    let position = config
+        .window
+        .position(monitor.as_ref(), window.outer_size());
+    if let Some(position) = position {
+        window.set_outer_position(position);
+    }
+    window.set_fullscreen(config.window.fullscreen(monitor));
+    window.set_visible(true);
+    let opened = WindowState {
+        mode: config.window.mode,
+        size: (config.window.width, config.window.height),
+        position: position.map(|position| (position.x, position.y)),
+    };
+
+    let mut options = A::context_options();
+    if let Some(backends) = config.graphics.backend.backends() {
+        options.backends = backends;
+    }
+    options.present_mode = config.graphics.present_mode.into();
+
+    let renderer = match pollster::block_on(Renderer::new(&window, &options)) {
+        Ok(renderer) => renderer,
+        Err(e) => {
+            log::error!("Failed to initialize the renderer: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let size = renderer.get_size();
+    let mut world = World::new();
+    world.insert_resource(Scene::new(Camera::basic(
+        size.width,
+        size.height,
+        cgmath::Deg(45.0),
+    )));
+    world.insert_resource(FrameTime::default());
+    world.insert_resource(Fonts::new());
+
+    let mut engine = Engine {
+        limiter: FrameLimiter::new(config.graphics.max_fps),
+        config,
+        renderer,
+        world,
+        render_schedule: scene::extract_schedule(),
+        input: InputHandler::new(),
+        clock: FrameClock::default(),
+        time: FrameTime::default(),
+    };
+    let mut app = A::init(&mut engine);
+    engine.clock.reset();
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::RedrawRequested(_) => {
+            let time = engine.clock.tick();
+            engine.time = time;
+            engine.world.insert_resource(time);
+            for _ in 0..time.steps {
+                app.update(&mut engine, time.fixed_dt);
+            }
+            // Keep clicks around until an update has had the chance to see them
+            if time.steps > 0 {
+                if engine.input.screenshot_requested() {
+                    let path = screenshot_path();
+                    match engine.renderer.save_screenshot(&path) {
+                        Ok(()) => log::info!("Saved screenshot to {}", path.display()),
+                        Err(e) => log::error!("Failed to save screenshot: {:?}", e),
+                    }
+                }
+                engine.input.frame();
+            }
+
+            app.render(&mut engine, time.alpha);
+            engine.render_schedule.run(&mut engine.world);
+            let result = engine.renderer.render(&engine.world.resource::<Scene>());
+            match result {
+                Ok(_) => {}
+                Err(wgpu::SurfaceError::Lost) => {
+                    let size = engine.renderer.get_size();
+                    engine.renderer.resize(size)
+                }
+                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                Err(e) => eprintln!("{:?}", e),
+            }
+            engine.limiter.wait();
+        }
+        Event::MainEventsCleared => window.request_redraw(),
+        Event::LoopDestroyed => {
+            stored_config.window.store_state(&window, &opened);
+            if let Err(e) = stored_config.save(&config_path) {
+                log::error!("Failed to save window state: {}", e);
+            }
+        }
+        Event::WindowEvent {
+            ref event,
+            window_id,
+        } if window_id == window.id() => match event {
+            WindowEvent::CloseRequested
+            | WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Escape),
+                        ..
+                    },
+                ..
+            } => *control_flow = ControlFlow::Exit,
+            &WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: key_state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => engine.input.update_key(key, key_state),
+            &WindowEvent::MouseWheel { delta, .. } => engine.input.update_wheel(delta),
+            &WindowEvent::MouseInput {
+                state: button_state,
+                button,
+                ..
+            } => engine.input.update_button(button, button_state),
+            &WindowEvent::CursorMoved { position, .. } => engine.input.update_cursor(position),
+            WindowEvent::Resized(size) => engine.resize(*size),
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                engine.resize(**new_inner_size)
+            }
+            _ => {}
+        },
+        _ => {}
+    })
+}
+
+fn screenshot_path() -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    PathBuf::from("screenshots").join(format!("screenshot_{}.png", timestamp))
+}
@@ -0,0 +1,599 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::buffers::ToData;
+use crate::renderer::Context;
+use crate::texture::Texture;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AtlasError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to write {path}: {source}")]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to load or save image {path}: {source}")]
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("invalid atlas manifest {path}: {source}")]
+    Manifest {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("the atlas already has an image named `{name}`")]
+    DuplicateName { name: String },
+    #[error("the images do not fit in a {max_size}x{max_size} atlas")]
+    TooLarge { max_size: u32 },
+}
+
+/// A rectangle of texture coordinates, with `min` at the top left.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UvRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl UvRect {
+    /// The whole texture.
+    pub const FULL: UvRect = UvRect {
+        min: [0.0, 0.0],
+        max: [1.0, 1.0],
+    };
+}
+
+impl ToData for UvRect {
+    type Data = [f32; 4];
+
+    fn to_data(&self) -> Self::Data {
+        [self.min[0], self.min[1], self.max[0], self.max[1]]
+    }
+}
+
+impl Default for UvRect {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+/// A rectangle of pixels, with `x` and `y` at the top left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Where one image ended up in an atlas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    pub rect: AtlasRect,
+    pub uv: UvRect,
+}
+
+impl AtlasRegion {
+    pub(crate) fn new(rect: AtlasRect, width: u32, height: u32) -> Self {
+        let (width, height) = (width as f32, height as f32);
+        Self {
+            rect,
+            uv: UvRect {
+                min: [rect.x as f32 / width, rect.y as f32 / height],
+                max: [
+                    (rect.x + rect.width) as f32 / width,
+                    (rect.y + rect.height) as f32 / height,
+                ],
+            },
+        }
+    }
+}
+
+/// The JSON file saved next to a baked atlas image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct AtlasManifest {
+    /// Relative to the manifest.
+    image: PathBuf,
+    width: u32,
+    height: u32,
+    regions: BTreeMap<String, AtlasRect>,
+}
+
+/// Many images packed into one, with the region each was packed into by name.
+pub struct Atlas {
+    image: RgbaImage,
+    regions: BTreeMap<String, AtlasRegion>,
+}
+
+impl Atlas {
+    pub fn builder() -> AtlasBuilder {
+        AtlasBuilder::new()
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    pub fn region(&self, name: &str) -> Option<&AtlasRegion> {
+        self.regions.get(name)
+    }
+
+    pub fn uv(&self, name: &str) -> Option<UvRect> {
+        self.region(name).map(|region| region.uv)
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = (&str, &AtlasRegion)> {
+        self.regions
+            .iter()
+            .map(|(name, region)| (name.as_str(), region))
+    }
+
+    pub fn texture(&self, context: &Context, label: &str) -> anyhow::Result<Texture> {
+        Texture::from_image(
+            context,
+            &DynamicImage::ImageRgba8(self.image.clone()),
+            Some(label),
+        )
+    }
+
+    /// Writes the image to `path`, which should be a `.png`, and the manifest
+    /// next to it with the extension changed to `.json`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), AtlasError> {
+        let path = path.as_ref();
+        self.image.save(path).map_err(|source| AtlasError::Image {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        let manifest_path = path.with_extension("json");
+        let manifest = AtlasManifest {
+            image: path.file_name().map(PathBuf::from).unwrap_or_default(),
+            width: self.width(),
+            height: self.height(),
+            regions: self
+                .regions
+                .iter()
+                .map(|(name, region)| (name.clone(), region.rect))
+                .collect(),
+        };
+        let json =
+            serde_json::to_string_pretty(&manifest).map_err(|source| AtlasError::Manifest {
+                path: manifest_path.clone(),
+                source,
+            })?;
+        std::fs::write(&manifest_path, json).map_err(|source| AtlasError::Write {
+            path: manifest_path,
+            source,
+        })
+    }
+
+    /// Loads an atlas saved with `save` from its manifest.
+    pub fn load(manifest_path: impl AsRef<Path>) -> Result<Self, AtlasError> {
+        let manifest_path = manifest_path.as_ref();
+        let json = std::fs::read_to_string(manifest_path).map_err(|source| AtlasError::Read {
+            path: manifest_path.to_owned(),
+            source,
+        })?;
+        let manifest: AtlasManifest =
+            serde_json::from_str(&json).map_err(|source| AtlasError::Manifest {
+                path: manifest_path.to_owned(),
+                source,
+            })?;
+
+        let image_path = manifest_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&manifest.image);
+        let image = image::open(&image_path)
+            .map_err(|source| AtlasError::Image {
+                path: image_path,
+                source,
+            })?
+            .to_rgba8();
+        let (width, height) = image.dimensions();
+        let regions = manifest
+            .regions
+            .into_iter()
+            .map(|(name, rect)| (name, AtlasRegion::new(rect, width, height)))
+            .collect();
+        Ok(Self { image, regions })
+    }
+}
+
+/// Packs images into an `Atlas`, either at startup or offline to be saved and
+/// loaded later.
+///
+/// Every image is surrounded by `extrude` copies of its edge pixels, so
+/// filtering at the edge of a region samples the image rather than its
+/// neighbours, and then by `padding` transparent pixels.
+pub struct AtlasBuilder {
+    images: Vec<(String, RgbaImage)>,
+    padding: u32,
+    extrude: u32,
+    max_size: u32,
+}
+
+impl Default for AtlasBuilder {
+    fn default() -> Self {
+        Self {
+            images: Vec::new(),
+            padding: 1,
+            extrude: 1,
+            max_size: 4096,
+        }
+    }
+}
+
+impl AtlasBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_extrude(mut self, extrude: u32) -> Self {
+        self.extrude = extrude;
+        self
+    }
+
+    /// The largest width and height the atlas may grow to.
+    pub fn with_max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn add(mut self, name: impl Into<String>, image: RgbaImage) -> Self {
+        self.images.push((name.into(), image));
+        self
+    }
+
+    /// Adds the image at `path`, named after its file name without the
+    /// extension.
+    pub fn add_file(self, path: impl AsRef<Path>) -> Result<Self, AtlasError> {
+        let path = path.as_ref();
+        let image = image::open(path).map_err(|source| AtlasError::Image {
+            path: path.to_owned(),
+            source,
+        })?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(self.add(name, image.to_rgba8()))
+    }
+
+    /// Packs the images into the smallest power of two sized atlas they fit
+    /// in, tallest first.
+    pub fn build(self) -> Result<Atlas, AtlasError> {
+        let mut names = std::collections::HashSet::new();
+        for (name, _) in &self.images {
+            if !names.insert(name) {
+                return Err(AtlasError::DuplicateName { name: name.clone() });
+            }
+        }
+
+        let border = 2 * self.extrude + self.padding;
+        let mut order = (0..self.images.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| {
+            let (name, image) = &self.images[i];
+            (
+                std::cmp::Reverse(image.height()),
+                std::cmp::Reverse(image.width()),
+                name,
+            )
+        });
+
+        let area = self
+            .images
+            .iter()
+            .map(|(_, image)| {
+                u64::from(image.width() + border) * u64::from(image.height() + border)
+            })
+            .sum::<u64>();
+        let widest = self.images.iter().map(|(_, image)| image.width()).max();
+        let tallest = self.images.iter().map(|(_, image)| image.height()).max();
+        let mut width = ((area as f64).sqrt() as u32)
+            .max(widest.unwrap_or(0) + border + self.padding)
+            .next_power_of_two();
+        let mut height = width
+            .max(tallest.unwrap_or(0) + border + self.padding)
+            .next_power_of_two();
+
+        let positions = loop {
+            if width > self.max_size || height > self.max_size {
+                return Err(AtlasError::TooLarge {
+                    max_size: self.max_size,
+                });
+            }
+            let mut skyline = Skyline::new(width - self.padding, height - self.padding);
+            let positions = order
+                .iter()
+                .map(|&i| {
+                    let image = &self.images[i].1;
+                    skyline.insert(image.width() + border, image.height() + border)
+                })
+                .collect::<Option<Vec<_>>>();
+            if let Some(positions) = positions {
+                break positions;
+            }
+            if width <= height {
+                width *= 2;
+            } else {
+                height *= 2;
+            }
+        };
+
+        let mut atlas = RgbaImage::new(width, height);
+        let mut regions = BTreeMap::new();
+        let offset = self.padding + self.extrude;
+        for (&i, (x, y)) in order.iter().zip(positions) {
+            let (name, image) = &self.images[i];
+            let rect = AtlasRect {
+                x: x + offset,
+                y: y + offset,
+                width: image.width(),
+                height: image.height(),
+            };
+            image::imageops::replace(&mut atlas, image, rect.x.into(), rect.y.into());
+            extrude(&mut atlas, rect, self.extrude);
+            regions.insert(name.clone(), AtlasRegion::new(rect, width, height));
+        }
+
+        Ok(Atlas {
+            image: atlas,
+            regions,
+        })
+    }
+}
+
+/// Copies the edge pixels of `rect` outwards `amount` times, corners included.
+fn extrude(atlas: &mut RgbaImage, rect: AtlasRect, amount: u32) {
+    if rect.width == 0 || rect.height == 0 {
+        return;
+    }
+    let (left, top) = (rect.x, rect.y);
+    let (right, bottom) = (rect.x + rect.width - 1, rect.y + rect.height - 1);
+    for y in top..=bottom {
+        for d in 1..=amount {
+            let pixel = *atlas.get_pixel(left, y);
+            atlas.put_pixel(left - d, y, pixel);
+            let pixel = *atlas.get_pixel(right, y);
+            atlas.put_pixel(right + d, y, pixel);
+        }
+    }
+    for x in left - amount..=right + amount {
+        for d in 1..=amount {
+            let pixel = *atlas.get_pixel(x, top);
+            atlas.put_pixel(x, top - d, pixel);
+            let pixel = *atlas.get_pixel(x, bottom);
+            atlas.put_pixel(x, bottom + d, pixel);
+        }
+    }
+}
+
+/// Bottom-left skyline bin packing. The skyline is the top edge of the packed
+/// rectangles, stored as segments sorted by `x`.
+pub(crate) struct Skyline {
+    width: u32,
+    height: u32,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Copy)]
+struct Segment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+impl Skyline {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            segments: vec![Segment { x: 0, y: 0, width }],
+        }
+    }
+
+    /// Places a `width` by `height` rectangle where its bottom is lowest,
+    /// preferring the left, and returns its top left corner.
+    pub(crate) fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (index, x, y) = (0..self.segments.len())
+            .filter_map(|i| Some((i, self.segments[i].x, self.fit(i, width, height)?)))
+            .min_by_key(|&(_, x, y)| (y + height, x))?;
+
+        let placed = Segment {
+            x,
+            y: y + height,
+            width,
+        };
+        self.segments.insert(index, placed);
+
+        // Trim the segments the new one covers
+        let end = x + width;
+        let i = index + 1;
+        while i < self.segments.len() && self.segments[i].x < end {
+            let segment = &mut self.segments[i];
+            let segment_end = segment.x + segment.width;
+            if segment_end <= end {
+                self.segments.remove(i);
+            } else {
+                segment.width = segment_end - end;
+                segment.x = end;
+                break;
+            }
+        }
+
+        // Merge neighbours at the same height
+        self.segments.dedup_by(|next, previous| {
+            let merge = previous.y == next.y;
+            if merge {
+                previous.width += next.width;
+            }
+            merge
+        });
+
+        Some((x, y))
+    }
+
+    /// The y a rectangle starting at segment `index` would rest at, or `None`
+    /// if it does not fit there.
+    fn fit(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.segments[index].x;
+        if x + width > self.width {
+            return None;
+        }
+        let y = self.segments[index..]
+            .iter()
+            .take_while(|segment| segment.x < x + width)
+            .map(|segment| segment.y)
+            .max()?;
+        (y + height <= self.height).then_some(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn solid(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([value, value, value, 255]))
+    }
+
+    fn builder() -> AtlasBuilder {
+        (0..20).fold(Atlas::builder().with_extrude(2), |builder, i| {
+            builder.add(
+                format!("image{}", i),
+                solid(3 + i * 7 % 13, 2 + i * 5 % 11, i as u8),
+            )
+        })
+    }
+
+    #[test]
+    fn regions_do_not_overlap() {
+        let atlas = builder().build().unwrap();
+        // Each region with its extruded border
+        let bounds = atlas
+            .regions()
+            .map(|(_, region)| {
+                let rect = region.rect;
+                (
+                    rect.x - 2,
+                    rect.y - 2,
+                    rect.x + rect.width + 2,
+                    rect.y + rect.height + 2,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(bounds.len(), 20);
+        for (i, a) in bounds.iter().enumerate() {
+            assert!(a.2 <= atlas.width() && a.3 <= atlas.height());
+            for b in &bounds[i + 1..] {
+                let disjoint = a.2 <= b.0 || b.2 <= a.0 || a.3 <= b.1 || b.3 <= a.1;
+                assert!(disjoint, "{:?} overlaps {:?}", a, b);
+            }
+        }
+        for (name, region) in atlas.regions() {
+            let value = name["image".len()..].parse::<u8>().unwrap();
+            let rect = region.rect;
+            assert_eq!(atlas.image().get_pixel(rect.x, rect.y)[0], value);
+            let (right, bottom) = (rect.x + rect.width - 1, rect.y + rect.height - 1);
+            assert_eq!(atlas.image().get_pixel(right, bottom)[0], value);
+        }
+    }
+
+    #[test]
+    fn extrusion_copies_edge_pixels() {
+        let mut image = RgbaImage::new(2, 2);
+        image.put_pixel(0, 0, Rgba([1, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([2, 0, 0, 255]));
+        image.put_pixel(0, 1, Rgba([3, 0, 0, 255]));
+        image.put_pixel(1, 1, Rgba([4, 0, 0, 255]));
+        let atlas = Atlas::builder()
+            .with_extrude(2)
+            .with_padding(1)
+            .add("quad", image)
+            .build()
+            .unwrap();
+        let AtlasRect { x, y, .. } = atlas.region("quad").unwrap().rect;
+        let pixel = |x, y| atlas.image().get_pixel(x, y)[0];
+
+        // Edges
+        assert_eq!(pixel(x - 1, y), 1);
+        assert_eq!(pixel(x - 2, y + 1), 3);
+        assert_eq!(pixel(x + 3, y), 2);
+        assert_eq!(pixel(x, y - 2), 1);
+        assert_eq!(pixel(x + 1, y + 3), 4);
+        // Corners
+        assert_eq!(pixel(x - 2, y - 2), 1);
+        assert_eq!(pixel(x + 3, y - 2), 2);
+        assert_eq!(pixel(x - 2, y + 3), 3);
+        assert_eq!(pixel(x + 3, y + 3), 4);
+        // Padding stays transparent
+        assert_eq!(atlas.image().get_pixel(x - 3, y)[3], 0);
+    }
+
+    #[test]
+    fn uv_rects_match_pixel_rects() {
+        let atlas = builder().build().unwrap();
+        let (width, height) = (atlas.width() as f32, atlas.height() as f32);
+        for (_, region) in atlas.regions() {
+            let rect = region.rect;
+            assert_eq!(
+                region.uv.min,
+                [rect.x as f32 / width, rect.y as f32 / height]
+            );
+            assert_eq!(
+                region.uv.max,
+                [
+                    (rect.x + rect.width) as f32 / width,
+                    (rect.y + rect.height) as f32 / height,
+                ]
+            );
+        }
+        assert_eq!(atlas.uv("image3"), Some(atlas.region("image3").unwrap().uv));
+        assert_eq!(atlas.uv("missing"), None);
+    }
+
+    #[test]
+    fn too_large() {
+        let result = Atlas::builder()
+            .with_max_size(64)
+            .add("big", solid(64, 8, 0))
+            .build();
+        assert!(matches!(result, Err(AtlasError::TooLarge { max_size: 64 })));
+
+        let result = (0..5)
+            .fold(Atlas::builder().with_max_size(64), |builder, i| {
+                builder.add(format!("{}", i), solid(30, 30, 0))
+            })
+            .build();
+        assert!(matches!(result, Err(AtlasError::TooLarge { max_size: 64 })));
+
+        let result = Atlas::builder()
+            .with_max_size(64)
+            .add("fits", solid(30, 30, 0))
+            .build();
+        assert_eq!(result.unwrap().width(), 64);
+    }
+}
@@ -2,10 +2,11 @@ use std::borrow::Borrow;
 use std::marker::PhantomData;
 use std::ops::RangeBounds;
 
-use bytemuck::{NoUninit, Pod};
+use bytemuck::{NoUninit, Pod, Zeroable};
 use wgpu::{util::DeviceExt, Device};
 use wgpu::{BindGroup, BindGroupLayout, BufferAddress, BufferUsages};
 
+use crate::reflect::{BindGroupResource, BindingLayout};
 use crate::renderer::{self, Context};
 const VERTEX: BufferUsages = wgpu::BufferUsages::VERTEX;
 const INDEX: BufferUsages = wgpu::BufferUsages::INDEX;
@@ -27,6 +28,14 @@ impl ToData for u32 {
     }
 }
 
+impl ToData for cgmath::Matrix4<f32> {
+    type Data = [[f32; 4]; 4];
+
+    fn to_data(&self) -> Self::Data {
+        (*self).into()
+    }
+}
+
 pub struct Uniform<C> {
     uniform: Buffer<C>,
 }
@@ -35,7 +44,7 @@ impl<C: ToData> Uniform<C> {
     #[inline]
     pub fn new(context: &Context, content: impl Borrow<C>) -> Self {
         let raw = uniform(context.device(), &[content.borrow().to_data()]);
-        let (bind_group, bind_group_layout) = create_uniform_bind_group(&context.device(), 0, &raw);
+        let (bind_group, bind_group_layout) = create_uniform_bind_group(context.device(), 0, &raw);
 
         Self {
             uniform: Buffer::new(raw, bind_group, bind_group_layout),
@@ -50,12 +59,12 @@ impl<C: ToData> Uniform<C> {
 
     #[inline]
     pub fn layout(&self) -> &BindGroupLayout {
-        &self.uniform.layout()
+        self.uniform.layout()
     }
 
     #[inline]
     pub fn bind_group(&self) -> &BindGroup {
-        &self.uniform.bind_group()
+        self.uniform.bind_group()
     }
 
     #[inline]
@@ -68,27 +77,52 @@ impl<C: ToData> Uniform<C> {
     }
 }
 
+impl<C: ToData> BindGroupResource for Uniform<C> {
+    fn layout(&self) -> &BindGroupLayout {
+        self.uniform.layout()
+    }
+
+    fn bind_group(&self) -> &BindGroup {
+        self.uniform.bind_group()
+    }
+
+    fn bindings(&self) -> Vec<BindingLayout> {
+        vec![BindingLayout {
+            entry: uniform_layout_entry(0),
+            size: Some(std::mem::size_of::<C::Data>() as u64),
+        }]
+    }
+
+    fn name(&self) -> String {
+        format!("Uniform<{}>", short_type_name::<C>())
+    }
+}
+
 pub struct Storage<C> {
     storage: Buffer<C>,
     length_buffer: wgpu::Buffer,
+    capacity: usize,
 }
 
 impl<C: ToData> Storage<C> {
+    /// A buffer holding `content`, with its length already written.
     #[inline]
     pub fn new(context: &Context, content: impl AsRef<[C]>) -> Self {
-        let content = content
-            .as_ref()
-            .iter()
-            .map(ToData::to_data)
-            .collect::<Vec<C::Data>>();
+        let (mut content, length) = storage_content(content.as_ref());
+        let capacity = content.len();
+        if content.is_empty() {
+            // Empty storage buffers cannot be bound
+            content.push(C::Data::zeroed());
+        }
         let storage_buffer = storage(context.device(), content.as_ref());
-        let length_buffer = uniform(context.device(), &[0u32]);
+        let length_buffer = uniform(context.device(), &[length]);
         let (bind_group, bind_group_layout) =
-            create_storage_bind_group(&context.device(), &storage_buffer, &length_buffer);
+            create_storage_bind_group(context.device(), &storage_buffer, &length_buffer);
 
         Self {
             storage: Buffer::new(storage_buffer, bind_group, bind_group_layout),
             length_buffer,
+            capacity,
         }
     }
 
@@ -98,36 +132,75 @@ impl<C: ToData> Storage<C> {
         &self.storage
     }
 
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
     #[inline]
     pub fn layout(&self) -> &BindGroupLayout {
-        &self.storage.layout()
+        self.storage.layout()
     }
 
     #[inline]
     pub fn bind_group(&self) -> &BindGroup {
-        &self.storage.bind_group()
+        self.storage.bind_group()
     }
 
     #[inline]
     pub fn update(&self, context: &Context, content: impl AsRef<[C]>) {
-        let content = content
-            .as_ref()
-            .iter()
-            .map(ToData::to_data)
-            .collect::<Vec<C::Data>>();
+        let (content, length) = storage_content(content.as_ref());
         context
             .queue()
             .write_buffer(&self.storage.raw, 0, bytemuck::cast_slice(&content));
-        context.queue().write_buffer(
-            &self.length_buffer,
-            0,
-            bytemuck::cast_slice(&[content.len() as u32]),
-        );
+        context
+            .queue()
+            .write_buffer(&self.length_buffer, 0, bytemuck::cast_slice(&[length]));
     }
 }
 
+/// The data of a storage buffer and the length the shaders see.
+fn storage_content<C: ToData>(content: &[C]) -> (Vec<C::Data>, u32) {
+    let data = content.iter().map(ToData::to_data).collect::<Vec<_>>();
+    let length = data.len() as u32;
+    (data, length)
+}
+
+impl<C: ToData> BindGroupResource for Storage<C> {
+    fn layout(&self) -> &BindGroupLayout {
+        self.storage.layout()
+    }
+
+    fn bind_group(&self) -> &BindGroup {
+        self.storage.bind_group()
+    }
+
+    fn bindings(&self) -> Vec<BindingLayout> {
+        vec![
+            BindingLayout {
+                entry: storage_layout_entry(0),
+                size: Some(std::mem::size_of::<C::Data>() as u64),
+            },
+            BindingLayout {
+                entry: uniform_layout_entry(1),
+                size: Some(std::mem::size_of::<u32>() as u64),
+            },
+        ]
+    }
+
+    fn name(&self) -> String {
+        format!("Storage<{}>", short_type_name::<C>())
+    }
+}
+
+fn short_type_name<C>() -> &'static str {
+    let name = std::any::type_name::<C>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
 pub struct InstanceBuffer<C> {
     raw: wgpu::Buffer,
+    capacity: usize,
     _content_marker: PhantomData<C>,
 }
 
@@ -143,10 +216,16 @@ impl<C: ToData> InstanceBuffer<C> {
 
         Self {
             raw,
+            capacity: content.len(),
             _content_marker: PhantomData,
         }
     }
 
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
     #[inline]
     pub fn update(&self, context: &renderer::Context, content: impl AsRef<[C]>) {
         let content = content
@@ -263,16 +342,7 @@ pub fn create_uniform_bind_group(
     buffer: &wgpu::Buffer,
 ) -> (wgpu::BindGroup, wgpu::BindGroupLayout) {
     let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: &[wgpu::BindGroupLayoutEntry {
-            binding,
-            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            },
-            count: None,
-        }],
+        entries: &[uniform_layout_entry(binding)],
         label: None,
     });
 
@@ -294,28 +364,7 @@ pub fn create_storage_bind_group(
     length: &wgpu::Buffer,
 ) -> (wgpu::BindGroup, wgpu::BindGroupLayout) {
     let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: &[
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-        ],
+        entries: &[storage_layout_entry(0), uniform_layout_entry(1)],
         label: None,
     });
 
@@ -336,3 +385,47 @@ pub fn create_storage_bind_group(
 
     (group, layout)
 }
+
+fn uniform_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn storage_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::UvRect;
+
+    #[test]
+    fn storage_content_carries_its_length() {
+        let uvs = [UvRect::FULL, UvRect::default(), UvRect::FULL];
+        let (data, length) = storage_content(&uvs);
+        assert_eq!(length, 3);
+        assert_eq!(data[0], [0.0, 0.0, 1.0, 1.0]);
+
+        let (data, length) = storage_content::<UvRect>(&[]);
+        assert!(data.is_empty());
+        assert_eq!(length, 0);
+    }
+}
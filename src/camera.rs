@@ -4,7 +4,7 @@ use crate::buffers::ToData;
 use crate::input::InputHandler;
 use crate::input::Key;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -44,7 +44,7 @@ impl Camera {
             -10.0,
         );
 
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+        OPENGL_TO_WGPU_MATRIX * proj * view
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
@@ -61,7 +61,7 @@ impl CameraController {
         Self { speed }
     }
 
-    pub fn update(&self, camera: &mut Camera, input: &InputHandler) {
+    pub fn update(&self, camera: &mut Camera, input: &InputHandler, dt: f32) {
         let mut disp: cgmath::Vector3<f32> = (0.0, 0.0, 0.0).into();
         if input.down(Key::W) {
             disp += (0.0, self.speed, 0.0).into();
@@ -77,8 +77,8 @@ impl CameraController {
             disp -= (self.speed, 0.0, 0.0).into();
         }
 
-        camera.eye += disp;
-        camera.target += disp;
+        camera.eye += disp * dt;
+        camera.target += disp * dt;
     }
 }
 
@@ -93,6 +93,26 @@ impl ToData for Camera {
     }
 }
 
+/// Projects pixel coordinates, with the origin at the top left corner of the
+/// window and y pointing down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenCamera {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ToData for ScreenCamera {
+    type Data = CameraUniform;
+
+    fn to_data(&self) -> Self::Data {
+        let proj = cgmath::ortho(0.0, self.width, self.height, 0.0, 1.0, -1.0);
+        CameraUniform {
+            view_position: [0.0, 0.0, 0.0, 1.0],
+            view_proj: (OPENGL_TO_WGPU_MATRIX * proj).into(),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
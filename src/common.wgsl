@@ -0,0 +1,54 @@
+// Structs shared between the engine's shaders. They have to match the
+// layouts of `InstanceRaw`, `Light` and `Camera` on the CPU side.
+
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+    [[location(9)]] scale: vec2<f32>;
+    [[location(10)]] rotation: f32;
+    [[location(11)]] depth: f32;
+    [[location(12)]] tint: vec4<f32>;
+    // min in xy, max in zw
+    [[location(13)]] uv_rect: vec4<f32>;
+};
+
+// Scales and rotates a quad vertex, places it with the model matrix and
+// moves it by the instance's depth.
+fn instance_world_position(instance: InstanceInput, position: vec3<f32>) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let scaled = position.xy * instance.scale;
+    let c = cos(instance.rotation);
+    let s = sin(instance.rotation);
+    let rotated = vec3<f32>(c * scaled.x - s * scaled.y, s * scaled.x + c * scaled.y, position.z);
+    var world_position = model_matrix * vec4<f32>(rotated, 1.0);
+    world_position.z = world_position.z + instance.depth;
+    return world_position;
+}
+
+// Maps the quad's 0..1 texture coordinates into the instance's UV rect.
+fn instance_tex_coords(instance: InstanceInput, tex_coords: vec2<f32>) -> vec2<f32> {
+    return mix(instance.uv_rect.xy, instance.uv_rect.zw, tex_coords);
+}
+
+struct Light {
+    position: vec3<f32>;
+    color: vec3<f32>;
+};
+struct Lights {
+    data: array<Light>;
+};
+struct LightCount {
+    data: u32;
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
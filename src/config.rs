@@ -0,0 +1,540 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::monitor::MonitorHandle;
+use winit::window::{Fullscreen, Window};
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.ron";
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub graphics: GraphicsConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    /// Outer position in physical pixels. `None` centers the window on the
+    /// monitor it opens on.
+    pub position: Option<(i32, i32)>,
+    pub mode: WindowMode,
+    pub resizable: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "Game Engine".to_string(),
+            width: 800,
+            height: 600,
+            position: None,
+            mode: WindowMode::Windowed,
+            resizable: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowMode {
+    Windowed,
+    /// Exclusive fullscreen using the monitor's video mode closest to the
+    /// configured size.
+    Fullscreen,
+    Borderless,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsConfig {
+    pub present_mode: PresentMode,
+    pub backend: Backend,
+    /// CPU-side frame rate cap. `None` renders as fast as the present mode
+    /// allows.
+    pub max_fps: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PresentMode {
+    /// Vsync.
+    #[default]
+    Fifo,
+    /// Vsync without blocking, dropping frames that are not presented in time.
+    Mailbox,
+    /// No vsync, may tear.
+    Immediate,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Backend {
+    /// Keeps whatever the app or the `WGPU_BACKEND` environment variable chose.
+    #[default]
+    Auto,
+    Vulkan,
+    Metal,
+    Dx12,
+    Dx11,
+    Gl,
+}
+
+impl Backend {
+    pub fn backends(self) -> Option<wgpu::Backends> {
+        match self {
+            Backend::Auto => None,
+            Backend::Vulkan => Some(wgpu::Backends::VULKAN),
+            Backend::Metal => Some(wgpu::Backends::METAL),
+            Backend::Dx12 => Some(wgpu::Backends::DX12),
+            Backend::Dx11 => Some(wgpu::Backends::DX11),
+            Backend::Gl => Some(wgpu::Backends::GL),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to write {path}: {source}")]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{path}:{source}")]
+    Parse { path: PathBuf, source: ron::Error },
+    #[error("failed to serialize the config: {0}")]
+    Serialize(ron::Error),
+    #[error("{0}\n\n{USAGE}")]
+    Args(String),
+}
+
+impl Config {
+    /// Loads the config from `path`, or the defaults if the file does not
+    /// exist yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.to_owned(),
+                    source,
+                })
+            }
+        };
+        ron::from_str(&source).map_err(|source| ConfigError::Parse {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(ConfigError::Serialize)?;
+        std::fs::write(path, source).map_err(|source| ConfigError::Write {
+            path: path.to_owned(),
+            source,
+        })
+    }
+}
+
+/// The mode, size and position of a window, to tell which of them changed
+/// while the game ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowState {
+    pub mode: WindowMode,
+    pub size: (u32, u32),
+    pub position: Option<(i32, i32)>,
+}
+
+impl WindowState {
+    pub fn of(window: &Window) -> Self {
+        let mode = match window.fullscreen() {
+            None => WindowMode::Windowed,
+            Some(Fullscreen::Borderless(_)) => WindowMode::Borderless,
+            Some(Fullscreen::Exclusive(_)) => WindowMode::Fullscreen,
+        };
+        let size = window.inner_size();
+        Self {
+            mode,
+            size: (size.width, size.height),
+            position: window
+                .outer_position()
+                .ok()
+                .map(|position| (position.x, position.y)),
+        }
+    }
+}
+
+impl WindowConfig {
+    /// Records the size, position and mode `window` ended up in, so the next
+    /// run opens the same way. Size and position are kept while fullscreen.
+    /// `opened` is the state the window was created with, so a value only
+    /// given on the command line is not stored unless it changed at runtime.
+    pub fn store_state(&mut self, window: &Window, opened: &WindowState) {
+        self.store(&WindowState::of(window), opened);
+    }
+
+    fn store(&mut self, state: &WindowState, opened: &WindowState) {
+        if state.mode != opened.mode {
+            self.mode = state.mode;
+        }
+        if state.mode != WindowMode::Windowed {
+            return;
+        }
+        // Leaving fullscreen restores a size and position the window was not
+        // opened with, so they only count as unchanged for a window that
+        // stayed windowed
+        let stayed_windowed = opened.mode == WindowMode::Windowed;
+        if !stayed_windowed || state.size != opened.size {
+            (self.width, self.height) = state.size;
+        }
+        if state.position.is_some() && (!stayed_windowed || state.position != opened.position) {
+            self.position = state.position;
+        }
+    }
+
+    pub fn fullscreen(&self, monitor: Option<MonitorHandle>) -> Option<Fullscreen> {
+        match self.mode {
+            WindowMode::Windowed => None,
+            WindowMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+            WindowMode::Fullscreen => {
+                let video_mode = monitor?.video_modes().min_by_key(|mode| {
+                    let size = mode.size();
+                    (
+                        (size.width as i64 - self.width as i64).abs()
+                            + (size.height as i64 - self.height as i64).abs(),
+                        std::cmp::Reverse(mode.refresh_rate()),
+                    )
+                })?;
+                Some(Fullscreen::Exclusive(video_mode))
+            }
+        }
+    }
+
+    /// The configured position, or the position that centers a window of
+    /// `outer_size` on `monitor`.
+    pub fn position(
+        &self,
+        monitor: Option<&MonitorHandle>,
+        outer_size: PhysicalSize<u32>,
+    ) -> Option<PhysicalPosition<i32>> {
+        if let Some((x, y)) = self.position {
+            return Some(PhysicalPosition::new(x, y));
+        }
+        let monitor = monitor?;
+        let origin = monitor.position();
+        let size = monitor.size();
+        Some(PhysicalPosition::new(
+            origin.x + (size.width as i32 - outer_size.width as i32) / 2,
+            origin.y + (size.height as i32 - outer_size.height as i32) / 2,
+        ))
+    }
+}
+
+pub const USAGE: &str = "\
+Options:
+    --config <path>          Config file to load and save window state to [default: config.ron]
+    --title <title>          Window title
+    --size <width>x<height>  Window size in physical pixels
+    --position <x>,<y>       Window position in physical pixels
+    --center                 Center the window on its monitor
+    --windowed               Open in a window
+    --fullscreen             Open in exclusive fullscreen
+    --borderless             Open in borderless fullscreen
+    --resizable              Allow resizing the window
+    --no-resizable           Disallow resizing the window
+    --vsync                  Same as --present-mode fifo
+    --no-vsync               Same as --present-mode immediate
+    --present-mode <mode>    fifo, mailbox or immediate
+    --backend <backend>      auto, vulkan, metal, dx12, dx11 or gl
+    --max-fps <fps>          Cap the frame rate, 0 for uncapped
+    --help                   Print this message";
+
+/// Command line overrides for the values in the config file.
+#[derive(Debug, Clone, Default)]
+pub struct CommandLine {
+    pub config_path: Option<PathBuf>,
+    pub help: bool,
+    title: Option<String>,
+    size: Option<(u32, u32)>,
+    position: Option<Option<(i32, i32)>>,
+    mode: Option<WindowMode>,
+    resizable: Option<bool>,
+    present_mode: Option<PresentMode>,
+    backend: Option<Backend>,
+    max_fps: Option<u32>,
+}
+
+impl CommandLine {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut command_line = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ConfigError::Args(format!("{} requires a value", arg)))
+            };
+            match arg.as_str() {
+                "--config" => command_line.config_path = Some(value()?.into()),
+                "--title" => command_line.title = Some(value()?),
+                "--size" => command_line.size = Some(parse_pair(&value()?, 'x')?),
+                "--position" => command_line.position = Some(Some(parse_pair(&value()?, ',')?)),
+                "--center" => command_line.position = Some(None),
+                "--windowed" => command_line.mode = Some(WindowMode::Windowed),
+                "--fullscreen" => command_line.mode = Some(WindowMode::Fullscreen),
+                "--borderless" => command_line.mode = Some(WindowMode::Borderless),
+                "--resizable" => command_line.resizable = Some(true),
+                "--no-resizable" => command_line.resizable = Some(false),
+                "--vsync" => command_line.present_mode = Some(PresentMode::Fifo),
+                "--no-vsync" => command_line.present_mode = Some(PresentMode::Immediate),
+                "--present-mode" => {
+                    command_line.present_mode = Some(match value()?.as_str() {
+                        "fifo" => PresentMode::Fifo,
+                        "mailbox" => PresentMode::Mailbox,
+                        "immediate" => PresentMode::Immediate,
+                        other => {
+                            return Err(ConfigError::Args(format!(
+                                "unknown present mode `{}`",
+                                other
+                            )))
+                        }
+                    })
+                }
+                "--backend" => {
+                    command_line.backend = Some(match value()?.as_str() {
+                        "auto" => Backend::Auto,
+                        "vulkan" => Backend::Vulkan,
+                        "metal" => Backend::Metal,
+                        "dx12" => Backend::Dx12,
+                        "dx11" => Backend::Dx11,
+                        "gl" => Backend::Gl,
+                        other => {
+                            return Err(ConfigError::Args(format!("unknown backend `{}`", other)))
+                        }
+                    })
+                }
+                "--max-fps" => {
+                    let fps = value()?;
+                    command_line.max_fps = Some(fps.parse().map_err(|_| {
+                        ConfigError::Args(format!("expected a frame rate, found `{}`", fps))
+                    })?)
+                }
+                "--help" | "-h" => command_line.help = true,
+                other => return Err(ConfigError::Args(format!("unknown argument `{}`", other))),
+            }
+        }
+        Ok(command_line)
+    }
+
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(title) = &self.title {
+            config.window.title.clone_from(title);
+        }
+        if let Some((width, height)) = self.size {
+            config.window.width = width;
+            config.window.height = height;
+        }
+        if let Some(position) = self.position {
+            config.window.position = position;
+        }
+        if let Some(mode) = self.mode {
+            config.window.mode = mode;
+        }
+        if let Some(resizable) = self.resizable {
+            config.window.resizable = resizable;
+        }
+        if let Some(present_mode) = self.present_mode {
+            config.graphics.present_mode = present_mode;
+        }
+        if let Some(backend) = self.backend {
+            config.graphics.backend = backend;
+        }
+        if let Some(max_fps) = self.max_fps {
+            config.graphics.max_fps = Some(max_fps).filter(|&fps| fps > 0);
+        }
+    }
+}
+
+fn parse_pair<T: std::str::FromStr>(value: &str, separator: char) -> Result<(T, T), ConfigError> {
+    value
+        .split_once(separator)
+        .and_then(|(a, b)| Some((a.trim().parse().ok()?, b.trim().parse().ok()?)))
+        .ok_or_else(|| {
+            ConfigError::Args(format!("expected `<a>{}<b>`, found `{}`", separator, value))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<CommandLine, ConfigError> {
+        CommandLine::parse(args.split_whitespace().map(String::from))
+    }
+
+    fn parse_error(args: &str) -> String {
+        match parse(args) {
+            Ok(command_line) => panic!("`{}` parsed as {:?}", args, command_line),
+            Err(ConfigError::Args(message)) => message,
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn command_line_overrides_the_config() {
+        let command_line = parse(
+            "--config other.ron --title Demo --size 1280x720 --position 10,-20 --borderless \
+             --resizable --no-vsync --backend vulkan --max-fps 144",
+        )
+        .unwrap();
+        assert_eq!(command_line.config_path, Some(PathBuf::from("other.ron")));
+        assert!(!command_line.help);
+
+        let mut config = Config::default();
+        command_line.apply(&mut config);
+        assert_eq!(config.window.title, "Demo");
+        assert_eq!((config.window.width, config.window.height), (1280, 720));
+        assert_eq!(config.window.position, Some((10, -20)));
+        assert_eq!(config.window.mode, WindowMode::Borderless);
+        assert!(config.window.resizable);
+        assert_eq!(config.graphics.present_mode, PresentMode::Immediate);
+        assert_eq!(config.graphics.backend, Backend::Vulkan);
+        assert_eq!(config.graphics.max_fps, Some(144));
+    }
+
+    #[test]
+    fn later_arguments_win_and_unset_values_are_kept() {
+        let mut config = Config::default();
+        config.window.position = Some((1, 2));
+        config.graphics.max_fps = Some(60);
+        parse("--fullscreen --windowed --present-mode mailbox --center --max-fps 0")
+            .unwrap()
+            .apply(&mut config);
+        assert_eq!(config.window.mode, WindowMode::Windowed);
+        assert_eq!(config.window.position, None);
+        assert_eq!(config.graphics.present_mode, PresentMode::Mailbox);
+        assert_eq!(config.graphics.max_fps, None);
+        assert_eq!(config.window.title, WindowConfig::default().title);
+
+        assert!(parse("-h").unwrap().help);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        assert_eq!(
+            parse_error("--frobnicate"),
+            "unknown argument `--frobnicate`"
+        );
+        assert_eq!(parse_error("--size"), "--size requires a value");
+        assert_eq!(
+            parse_error("--size 800by600"),
+            "expected `<a>x<b>`, found `800by600`"
+        );
+        assert_eq!(
+            parse_error("--size -800x600"),
+            "expected `<a>x<b>`, found `-800x600`"
+        );
+        assert_eq!(
+            parse_error("--position 1;2"),
+            "expected `<a>,<b>`, found `1;2`"
+        );
+        assert_eq!(
+            parse_error("--present-mode vsync"),
+            "unknown present mode `vsync`"
+        );
+        assert_eq!(parse_error("--backend webgpu"), "unknown backend `webgpu`");
+        assert_eq!(
+            parse_error("--max-fps fast"),
+            "expected a frame rate, found `fast`"
+        );
+    }
+
+    #[test]
+    fn parse_pair_splits_and_trims() {
+        assert_eq!(parse_pair::<u32>("800x600", 'x').unwrap(), (800, 600));
+        assert_eq!(parse_pair::<i32>(" -5 , 7 ", ',').unwrap(), (-5, 7));
+        assert!(parse_pair::<u32>("800x", 'x').is_err());
+        assert!(parse_pair::<u32>("x600", 'x').is_err());
+        assert!(parse_pair::<u32>("800x600x1", 'x').is_err());
+        assert!(parse_pair::<u32>("800,600", 'x').is_err());
+    }
+
+    fn state(mode: WindowMode, size: (u32, u32), position: (i32, i32)) -> WindowState {
+        WindowState {
+            mode,
+            size,
+            position: Some(position),
+        }
+    }
+
+    #[test]
+    fn only_runtime_mode_changes_are_stored() {
+        let mut config = WindowConfig::default();
+        let fullscreen = state(WindowMode::Fullscreen, (1920, 1080), (0, 0));
+        // Opened with --fullscreen and left that way
+        config.store(&fullscreen, &fullscreen);
+        assert_eq!(config.mode, WindowMode::Windowed);
+        // Opened with --fullscreen and switched to borderless
+        let borderless = state(WindowMode::Borderless, (1920, 1080), (0, 0));
+        config.store(&borderless, &fullscreen);
+        assert_eq!(config.mode, WindowMode::Borderless);
+        // Opened borderless from the config and switched back to a window
+        let windowed = state(WindowMode::Windowed, (800, 600), (5, 5));
+        config.store(&windowed, &borderless);
+        assert_eq!(config.mode, WindowMode::Windowed);
+        assert_eq!((config.width, config.height), (800, 600));
+        assert_eq!(config.position, Some((5, 5)));
+    }
+
+    #[test]
+    fn only_runtime_size_and_position_changes_are_stored() {
+        let stored = WindowConfig::default();
+        // Opened with --size 1280x720 --position 10,20
+        let opened = state(WindowMode::Windowed, (1280, 720), (10, 20));
+
+        let mut config = stored.clone();
+        config.store(&opened, &opened);
+        assert_eq!(config, stored);
+
+        // Resized, but not moved
+        config.store(&state(WindowMode::Windowed, (1024, 768), (10, 20)), &opened);
+        assert_eq!((config.width, config.height), (1024, 768));
+        assert_eq!(config.position, stored.position);
+
+        // Moved, but not resized
+        let mut config = stored.clone();
+        config.store(&state(WindowMode::Windowed, (1280, 720), (30, 40)), &opened);
+        assert_eq!((config.width, config.height), (stored.width, stored.height));
+        assert_eq!(config.position, Some((30, 40)));
+
+        // Size and position are kept while fullscreen
+        let mut config = stored.clone();
+        config.store(
+            &state(WindowMode::Borderless, (1920, 1080), (0, 0)),
+            &opened,
+        );
+        assert_eq!((config.width, config.height), (stored.width, stored.height));
+        assert_eq!(config.position, stored.position);
+        assert_eq!(config.mode, WindowMode::Borderless);
+    }
+}
@@ -0,0 +1,1082 @@
+use std::any::{type_name, Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+/// Components of one type, stored densely and indexed through a sparse array
+/// of entity indices, so iteration is linear and lookups are constant time.
+pub struct SparseSet<T> {
+    sparse: Vec<Option<u32>>,
+    entities: Vec<Entity>,
+    data: Vec<T>,
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self {
+            sparse: Vec::new(),
+            entities: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+}
+
+impl<T> SparseSet<T> {
+    fn dense_index(&self, entity: Entity) -> Option<usize> {
+        let dense = (*self.sparse.get(entity.index as usize)?)? as usize;
+        (self.entities[dense] == entity).then_some(dense)
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.dense_index(entity).is_some()
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        self.dense_index(entity).map(|i| &self.data[i])
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.dense_index(entity).map(move |i| &mut self.data[i])
+    }
+
+    pub fn insert(&mut self, entity: Entity, value: T) -> Option<T> {
+        if let Some(dense) = self.dense_index(entity) {
+            return Some(std::mem::replace(&mut self.data[dense], value));
+        }
+        let index = entity.index as usize;
+        if index >= self.sparse.len() {
+            self.sparse.resize(index + 1, None);
+        }
+        self.sparse[index] = Some(self.entities.len() as u32);
+        self.entities.push(entity);
+        self.data.push(value);
+        None
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let dense = self.dense_index(entity)?;
+        self.sparse[entity.index as usize] = None;
+        self.entities.swap_remove(dense);
+        if let Some(&moved) = self.entities.get(dense) {
+            self.sparse[moved.index as usize] = Some(dense as u32);
+        }
+        Some(self.data.swap_remove(dense))
+    }
+
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.entities.iter().copied().zip(&self.data)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+trait ComponentStorage {
+    fn remove_entity(&self, entity: Entity);
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> ComponentStorage for RefCell<SparseSet<T>> {
+    fn remove_entity(&self, entity: Entity) {
+        self.borrow_mut().remove(entity);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The components and resources a system reads and writes.
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    reads: HashSet<TypeId>,
+    writes: HashSet<TypeId>,
+    names: HashMap<TypeId, &'static str>,
+}
+
+impl Access {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read<T: 'static>(mut self) -> Self {
+        self.add_read::<T>();
+        self
+    }
+
+    pub fn write<T: 'static>(mut self) -> Self {
+        self.add_write::<T>();
+        self
+    }
+
+    pub fn add_read<T: 'static>(&mut self) {
+        self.reads.insert(TypeId::of::<T>());
+        self.names.insert(TypeId::of::<T>(), type_name::<T>());
+    }
+
+    pub fn add_write<T: 'static>(&mut self) {
+        self.writes.insert(TypeId::of::<T>());
+        self.names.insert(TypeId::of::<T>(), type_name::<T>());
+    }
+
+    /// Whether two systems with these accesses could not safely run at the
+    /// same time.
+    pub fn conflicts_with(&self, other: &Access) -> bool {
+        !self.writes.is_disjoint(&other.writes)
+            || !self.writes.is_disjoint(&other.reads)
+            || !self.reads.is_disjoint(&other.writes)
+    }
+
+    /// Returns the name of the first type `required` uses that this access
+    /// does not declare.
+    fn undeclared(&self, required: &Access) -> Option<&'static str> {
+        required
+            .writes
+            .iter()
+            .find(|ty| !self.writes.contains(ty))
+            .or_else(|| {
+                required
+                    .reads
+                    .iter()
+                    .find(|ty| !self.reads.contains(ty) && !self.writes.contains(ty))
+            })
+            .map(|ty| required.names[ty])
+    }
+}
+
+/// A set of components to spawn an entity with.
+pub trait Bundle: 'static {
+    fn insert(self, world: &mut World, entity: Entity);
+}
+
+macro_rules! impl_bundle {
+    ($($name:ident),*) => {
+        impl<$($name: 'static),*> Bundle for ($($name,)*) {
+            #[allow(non_snake_case, unused_variables)]
+            fn insert(self, world: &mut World, entity: Entity) {
+                let ($($name,)*) = self;
+                $(world.insert(entity, $name);)*
+            }
+        }
+    };
+}
+
+impl_bundle!();
+impl_bundle!(A);
+impl_bundle!(A, B);
+impl_bundle!(A, B, C);
+impl_bundle!(A, B, C, D);
+impl_bundle!(A, B, C, D, E);
+impl_bundle!(A, B, C, D, E, F);
+impl_bundle!(A, B, C, D, E, F, G);
+impl_bundle!(A, B, C, D, E, F, G, H);
+
+type Command = Box<dyn FnOnce(&mut World)>;
+
+#[derive(Default)]
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    storages: HashMap<TypeId, Box<dyn ComponentStorage>>,
+    resources: HashMap<TypeId, Box<dyn Any>>,
+    deferred: RefCell<Vec<Command>>,
+    current_system: RefCell<Option<(&'static str, Access)>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self, bundle: impl Bundle) -> Entity {
+        let entity = match self.free.pop() {
+            Some(index) => {
+                self.alive[index as usize] = true;
+                Entity {
+                    index,
+                    generation: self.generations[index as usize],
+                }
+            }
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+        bundle.insert(self, entity);
+        entity
+    }
+
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        for storage in self.storages.values() {
+            storage.remove_entity(entity);
+        }
+        let index = entity.index as usize;
+        self.alive[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.free.push(entity.index);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        self.alive.get(index) == Some(&true) && self.generations[index] == entity.generation
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.alive
+            .iter()
+            .zip(&self.generations)
+            .enumerate()
+            .filter(|(_, (&alive, _))| alive)
+            .map(|(index, (_, &generation))| Entity {
+                index: index as u32,
+                generation,
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.alive.iter().filter(|&&alive| alive).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds `component` to `entity`, returning the component it replaced.
+    ///
+    /// Panics if the entity was despawned.
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        assert!(self.is_alive(entity), "{:?} is not alive", entity);
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(RefCell::new(SparseSet::<T>::default())))
+            .as_any_mut()
+            .downcast_mut::<RefCell<SparseSet<T>>>()
+            .unwrap()
+            .get_mut()
+            .insert(entity, component)
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.storage_mut::<T>()?.remove(entity)
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.read::<T>()?, |storage| storage.get(entity)).ok()
+    }
+
+    pub fn get_mut<T: 'static>(&self, entity: Entity) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.write::<T>()?, |storage| storage.get_mut(entity)).ok()
+    }
+
+    /// Borrows every component of type `T`. Panics if they are mutably
+    /// borrowed already.
+    pub fn read<T: 'static>(&self) -> Option<Ref<'_, SparseSet<T>>> {
+        self.check_access(&Access::new().read::<T>());
+        let storage = self.storage::<T>()?;
+        Some(storage.try_borrow().unwrap_or_else(|_| {
+            panic!(
+                "components `{}` are already mutably borrowed",
+                type_name::<T>()
+            )
+        }))
+    }
+
+    /// Mutably borrows every component of type `T`. Panics if they are
+    /// borrowed already.
+    pub fn write<T: 'static>(&self) -> Option<RefMut<'_, SparseSet<T>>> {
+        self.check_access(&Access::new().write::<T>());
+        let storage = self.storage::<T>()?;
+        Some(
+            storage.try_borrow_mut().unwrap_or_else(|_| {
+                panic!("components `{}` are already borrowed", type_name::<T>())
+            }),
+        )
+    }
+
+    fn storage<T: 'static>(&self) -> Option<&RefCell<SparseSet<T>>> {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .map(|storage| storage.as_any().downcast_ref().unwrap())
+    }
+
+    fn storage_mut<T: 'static>(&mut self) -> Option<&mut SparseSet<T>> {
+        self.storages.get_mut(&TypeId::of::<T>()).map(|storage| {
+            storage
+                .as_any_mut()
+                .downcast_mut::<RefCell<SparseSet<T>>>()
+                .unwrap()
+                .get_mut()
+        })
+    }
+
+    /// Iterates the entities matching `Q`, e.g. `(&Camera, With<ActiveCamera>)`.
+    pub fn query<Q: Query>(&self) -> QueryBorrow<'_, Q> {
+        let mut required = Access::new();
+        Q::access(&mut required);
+        self.check_access(&required);
+        QueryBorrow {
+            world: self,
+            fetch: Q::Fetch::new(self),
+        }
+    }
+
+    pub fn insert_resource<T: 'static>(&mut self, resource: T) -> Option<T> {
+        self.resources
+            .insert(TypeId::of::<T>(), Box::new(RefCell::new(resource)))
+            .map(|old| old.downcast::<RefCell<T>>().unwrap().into_inner())
+    }
+
+    pub fn remove_resource<T: 'static>(&mut self) -> Option<T> {
+        self.resources
+            .remove(&TypeId::of::<T>())
+            .map(|old| old.downcast::<RefCell<T>>().unwrap().into_inner())
+    }
+
+    pub fn has_resource<T: 'static>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn get_resource<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        self.check_access(&Access::new().read::<T>());
+        self.resource_cell::<T>().map(|cell| {
+            cell.try_borrow().unwrap_or_else(|_| {
+                panic!(
+                    "resource `{}` is already mutably borrowed",
+                    type_name::<T>()
+                )
+            })
+        })
+    }
+
+    pub fn get_resource_mut<T: 'static>(&self) -> Option<RefMut<'_, T>> {
+        self.check_access(&Access::new().write::<T>());
+        self.resource_cell::<T>().map(|cell| {
+            cell.try_borrow_mut()
+                .unwrap_or_else(|_| panic!("resource `{}` is already borrowed", type_name::<T>()))
+        })
+    }
+
+    pub fn resource<T: 'static>(&self) -> Ref<'_, T> {
+        self.get_resource()
+            .unwrap_or_else(|| panic!("no resource `{}`", type_name::<T>()))
+    }
+
+    pub fn resource_mut<T: 'static>(&self) -> RefMut<'_, T> {
+        self.get_resource_mut()
+            .unwrap_or_else(|| panic!("no resource `{}`", type_name::<T>()))
+    }
+
+    fn resource_cell<T: 'static>(&self) -> Option<&RefCell<T>> {
+        self.resources
+            .get(&TypeId::of::<T>())
+            .map(|resource| resource.downcast_ref().unwrap())
+    }
+
+    /// Queues a structural change, like spawning or despawning, to be applied
+    /// once the running system is done.
+    pub fn defer(&self, command: impl FnOnce(&mut World) + 'static) {
+        self.deferred.borrow_mut().push(Box::new(command));
+    }
+
+    pub fn apply_deferred(&mut self) {
+        let commands = std::mem::take(self.deferred.get_mut());
+        for command in commands {
+            command(self);
+        }
+    }
+
+    fn check_access(&self, required: &Access) {
+        if let Some((system, access)) = &*self.current_system.borrow() {
+            if let Some(undeclared) = access.undeclared(required) {
+                panic!(
+                    "system `{}` accessed `{}` without declaring it",
+                    system, undeclared
+                );
+            }
+        }
+    }
+}
+
+/// Something that can be fetched for each entity in a query: `&T`,
+/// `&mut T`, `Option<&T>`, the filters `With<T>` and `Without<T>`, or a tuple
+/// of those.
+pub trait Query {
+    type Fetch<'w>: Fetch<'w>;
+
+    fn access(access: &mut Access);
+}
+
+pub trait Fetch<'w>: Sized {
+    type Item<'q>
+    where
+        Self: 'q;
+
+    fn new(world: &'w World) -> Self;
+
+    /// The entities this fetch can match, if it restricts them. Queries
+    /// iterate the shortest such list.
+    fn candidates(&self) -> Option<&[Entity]>;
+
+    fn matches(&self, entity: Entity) -> bool;
+
+    /// # Safety
+    ///
+    /// `entity` must match, and a mutable fetch must not be called for the
+    /// same entity while an item it returned earlier is still alive.
+    unsafe fn fetch<'q>(&'q self, entity: Entity) -> Self::Item<'q>;
+}
+
+pub struct FetchRead<'w, T> {
+    storage: Option<Ref<'w, SparseSet<T>>>,
+}
+
+impl<T: 'static> Query for &T {
+    type Fetch<'w> = FetchRead<'w, T>;
+
+    fn access(access: &mut Access) {
+        access.add_read::<T>();
+    }
+}
+
+impl<'w, T: 'static> Fetch<'w> for FetchRead<'w, T> {
+    type Item<'q>
+        = &'q T
+    where
+        Self: 'q;
+
+    fn new(world: &'w World) -> Self {
+        Self {
+            storage: world.storage::<T>().map(|storage| {
+                storage.try_borrow().unwrap_or_else(|_| {
+                    panic!(
+                        "components `{}` are already mutably borrowed",
+                        type_name::<T>()
+                    )
+                })
+            }),
+        }
+    }
+
+    fn candidates(&self) -> Option<&[Entity]> {
+        Some(
+            self.storage
+                .as_ref()
+                .map_or(&[], |storage| storage.entities()),
+        )
+    }
+
+    fn matches(&self, entity: Entity) -> bool {
+        self.storage
+            .as_ref()
+            .is_some_and(|storage| storage.contains(entity))
+    }
+
+    unsafe fn fetch(&self, entity: Entity) -> &T {
+        self.storage.as_ref().unwrap().get(entity).unwrap()
+    }
+}
+
+pub struct FetchWrite<'w, T> {
+    storage: Option<RefMut<'w, SparseSet<T>>>,
+    data: *mut T,
+}
+
+impl<T: 'static> Query for &mut T {
+    type Fetch<'w> = FetchWrite<'w, T>;
+
+    fn access(access: &mut Access) {
+        access.add_write::<T>();
+    }
+}
+
+impl<'w, T: 'static> Fetch<'w> for FetchWrite<'w, T> {
+    type Item<'q>
+        = &'q mut T
+    where
+        Self: 'q;
+
+    fn new(world: &'w World) -> Self {
+        let mut storage = world.storage::<T>().map(|storage| {
+            storage.try_borrow_mut().unwrap_or_else(|_| {
+                panic!("components `{}` are already borrowed", type_name::<T>())
+            })
+        });
+        let data = storage
+            .as_mut()
+            .map_or(std::ptr::null_mut(), |storage| storage.data.as_mut_ptr());
+        Self { storage, data }
+    }
+
+    fn candidates(&self) -> Option<&[Entity]> {
+        Some(
+            self.storage
+                .as_ref()
+                .map_or(&[], |storage| storage.entities()),
+        )
+    }
+
+    fn matches(&self, entity: Entity) -> bool {
+        self.storage
+            .as_ref()
+            .is_some_and(|storage| storage.contains(entity))
+    }
+
+    unsafe fn fetch(&self, entity: Entity) -> &mut T {
+        let dense = self.storage.as_ref().unwrap().dense_index(entity).unwrap();
+        // SAFETY: the storage is mutably borrowed for `'w`, and the caller
+        // guarantees each entity, and so each dense index, is only handed out
+        // once at a time.
+        unsafe { &mut *self.data.add(dense) }
+    }
+}
+
+impl<T: 'static> Query for Option<&T> {
+    type Fetch<'w> = FetchOption<'w, T>;
+
+    fn access(access: &mut Access) {
+        access.add_read::<T>();
+    }
+}
+
+pub struct FetchOption<'w, T>(FetchRead<'w, T>);
+
+impl<'w, T: 'static> Fetch<'w> for FetchOption<'w, T> {
+    type Item<'q>
+        = Option<&'q T>
+    where
+        Self: 'q;
+
+    fn new(world: &'w World) -> Self {
+        Self(FetchRead::new(world))
+    }
+
+    fn candidates(&self) -> Option<&[Entity]> {
+        None
+    }
+
+    fn matches(&self, _entity: Entity) -> bool {
+        true
+    }
+
+    unsafe fn fetch(&self, entity: Entity) -> Option<&T> {
+        self.0
+            .storage
+            .as_ref()
+            .and_then(|storage| storage.get(entity))
+    }
+}
+
+/// Only matches entities that have a `T`, without fetching it.
+pub struct With<T>(PhantomData<T>);
+
+/// Only matches entities that do not have a `T`.
+pub struct Without<T>(PhantomData<T>);
+
+pub struct FetchFilter<'w, T, const WITH: bool>(FetchRead<'w, T>);
+
+impl<T: 'static> Query for With<T> {
+    type Fetch<'w> = FetchFilter<'w, T, true>;
+
+    fn access(access: &mut Access) {
+        access.add_read::<T>();
+    }
+}
+
+impl<T: 'static> Query for Without<T> {
+    type Fetch<'w> = FetchFilter<'w, T, false>;
+
+    fn access(access: &mut Access) {
+        access.add_read::<T>();
+    }
+}
+
+impl<'w, T: 'static, const WITH: bool> Fetch<'w> for FetchFilter<'w, T, WITH> {
+    type Item<'q>
+        = ()
+    where
+        Self: 'q;
+
+    fn new(world: &'w World) -> Self {
+        Self(FetchRead::new(world))
+    }
+
+    fn candidates(&self) -> Option<&[Entity]> {
+        if WITH {
+            self.0.candidates()
+        } else {
+            None
+        }
+    }
+
+    fn matches(&self, entity: Entity) -> bool {
+        self.0.matches(entity) == WITH
+    }
+
+    unsafe fn fetch(&self, _entity: Entity) {}
+}
+
+macro_rules! impl_query {
+    ($($name:ident),*) => {
+        impl<$($name: Query),*> Query for ($($name,)*) {
+            type Fetch<'w> = ($($name::Fetch<'w>,)*);
+
+            fn access(access: &mut Access) {
+                $($name::access(access);)*
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<'w, $($name: Fetch<'w>),*> Fetch<'w> for ($($name,)*) {
+            type Item<'q> = ($($name::Item<'q>,)*) where Self: 'q;
+
+            fn new(world: &'w World) -> Self {
+                ($($name::new(world),)*)
+            }
+
+            fn candidates(&self) -> Option<&[Entity]> {
+                let ($($name,)*) = self;
+                [$($name.candidates()),*]
+                    .into_iter()
+                    .flatten()
+                    .min_by_key(|candidates| candidates.len())
+            }
+
+            fn matches(&self, entity: Entity) -> bool {
+                let ($($name,)*) = self;
+                $($name.matches(entity))&&*
+            }
+
+            unsafe fn fetch<'q>(&'q self, entity: Entity) -> Self::Item<'q> {
+                let ($($name,)*) = self;
+                // SAFETY: forwarded from the caller.
+                unsafe { ($($name.fetch(entity),)*) }
+            }
+        }
+    };
+}
+
+impl_query!(A);
+impl_query!(A, B);
+impl_query!(A, B, C);
+impl_query!(A, B, C, D);
+impl_query!(A, B, C, D, E);
+impl_query!(A, B, C, D, E, F);
+impl_query!(A, B, C, D, E, F, G);
+impl_query!(A, B, C, D, E, F, G, H);
+
+/// The storages a query uses, borrowed for as long as this lives.
+pub struct QueryBorrow<'w, Q: Query> {
+    world: &'w World,
+    fetch: Q::Fetch<'w>,
+}
+
+impl<'w, Q: Query> QueryBorrow<'w, Q> {
+    pub fn iter(&mut self) -> QueryIter<'_, 'w, Q> {
+        let entities = match self.fetch.candidates() {
+            Some(candidates) => candidates.to_vec(),
+            None => self.world.entities().collect(),
+        };
+        QueryIter {
+            fetch: &self.fetch,
+            entities: entities.into_iter(),
+        }
+    }
+
+    pub fn get(&mut self, entity: Entity) -> Option<<Q::Fetch<'w> as Fetch<'w>>::Item<'_>> {
+        // SAFETY: `&mut self` keeps any other item from being alive.
+        (self.world.is_alive(entity) && self.fetch.matches(entity))
+            .then(|| unsafe { self.fetch.fetch(entity) })
+    }
+}
+
+pub struct QueryIter<'q, 'w, Q: Query> {
+    fetch: &'q Q::Fetch<'w>,
+    entities: std::vec::IntoIter<Entity>,
+}
+
+impl<'q, 'w, Q: Query> Iterator for QueryIter<'q, 'w, Q> {
+    type Item = (Entity, <Q::Fetch<'w> as Fetch<'w>>::Item<'q>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for entity in self.entities.by_ref() {
+            if self.fetch.matches(entity) {
+                // SAFETY: the candidate list has no duplicates, and the
+                // iterator borrows the query mutably, so no item outlives it.
+                return Some((entity, unsafe { self.fetch.fetch(entity) }));
+            }
+        }
+        None
+    }
+}
+
+pub trait System {
+    fn name(&self) -> &'static str;
+
+    fn access(&self) -> Access;
+
+    fn run(&mut self, world: &World);
+}
+
+pub struct FnSystem<F> {
+    name: &'static str,
+    access: Access,
+    run: F,
+}
+
+impl<F: FnMut(&World)> System for FnSystem<F> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn access(&self) -> Access {
+        self.access.clone()
+    }
+
+    fn run(&mut self, world: &World) {
+        (self.run)(world)
+    }
+}
+
+/// Makes a system from a closure. Accessing components or resources not in
+/// `access` while the system runs panics.
+pub fn system<F: FnMut(&World) + 'static>(
+    name: &'static str,
+    access: Access,
+    run: F,
+) -> FnSystem<F> {
+    FnSystem { name, access, run }
+}
+
+/// Runs systems in the order they were added, applying the commands each one
+/// deferred before running the next. `batches` groups the systems by which
+/// ones could run at the same time.
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<Box<dyn System>>,
+    batches: Option<Vec<Vec<usize>>>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_system(mut self, system: impl System + 'static) -> Self {
+        self.add_system(system);
+        self
+    }
+
+    pub fn add_system(&mut self, system: impl System + 'static) {
+        self.systems.push(Box::new(system));
+        self.batches = None;
+    }
+
+    /// System names per batch. Systems in the same batch could run in
+    /// parallel.
+    pub fn batches(&mut self) -> Vec<Vec<&'static str>> {
+        self.ensure_batched();
+        self.batches
+            .iter()
+            .flatten()
+            .map(|batch| batch.iter().map(|&i| self.systems[i].name()).collect())
+            .collect()
+    }
+
+    /// A system goes into the first batch after every batch holding an
+    /// earlier system it conflicts with.
+    fn ensure_batched(&mut self) {
+        if self.batches.is_some() {
+            return;
+        }
+        let accesses = self.systems.iter().map(|s| s.access()).collect::<Vec<_>>();
+        let mut batch_of = Vec::with_capacity(self.systems.len());
+        let mut batches: Vec<Vec<usize>> = Vec::new();
+        for (i, access) in accesses.iter().enumerate() {
+            let batch = (0..i)
+                .filter(|&j| accesses[j].conflicts_with(access))
+                .map(|j| batch_of[j] + 1)
+                .max()
+                .unwrap_or(0);
+            if batch == batches.len() {
+                batches.push(Vec::new());
+            }
+            batches[batch].push(i);
+            batch_of.push(batch);
+        }
+        self.batches = Some(batches);
+    }
+
+    pub fn run(&mut self, world: &mut World) {
+        for system in &mut self.systems {
+            *world.current_system.get_mut() = Some((system.name(), system.access()));
+            system.run(world);
+            *world.current_system.get_mut() = None;
+            world.apply_deferred();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32);
+
+    struct Frozen;
+
+    fn entity(index: u32, generation: u32) -> Entity {
+        Entity { index, generation }
+    }
+
+    #[test]
+    fn despawned_indices_are_reused_with_a_new_generation() {
+        let mut world = World::new();
+        let a = world.spawn((Position(1),));
+        let b = world.spawn((Position(2),));
+        assert_eq!((a, b), (entity(0, 0), entity(1, 0)));
+
+        assert!(world.despawn(a));
+        assert!(!world.despawn(a));
+        assert!(!world.is_alive(a));
+        assert!(world.get::<Position>(a).is_none());
+        assert_eq!(world.len(), 1);
+
+        let c = world.spawn((Position(3),));
+        assert_eq!(c, entity(0, 1));
+        assert!(world.is_alive(c));
+        assert!(!world.is_alive(a));
+        // The stale handle does not see the new entity's components
+        assert!(world.get::<Position>(a).is_none());
+        assert_eq!(*world.get::<Position>(c).unwrap(), Position(3));
+        assert_eq!(world.entities().collect::<Vec<_>>(), [c, b]);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not alive")]
+    fn inserting_into_a_despawned_entity_panics() {
+        let mut world = World::new();
+        let a = world.spawn(());
+        world.despawn(a);
+        world.insert(a, Position(0));
+    }
+
+    #[test]
+    fn sparse_set_remove_fixes_up_the_moved_entity() {
+        let mut set = SparseSet::default();
+        let (a, b, c) = (entity(0, 0), entity(5, 0), entity(2, 0));
+        set.insert(a, 'a');
+        set.insert(b, 'b');
+        set.insert(c, 'c');
+
+        assert_eq!(set.remove(a), Some('a'));
+        assert_eq!(set.entities(), [c, b]);
+        assert_eq!(set.get(c), Some(&'c'));
+        assert_eq!(set.get(b), Some(&'b'));
+        assert_eq!(set.get(a), None);
+
+        // Removing the last element moves nothing
+        assert_eq!(set.remove(b), Some('b'));
+        assert_eq!(set.iter().collect::<Vec<_>>(), [(c, &'c')]);
+        assert_eq!(set.remove(b), None);
+
+        // A stale generation at the same index does not match
+        assert_eq!(set.get(entity(2, 1)), None);
+        assert_eq!(set.insert(c, 'C'), Some('c'));
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn query_filters() {
+        let mut world = World::new();
+        let moving = world.spawn((Position(0), Velocity(1)));
+        let frozen = world.spawn((Position(1), Velocity(2), Frozen));
+        let still = world.spawn((Position(2),));
+        world.spawn((Velocity(3),));
+
+        let mut query = world.query::<(&Position, With<Velocity>)>();
+        let with = query.iter().map(|(e, _)| e).collect::<Vec<_>>();
+        assert_eq!(with, [moving, frozen]);
+        drop(query);
+
+        let mut query = world.query::<(&Position, Without<Frozen>)>();
+        let without = query.iter().map(|(e, _)| e).collect::<Vec<_>>();
+        assert_eq!(without, [moving, still]);
+        drop(query);
+
+        let mut query = world.query::<(&Position, Option<&Velocity>)>();
+        let optional = query
+            .iter()
+            .map(|(_, (p, v))| (p.0, v.map(|v| v.0)))
+            .collect::<Vec<_>>();
+        assert_eq!(optional, [(0, Some(1)), (1, Some(2)), (2, None)]);
+        drop(query);
+
+        for (_, (position, velocity, ())) in world
+            .query::<(&mut Position, &Velocity, Without<Frozen>)>()
+            .iter()
+        {
+            position.0 += velocity.0;
+        }
+        assert_eq!(*world.get::<Position>(moving).unwrap(), Position(1));
+        assert_eq!(*world.get::<Position>(frozen).unwrap(), Position(1));
+
+        assert!(world.query::<&Frozen>().get(still).is_none());
+        assert!(world.query::<&Frozen>().get(frozen).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn aliasing_mutable_queries_panic() {
+        let mut world = World::new();
+        world.spawn((Position(0),));
+        let _query = world.query::<(&mut Position, &Position)>();
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn writing_while_a_query_reads_panics() {
+        let mut world = World::new();
+        world.spawn((Position(0),));
+        let _query = world.query::<&Position>();
+        world.query::<&mut Position>();
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn reading_while_a_query_writes_panics() {
+        let mut world = World::new();
+        let a = world.spawn((Position(0),));
+        let _query = world.query::<&mut Position>();
+        world.get::<Position>(a);
+    }
+
+    #[test]
+    #[should_panic(expected = "system `sneaky` accessed")]
+    fn undeclared_access_panics() {
+        let mut world = World::new();
+        world.spawn((Position(0), Velocity(0)));
+        Schedule::new()
+            .with_system(system(
+                "sneaky",
+                Access::new().read::<Position>(),
+                |world| {
+                    world.query::<(&Position, &mut Velocity)>();
+                },
+            ))
+            .run(&mut world);
+    }
+
+    #[test]
+    fn writes_cover_reads() {
+        let mut world = World::new();
+        world.insert_resource(0u32);
+        Schedule::new()
+            .with_system(system("count", Access::new().write::<u32>(), |world| {
+                let count = *world.resource::<u32>();
+                *world.resource_mut::<u32>() = count + 1;
+            }))
+            .run(&mut world);
+        assert_eq!(*world.resource::<u32>(), 1);
+    }
+
+    fn noop(_: &World) {}
+
+    #[test]
+    fn conflicting_systems_go_into_later_batches() {
+        let mut schedule = Schedule::new()
+            .with_system(system("move", Access::new().write::<Position>(), noop))
+            .with_system(system("draw", Access::new().read::<Position>(), noop))
+            .with_system(system("physics", Access::new().write::<Velocity>(), noop))
+            .with_system(system(
+                "both",
+                Access::new().read::<Position>().read::<Velocity>(),
+                noop,
+            ))
+            .with_system(system("log", Access::new().read::<Position>(), noop));
+        assert_eq!(
+            schedule.batches(),
+            [vec!["move", "physics"], vec!["draw", "both", "log"]]
+        );
+    }
+
+    #[test]
+    fn systems_run_in_registration_order() {
+        #[derive(Default)]
+        struct Log(Vec<&'static str>);
+
+        let mut world = World::new();
+        world.insert_resource(Log::default());
+        let logger = |name: &'static str| {
+            system(name, Access::new().write::<Log>(), move |world| {
+                world.resource_mut::<Log>().0.push(name);
+            })
+        };
+        let spawner = |name: &'static str| {
+            system(name, Access::new().write::<Position>(), move |world| {
+                world.defer(move |world| {
+                    world.spawn((Position(0),));
+                    world.resource_mut::<Log>().0.push(name);
+                });
+            })
+        };
+        // "last" does not conflict with "b" and is batched with "a", but
+        // still runs after "b"
+        let mut schedule = Schedule::new()
+            .with_system(logger("a"))
+            .with_system(logger("b"))
+            .with_system(spawner("last"));
+        assert_eq!(schedule.batches(), [vec!["a", "last"], vec!["b"]]);
+        schedule.run(&mut world);
+        assert_eq!(world.resource::<Log>().0, ["a", "b", "last"]);
+        assert_eq!(world.len(), 1);
+    }
+}
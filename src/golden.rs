@@ -0,0 +1,328 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+use image::{Rgba, RgbaImage};
+
+use crate::camera::Camera;
+use crate::light::Light;
+use crate::quad::Instance;
+use crate::renderer::{ContextError, ContextOptions, Renderer};
+use crate::scene::Scene;
+use crate::texture::Texture;
+
+pub const BLESS_ENV: &str = "GOLDEN_BLESS";
+/// Set to turn a skipped check (no graphics adapter) into a failure, so a CI
+/// machine that is expected to render cannot pass by skipping.
+pub const REQUIRE_ADAPTER_ENV: &str = "GOLDEN_REQUIRE_ADAPTER";
+pub const DEFAULT_TOLERANCE: u8 = 2;
+
+pub struct GoldenScene {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub texture: Option<PathBuf>,
+    pub quads: Vec<[f32; 3]>,
+    pub lights: Vec<([f32; 3], [f32; 3])>,
+    pub camera_eye: [f32; 2],
+    pub tolerance: u8,
+}
+
+impl GoldenScene {
+    pub fn new(name: &'static str, width: u32, height: u32) -> Self {
+        Self {
+            name,
+            width,
+            height,
+            texture: None,
+            quads: Vec::new(),
+            lights: Vec::new(),
+            camera_eye: [0.0, 0.0],
+            tolerance: DEFAULT_TOLERANCE,
+        }
+    }
+
+    pub fn with_texture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.texture = Some(path.into());
+        self
+    }
+
+    pub fn with_quad(mut self, position: [f32; 3]) -> Self {
+        self.quads.push(position);
+        self
+    }
+
+    pub fn with_light(mut self, position: [f32; 3], color: [f32; 3]) -> Self {
+        self.lights.push((position, color));
+        self
+    }
+
+    pub fn with_camera(mut self, x: f32, y: f32) -> Self {
+        self.camera_eye = [x, y];
+        self
+    }
+
+    pub fn with_tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Renders the scene offscreen, falling back to the software adapter when
+    /// there is no hardware one. Returns `None` only when the machine has no
+    /// graphics adapter at all.
+    pub fn render(&self) -> Result<Option<RgbaImage>> {
+        let options = ContextOptions::default();
+        let renderer =
+            pollster::block_on(Renderer::new_headless(self.width, self.height, &options)).or_else(
+                |_| {
+                    let options = ContextOptions {
+                        force_fallback_adapter: true,
+                        ..options
+                    };
+                    pollster::block_on(Renderer::new_headless(self.width, self.height, &options))
+                },
+            );
+        let mut renderer = match renderer {
+            Ok(renderer) => renderer,
+            Err(ContextError::NoAdapter { .. }) => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("cannot render golden scene `{}`", self.name))
+            }
+        };
+
+        if let Some(path) = &self.texture {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("failed to read texture {}", path.display()))?;
+            let texture = Texture::from_bytes(renderer.context(), &bytes, &path.to_string_lossy())?;
+            renderer.set_texture(texture);
+        }
+
+        let instances = self
+            .quads
+            .iter()
+            .map(|&position| Instance::from_position(position).into())
+            .collect::<Vec<_>>();
+        let lights = self
+            .lights
+            .iter()
+            .map(|&(position, color)| Light::new(position, color))
+            .collect::<Vec<_>>();
+
+        let mut camera = Camera::basic(self.width, self.height, cgmath::Deg(45.0));
+        let [x, y] = self.camera_eye;
+        camera.eye = (x, y, 1.0).into();
+        camera.target = (x, y, 0.0).into();
+
+        let mut scene = Scene::new(camera);
+        scene.sprites = instances;
+        scene.lights = lights;
+
+        renderer
+            .render(&scene)
+            .map_err(|e| anyhow::anyhow!("failed to render golden scene: {:?}", e))?;
+        renderer.capture().map(Some)
+    }
+
+    /// Renders the scene and compares it against `tests/golden/<name>.png`.
+    ///
+    /// Set `GOLDEN_BLESS=1` to write the current output as the new reference.
+    /// Without a graphics adapter the check is skipped with a message, unless
+    /// `GOLDEN_REQUIRE_ADAPTER` is set.
+    pub fn check(&self) -> Result<()> {
+        let actual = match self.render()? {
+            Some(actual) => actual,
+            None if std::env::var_os(REQUIRE_ADAPTER_ENV).is_some() => {
+                bail!(
+                    "cannot render golden scene `{}`: no graphics adapter and {} is set",
+                    self.name,
+                    REQUIRE_ADAPTER_ENV
+                )
+            }
+            None => {
+                eprintln!(
+                    "skipping golden scene `{}`: no graphics adapter, not even a fallback one",
+                    self.name
+                );
+                return Ok(());
+            }
+        };
+
+        let reference_path = reference_dir().join(format!("{}.png", self.name));
+        if std::env::var_os(BLESS_ENV).is_some() {
+            std::fs::create_dir_all(reference_dir())?;
+            actual.save(&reference_path)?;
+            return Ok(());
+        }
+
+        let expected = image::open(&reference_path)
+            .with_context(|| {
+                format!(
+                    "missing reference {}, run with {}=1 to create it",
+                    reference_path.display(),
+                    BLESS_ENV
+                )
+            })?
+            .to_rgba8();
+
+        let comparison = compare(&actual, &expected, self.tolerance);
+        if comparison.matches() {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(output_dir())?;
+        let actual_path = output_dir().join(format!("{}.actual.png", self.name));
+        actual.save(&actual_path)?;
+        let mut message = format!(
+            "golden image `{}` differs: {} pixels over tolerance {} (max channel difference {}), output written to {}",
+            self.name,
+            comparison.mismatched,
+            self.tolerance,
+            comparison.max_difference,
+            actual_path.display(),
+        );
+        if let Some(diff) = comparison.diff {
+            let diff_path = output_dir().join(format!("{}.diff.png", self.name));
+            diff.save(&diff_path)?;
+            message.push_str(&format!(", diff written to {}", diff_path.display()));
+        }
+        bail!(message)
+    }
+}
+
+pub struct Comparison {
+    pub mismatched: usize,
+    pub max_difference: u8,
+    pub diff: Option<RgbaImage>,
+}
+
+impl Comparison {
+    pub fn matches(&self) -> bool {
+        self.mismatched == 0
+    }
+}
+
+/// Compares two images channel by channel. Mismatching pixels are painted red
+/// in the diff image on top of a faded copy of the expected image.
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage, tolerance: u8) -> Comparison {
+    if actual.dimensions() != expected.dimensions() {
+        return Comparison {
+            mismatched: (actual.width() * actual.height()).max(expected.width() * expected.height())
+                as usize,
+            max_difference: u8::MAX,
+            diff: None,
+        };
+    }
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+    for ((a, e), d) in actual
+        .pixels()
+        .zip(expected.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let difference =
+            a.0.iter()
+                .zip(e.0.iter())
+                .map(|(a, e)| a.abs_diff(*e))
+                .max()
+                .unwrap_or(0);
+        max_difference = max_difference.max(difference);
+
+        *d = if difference > tolerance {
+            mismatched += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let [r, g, b, _] = e.0;
+            Rgba([r / 4, g / 4, b / 4, 255])
+        };
+    }
+
+    Comparison {
+        mismatched,
+        max_difference,
+        diff: (mismatched > 0).then_some(diff),
+    }
+}
+
+fn reference_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("target")
+        .join("golden")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_texture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/happy-tree.png")
+    }
+
+    #[test]
+    fn compare_detects_differences_over_tolerance() {
+        let expected = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 1, Rgba([102, 100, 100, 255]));
+        assert!(compare(&actual, &expected, 2).matches());
+
+        actual.put_pixel(2, 2, Rgba([110, 100, 100, 255]));
+        let comparison = compare(&actual, &expected, 2);
+        assert_eq!(comparison.mismatched, 1);
+        assert_eq!(comparison.max_difference, 10);
+        let diff = comparison.diff.unwrap();
+        assert_eq!(*diff.get_pixel(2, 2), Rgba([255, 0, 0, 255]));
+        assert_eq!(*diff.get_pixel(1, 1), Rgba([25, 25, 25, 255]));
+    }
+
+    #[test]
+    fn compare_rejects_size_mismatch() {
+        let expected = RgbaImage::new(4, 4);
+        let actual = RgbaImage::new(4, 3);
+        assert!(!compare(&actual, &expected, 255).matches());
+    }
+
+    #[test]
+    fn golden_single_light() {
+        GoldenScene::new("single_light", 128, 96)
+            .with_texture(tree_texture())
+            .with_quad([-1.0, 0.0, 0.0])
+            .with_quad([0.0, 0.0, 0.0])
+            .with_quad([1.0, 0.0, 0.0])
+            .with_light([0.0, 0.5, -0.1], [1.0, 1.0, 1.0])
+            .check()
+            .unwrap();
+    }
+
+    #[test]
+    fn golden_colored_lights() {
+        GoldenScene::new("colored_lights", 128, 96)
+            .with_texture(tree_texture())
+            .with_quad([-0.5, -0.5, 0.0])
+            .with_quad([0.5, -0.5, 0.0])
+            .with_quad([-0.5, 0.5, 0.0])
+            .with_quad([0.5, 0.5, 0.0])
+            .with_light([-1.5, 0.0, -0.1], [1.0, 0.2, 0.2])
+            .with_light([1.5, 0.0, -0.1], [0.2, 0.2, 1.0])
+            .with_tolerance(3)
+            .check()
+            .unwrap();
+    }
+
+    #[test]
+    fn golden_camera_offset() {
+        GoldenScene::new("camera_offset", 128, 96)
+            .with_texture(tree_texture())
+            .with_quad([2.0, 1.0, 0.0])
+            .with_light([2.0, 2.0, -0.1], [1.0, 1.0, 1.0])
+            .with_camera(2.0, 1.0)
+            .check()
+            .unwrap();
+    }
+}
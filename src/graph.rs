@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+
+use crate::renderer::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Debug, thiserror::Error)]
+pub enum GraphError {
+    #[error("render graph has a cycle through the passes {}", format_cycle(.passes))]
+    Cycle { passes: Vec<&'static str> },
+}
+
+fn format_cycle(passes: &[&'static str]) -> String {
+    passes
+        .iter()
+        .chain(passes.first())
+        .map(|label| format!("`{}`", label))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureSize {
+    Backbuffer,
+    Scaled(f32),
+    Fixed(u32, u32),
+}
+
+impl TextureSize {
+    fn resolve(self, backbuffer: (u32, u32)) -> (u32, u32) {
+        match self {
+            TextureSize::Backbuffer => backbuffer,
+            TextureSize::Scaled(scale) => (
+                ((backbuffer.0 as f32 * scale) as u32).max(1),
+                ((backbuffer.1 as f32 * scale) as u32).max(1),
+            ),
+            TextureSize::Fixed(width, height) => (width, height),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TextureDesc {
+    pub label: &'static str,
+    pub format: wgpu::TextureFormat,
+    pub size: TextureSize,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TextureDesc {
+    pub fn new(label: &'static str, format: wgpu::TextureFormat) -> Self {
+        Self {
+            label,
+            format,
+            size: TextureSize::Backbuffer,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ColorAttachment {
+    pub target: ResourceId,
+    pub resolve_target: Option<ResourceId>,
+    pub ops: wgpu::Operations<wgpu::Color>,
+}
+
+impl ColorAttachment {
+    pub fn clear(target: ResourceId, color: wgpu::Color) -> Self {
+        Self {
+            target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(color),
+                store: true,
+            },
+        }
+    }
+
+    pub fn load(target: ResourceId) -> Self {
+        Self {
+            target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: true,
+            },
+        }
+    }
+
+    /// Resolves into `target` at the end of the pass. wgpu rejects resolving
+    /// a single-sampled attachment, so without multisampling import `target`
+    /// and the attachment with the same view in `execute`, or not at all, and
+    /// the resolve is skipped.
+    pub fn resolve(mut self, target: ResourceId) -> Self {
+        self.resolve_target = Some(target);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DepthAttachment {
+    pub target: ResourceId,
+    pub ops: wgpu::Operations<f32>,
+}
+
+impl DepthAttachment {
+    pub fn clear(target: ResourceId, depth: f32) -> Self {
+        Self {
+            target,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(depth),
+                store: true,
+            },
+        }
+    }
+
+    pub fn load(target: ResourceId) -> Self {
+        Self {
+            target,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: true,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PassDesc {
+    pub label: &'static str,
+    pub color_attachments: Vec<ColorAttachment>,
+    pub depth_attachment: Option<DepthAttachment>,
+    pub reads: Vec<ResourceId>,
+}
+
+impl PassDesc {
+    pub fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            color_attachments: Vec::new(),
+            depth_attachment: None,
+            reads: Vec::new(),
+        }
+    }
+
+    pub fn with_color(mut self, attachment: ColorAttachment) -> Self {
+        self.color_attachments.push(attachment);
+        self
+    }
+
+    pub fn with_depth(mut self, attachment: DepthAttachment) -> Self {
+        self.depth_attachment = Some(attachment);
+        self
+    }
+
+    pub fn reads(mut self, resource: ResourceId) -> Self {
+        self.reads.push(resource);
+        self
+    }
+
+    fn writes(&self) -> impl Iterator<Item = ResourceId> + '_ {
+        self.color_attachments
+            .iter()
+            .flat_map(|a| std::iter::once(a.target).chain(a.resolve_target))
+            .chain(self.depth_attachment.iter().map(|a| a.target))
+    }
+}
+
+pub trait RenderNode<D> {
+    /// Called before any pass of the frame is recorded, e.g. to rebuild bind
+    /// groups that sample transient textures.
+    fn prepare(&mut self, _context: &Context, _resources: &GraphResources, _data: &D) {}
+
+    fn run<'a>(
+        &'a self,
+        data: &'a D,
+        resources: &'a GraphResources,
+        pass: &mut wgpu::RenderPass<'a>,
+    );
+}
+
+struct Pass<D> {
+    desc: PassDesc,
+    node: Box<dyn RenderNode<D>>,
+}
+
+enum ResourceKind {
+    Imported,
+    Transient(TextureDesc),
+}
+
+struct Resource {
+    label: &'static str,
+    kind: ResourceKind,
+}
+
+pub struct TransientTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    size: (u32, u32),
+}
+
+#[derive(Default)]
+pub struct GraphResources {
+    transients: HashMap<ResourceId, TransientTexture>,
+}
+
+impl GraphResources {
+    pub fn texture(&self, id: ResourceId) -> Option<&TransientTexture> {
+        self.transients.get(&id)
+    }
+
+    pub fn view(&self, id: ResourceId) -> Option<&wgpu::TextureView> {
+        self.transients.get(&id).map(|t| &t.view)
+    }
+}
+
+pub struct RenderGraph<D> {
+    resources: Vec<Resource>,
+    passes: Vec<Pass<D>>,
+    order: Option<Vec<usize>>,
+    allocated: GraphResources,
+}
+
+impl<D> Default for RenderGraph<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> RenderGraph<D> {
+    pub fn new() -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+            order: None,
+            allocated: GraphResources::default(),
+        }
+    }
+
+    /// Declares a texture whose view is supplied every frame to `execute`,
+    /// like the swapchain image.
+    pub fn import(&mut self, label: &'static str) -> ResourceId {
+        self.add_resource(label, ResourceKind::Imported)
+    }
+
+    /// Declares a texture owned by the graph. It is allocated on first use and
+    /// reallocated whenever its resolved size changes.
+    pub fn create_texture(&mut self, desc: TextureDesc) -> ResourceId {
+        self.add_resource(desc.label, ResourceKind::Transient(desc))
+    }
+
+    fn add_resource(&mut self, label: &'static str, kind: ResourceKind) -> ResourceId {
+        self.resources.push(Resource { label, kind });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, desc: PassDesc, node: impl RenderNode<D> + 'static) {
+        self.passes.push(Pass {
+            desc,
+            node: Box::new(node),
+        });
+        self.order = None;
+    }
+
+    pub fn pass_labels(&mut self) -> Result<Vec<&'static str>, GraphError> {
+        self.ensure_ordered()?;
+        Ok(self
+            .order
+            .iter()
+            .flatten()
+            .map(|&i| self.passes[i].desc.label)
+            .collect())
+    }
+
+    /// Orders passes so that a pass reading a resource runs after the most
+    /// recent pass added before it that writes the resource, and before the
+    /// next one. Passes writing the same resource keep the order they were
+    /// added in. A pass reading a resource that no earlier pass writes runs
+    /// after the first later pass that does.
+    fn ensure_ordered(&mut self) -> Result<(), GraphError> {
+        if self.order.is_none() {
+            self.order = Some(self.sort_passes()?);
+        }
+        Ok(())
+    }
+
+    fn sort_passes(&self) -> Result<Vec<usize>, GraphError> {
+        let mut dependencies = vec![Vec::new(); self.passes.len()];
+        let mut last_writer: HashMap<ResourceId, usize> = HashMap::new();
+        let mut readers: HashMap<ResourceId, Vec<usize>> = HashMap::new();
+        let mut waiting: HashMap<ResourceId, Vec<usize>> = HashMap::new();
+        for (i, pass) in self.passes.iter().enumerate() {
+            for &resource in &pass.desc.reads {
+                match last_writer.get(&resource) {
+                    Some(&writer) => {
+                        dependencies[i].push(writer);
+                        readers.entry(resource).or_default().push(i);
+                    }
+                    None => waiting.entry(resource).or_default().push(i),
+                }
+            }
+            for resource in pass.desc.writes() {
+                if let Some(&writer) = last_writer.get(&resource) {
+                    dependencies[i].push(writer);
+                }
+                for reader in readers.remove(&resource).unwrap_or_default() {
+                    if reader != i {
+                        dependencies[i].push(reader);
+                    }
+                }
+                for reader in waiting.remove(&resource).unwrap_or_default() {
+                    if reader != i {
+                        dependencies[reader].push(i);
+                        readers.entry(resource).or_default().push(reader);
+                    }
+                }
+                last_writer.insert(resource, i);
+            }
+        }
+
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            None,
+            Visiting,
+            Done,
+        }
+
+        fn visit<D>(
+            i: usize,
+            graph: &RenderGraph<D>,
+            dependencies: &[Vec<usize>],
+            marks: &mut [Mark],
+            stack: &mut Vec<usize>,
+            order: &mut Vec<usize>,
+        ) -> Result<(), GraphError> {
+            match marks[i] {
+                Mark::Done => return Ok(()),
+                Mark::Visiting => {
+                    let start = stack.iter().position(|&j| j == i).unwrap_or(0);
+                    return Err(GraphError::Cycle {
+                        passes: stack[start..]
+                            .iter()
+                            .map(|&j| graph.passes[j].desc.label)
+                            .collect(),
+                    });
+                }
+                Mark::None => {}
+            }
+            marks[i] = Mark::Visiting;
+            stack.push(i);
+            for &dependency in &dependencies[i] {
+                visit(dependency, graph, dependencies, marks, stack, order)?;
+            }
+            stack.pop();
+            marks[i] = Mark::Done;
+            order.push(i);
+            Ok(())
+        }
+
+        let mut marks = vec![Mark::None; self.passes.len()];
+        let mut stack = Vec::new();
+        let mut order = Vec::with_capacity(self.passes.len());
+        for i in 0..self.passes.len() {
+            visit(i, self, &dependencies, &mut marks, &mut stack, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn allocate(&mut self, context: &Context) {
+        let config = context.config();
+        let backbuffer = (config.width, config.height);
+        for (i, resource) in self.resources.iter().enumerate() {
+            let desc = match &resource.kind {
+                ResourceKind::Transient(desc) => desc,
+                ResourceKind::Imported => continue,
+            };
+            let size = desc.size.resolve(backbuffer);
+            let id = ResourceId(i);
+            if self
+                .allocated
+                .transients
+                .get(&id)
+                .is_some_and(|t| t.size == size)
+            {
+                continue;
+            }
+
+            let texture = context.device().create_texture(&wgpu::TextureDescriptor {
+                label: Some(desc.label),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: desc.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: desc.format,
+                usage: desc.usage,
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.allocated.transients.insert(
+                id,
+                TransientTexture {
+                    texture,
+                    view,
+                    size,
+                },
+            );
+        }
+    }
+
+    pub fn execute(
+        &mut self,
+        context: &Context,
+        encoder: &mut wgpu::CommandEncoder,
+        imports: &[(ResourceId, &wgpu::TextureView)],
+        data: &D,
+    ) -> Result<(), GraphError> {
+        self.allocate(context);
+        self.ensure_ordered()?;
+
+        for pass in &mut self.passes {
+            pass.node.prepare(context, &self.allocated, data);
+        }
+
+        let resources = &self.resources;
+        let allocated = &self.allocated;
+        let try_view = |id: ResourceId| -> Option<&wgpu::TextureView> {
+            imports
+                .iter()
+                .find(|(import, _)| *import == id)
+                .map(|(_, view)| *view)
+                .or_else(|| allocated.view(id))
+        };
+        let view = |id: ResourceId| -> &wgpu::TextureView {
+            try_view(id).unwrap_or_else(|| {
+                panic!(
+                    "render graph resource `{}` was not imported",
+                    resources[id.0].label
+                )
+            })
+        };
+
+        for &i in self.order.iter().flatten() {
+            let pass = &self.passes[i];
+            let color_attachments = pass
+                .desc
+                .color_attachments
+                .iter()
+                .map(|attachment| {
+                    let (view, resolve_target) = color_views(attachment, view, try_view);
+                    wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target,
+                        ops: attachment.ops,
+                    }
+                })
+                .collect::<Vec<_>>();
+            let depth_stencil_attachment = pass.desc.depth_attachment.as_ref().map(|attachment| {
+                wgpu::RenderPassDepthStencilAttachment {
+                    view: view(attachment.target),
+                    depth_ops: Some(attachment.ops),
+                    stencil_ops: None,
+                }
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(pass.desc.label),
+                color_attachments: &color_attachments,
+                depth_stencil_attachment,
+            });
+            pass.node.run(data, allocated, &mut render_pass);
+        }
+        Ok(())
+    }
+}
+
+/// The views of a color attachment and of its resolve target. A resolve
+/// target without a view, or with the attachment's own view, is skipped.
+fn color_views<'v, V>(
+    attachment: &ColorAttachment,
+    view: impl Fn(ResourceId) -> &'v V,
+    try_view: impl Fn(ResourceId) -> Option<&'v V>,
+) -> (&'v V, Option<&'v V>) {
+    let target = view(attachment.target);
+    let resolve = attachment
+        .resolve_target
+        .and_then(try_view)
+        .filter(|&resolve| !std::ptr::eq(resolve, target));
+    (target, resolve)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Noop;
+
+    impl RenderNode<()> for Noop {
+        fn run<'a>(&'a self, _: &'a (), _: &'a GraphResources, _: &mut wgpu::RenderPass<'a>) {}
+    }
+
+    fn writes(label: &'static str, target: ResourceId) -> PassDesc {
+        PassDesc::new(label).with_color(ColorAttachment::load(target))
+    }
+
+    #[test]
+    fn linear_chain_keeps_dependency_order() {
+        let mut graph = RenderGraph::<()>::new();
+        let backbuffer = graph.import("backbuffer");
+        let scene = graph.import("scene");
+        let bloom = graph.import("bloom");
+        // Added in reverse, so every pass reads something written later
+        graph.add_pass(
+            writes("Composite", backbuffer).reads(scene).reads(bloom),
+            Noop,
+        );
+        graph.add_pass(writes("Bloom", bloom).reads(scene), Noop);
+        graph.add_pass(writes("Scene", scene), Noop);
+
+        assert_eq!(
+            graph.pass_labels().unwrap(),
+            ["Scene", "Bloom", "Composite"]
+        );
+    }
+
+    #[test]
+    fn later_writer_runs_after_earlier_readers() {
+        let mut graph = RenderGraph::<()>::new();
+        let target = graph.import("target");
+        let output = graph.import("output");
+        graph.add_pass(writes("A", target), Noop);
+        graph.add_pass(writes("B", output).reads(target), Noop);
+        graph.add_pass(writes("C", target), Noop);
+
+        assert_eq!(graph.pass_labels().unwrap(), ["A", "B", "C"]);
+    }
+
+    #[test]
+    fn reader_depends_on_the_most_recent_writer_only() {
+        let mut graph = RenderGraph::<()>::new();
+        let target = graph.import("target");
+        let output = graph.import("output");
+        graph.add_pass(writes("A", target), Noop);
+        graph.add_pass(writes("B", target), Noop);
+        graph.add_pass(writes("C", output).reads(target), Noop);
+        graph.add_pass(writes("D", target), Noop);
+
+        assert_eq!(graph.pass_labels().unwrap(), ["A", "B", "C", "D"]);
+    }
+
+    /// The attachment and resolve views of every pass, given `imports`.
+    fn views<'v>(
+        graph: &RenderGraph<()>,
+        imports: &[(ResourceId, &'v String)],
+    ) -> Vec<(&'v str, Option<&'v str>)> {
+        let try_view = |id: ResourceId| {
+            imports
+                .iter()
+                .find(|(import, _)| *import == id)
+                .map(|(_, view)| *view)
+        };
+        graph
+            .passes
+            .iter()
+            .flat_map(|pass| &pass.desc.color_attachments)
+            .map(|attachment| {
+                let (view, resolve) = color_views(attachment, |id| try_view(id).unwrap(), try_view);
+                (view.as_str(), resolve.map(String::as_str))
+            })
+            .collect()
+    }
+
+    /// Laid out like the renderer's built-in passes.
+    fn resolving_graph() -> (RenderGraph<()>, [ResourceId; 2]) {
+        let mut graph = RenderGraph::<()>::new();
+        let backbuffer = graph.import("backbuffer");
+        let color = graph.import("color");
+        graph.add_pass(writes("Scene", color), Noop);
+        graph.add_pass(
+            PassDesc::new("Text").with_color(ColorAttachment::load(color).resolve(backbuffer)),
+            Noop,
+        );
+        (graph, [backbuffer, color])
+    }
+
+    #[test]
+    fn single_sampled_attachments_skip_their_resolve() {
+        let (graph, [backbuffer, color]) = resolving_graph();
+        let surface = "surface".to_string();
+        let imports = [(backbuffer, &surface), (color, &surface)];
+        assert_eq!(
+            views(&graph, &imports),
+            [("surface", None), ("surface", None)]
+        );
+        assert_eq!(views(&graph, &imports[1..])[1], ("surface", None));
+    }
+
+    #[test]
+    fn multisampled_attachments_resolve_into_the_backbuffer() {
+        let (graph, [backbuffer, color]) = resolving_graph();
+        let (surface, multisampled) = ("surface".to_string(), "msaa".to_string());
+        let imports = [(backbuffer, &surface), (color, &multisampled)];
+        assert_eq!(
+            views(&graph, &imports),
+            [("msaa", None), ("msaa", Some("surface"))]
+        );
+    }
+
+    #[test]
+    fn cycles_are_reported_with_their_passes() {
+        let mut graph = RenderGraph::<()>::new();
+        let first = graph.import("first");
+        let second = graph.import("second");
+        graph.add_pass(writes("A", first).reads(second), Noop);
+        graph.add_pass(writes("B", second).reads(first), Noop);
+
+        let error = graph.pass_labels().unwrap_err();
+        let GraphError::Cycle { passes } = &error;
+        let mut passes = passes.clone();
+        passes.sort_unstable();
+        assert_eq!(passes, ["A", "B"]);
+        assert!(error.to_string().contains("`A`"));
+        assert!(error.to_string().contains("`B`"));
+    }
+}
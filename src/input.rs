@@ -14,9 +14,11 @@ pub struct InputHandler {
     mouse_clicked: HashSet<Button>,
     mouse_pos: (f64, f64),
     mouse_scroll: (f64, f64),
+    screenshot_key: Option<Key>,
 }
 
 pub trait Pressable {
+    #[allow(clippy::wrong_self_convention)]
     fn as_button_or_key(self) -> Either<Key, Button>;
 }
 
@@ -32,6 +34,12 @@ impl Pressable for Button {
     }
 }
 
+impl Default for InputHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl InputHandler {
     pub fn new() -> Self {
         Self {
@@ -41,6 +49,7 @@ impl InputHandler {
             mouse_clicked: HashSet::default(),
             mouse_pos: (0.0, 0.0),
             mouse_scroll: (0.0, 0.0),
+            screenshot_key: Some(Key::F12),
         }
     }
 
@@ -83,6 +92,14 @@ impl InputHandler {
         self.mouse_scroll
     }
 
+    pub fn set_screenshot_key(&mut self, key: Option<Key>) {
+        self.screenshot_key = key;
+    }
+
+    pub fn screenshot_requested(&self) -> bool {
+        self.screenshot_key.is_some_and(|key| self.clicked(key))
+    }
+
     pub fn update_key(&mut self, key: Key, state: ElementState) {
         match state {
             ElementState::Pressed => {
@@ -0,0 +1,32 @@
+pub mod animation;
+pub mod app;
+pub mod atlas;
+pub mod buffers;
+pub mod camera;
+pub mod config;
+pub mod ecs;
+#[cfg(any(test, feature = "golden"))]
+pub mod golden;
+pub mod graph;
+pub mod input;
+pub mod light;
+pub mod pipeline;
+pub mod prefab;
+pub mod quad;
+pub mod reflect;
+pub mod renderer;
+pub mod scene;
+pub mod scene_file;
+pub mod shader;
+pub mod sprite;
+#[cfg(test)]
+mod test_util;
+pub mod text;
+pub mod texture;
+pub mod tiled;
+pub mod tilemap;
+pub mod time;
+pub mod transform;
+pub mod vertex;
+
+pub use app::{run, App, Engine};
@@ -4,7 +4,7 @@ use cgmath::Vector3;
 
 use crate::buffers::ToData;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Light {
     pub position: Vector3<f32>,
     pub color: Vector3<f32>,
@@ -39,7 +39,7 @@ impl<V: AsRef<[Light]>> From<V> for LightsUniform {
 
         LightsUniform(
             lights
-                .into_iter()
+                .iter()
                 .map(|light| LightUniform {
                     position: light.position.into(),
                     _padding_pos: 0,
@@ -1,24 +1,12 @@
+#include "common.wgsl"
+
 // Vertex Shader
 
-struct Camera {
-    view_pos: vec4<f32>;
-    view_proj: mat4x4<f32>;
-};
 [[group(0), binding(0)]]
-var<uniform> camera: Camera;
+var<uniform> camera: CameraUniform;
 
-struct Light {
-    position: vec3<f32>;
-    color: vec3<f32>;
-};
-struct Lights {
-    data: array<Light>;
-};
 [[group(1), binding(0)]]
 var<storage, read> lights: Lights;
-struct LightCount {
-    data: u32;
-};
 
 struct VertexInput {
     [[location(0)]] position: vec3<f32>;
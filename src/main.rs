@@ -1,82 +1,287 @@
-mod camera;
-mod input;
-mod renderer;
-mod texture;
-mod vertex;
-mod light;
-mod quad;
-mod buffers;
-
-use renderer::Renderer;
-use winit::{
-    dpi::{PhysicalSize, Size, Position, PhysicalPosition},
-    event::*,
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
-};
+use cgmath::Rotation3;
+use game_engine::camera::{Camera, CameraController};
+use game_engine::ecs::{system, Access, Entity, Schedule, With, Without, World};
+use game_engine::input::Key;
+use game_engine::prefab::{Override, PrefabInstance, PrefabLibrary};
+use game_engine::quad::Instance;
+use game_engine::scene::{ActiveCamera, Hidden};
+use game_engine::scene_file::{self, SceneFile};
+use game_engine::time::FrameTime;
+use game_engine::transform::{Parent, Transform};
+use game_engine::{App, Engine};
 
-fn main() {
-    env_logger::init();
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_inner_size(Size::Physical(PhysicalSize::<u32>::new(800, 600)))
-        .with_position(Position::Physical(PhysicalPosition::<i32>::new((2560-800)/2 , (1440-600)/2)))
-        .with_resizable(false)
-        .build(&event_loop)
-        .unwrap();
-
-    let mut state = pollster::block_on(Renderer::new(&window));
-
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::RedrawRequested(_) => {
-            state.update();
-            state.input().frame();
-            match state.render() {
-                Ok(_) => {}
-                Err(wgpu::SurfaceError::Lost) => state.resize(state.get_size()),
-                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                Err(e) => eprintln!("{:?}", e),
+const NUM_INSTANCES_PER_ROW: u32 = 10;
+const CAMERA_SPEED: f32 = 12.0;
+const SCENE_PATH: &str = "scene.ron";
+const LANTERN_PREFAB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/prefabs/lantern.ron");
+const LIGHT_DEGREES_PER_SECOND: f32 = 60.0;
+const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(
+    NUM_INSTANCES_PER_ROW as f32 * 0.5 - 0.5,
+    NUM_INSTANCES_PER_ROW as f32 * 0.5 - 0.5,
+    0.0,
+);
+
+struct Demo {
+    camera_controller: CameraController,
+    prefabs: PrefabLibrary,
+    /// The grid quads in spawn order. The first `instances_to_draw` are visible.
+    instances: Vec<Entity>,
+    instances_to_draw: usize,
+    update_schedule: Schedule,
+    render_schedule: Schedule,
+}
+
+/// Spins an entity around the z axis. The entity is drawn between `previous`
+/// and `current`, its angles after the last two fixed updates.
+struct Orbit {
+    degrees_per_second: f32,
+    previous: cgmath::Deg<f32>,
+    current: cgmath::Deg<f32>,
+}
+
+impl Orbit {
+    fn new(degrees_per_second: f32) -> Self {
+        Self {
+            degrees_per_second,
+            previous: cgmath::Deg(0.0),
+            current: cgmath::Deg(0.0),
+        }
+    }
+}
+
+/// The camera's eye after the last two fixed updates. The controller moves
+/// `current`, and the camera is drawn in between like the orbits.
+struct CameraMotion {
+    previous: cgmath::Point3<f32>,
+    current: cgmath::Point3<f32>,
+}
+
+impl CameraMotion {
+    fn new(camera: &Camera) -> Self {
+        Self {
+            previous: camera.eye,
+            current: camera.eye,
+        }
+    }
+}
+
+fn instance_grid() -> impl Iterator<Item = Instance> {
+    (0..NUM_INSTANCES_PER_ROW).flat_map(|y| {
+        (0..NUM_INSTANCES_PER_ROW).map(move |x| {
+            let fraction = |i: u32| i as f32 / (NUM_INSTANCES_PER_ROW - 1) as f32;
+            Instance::from_position(
+                cgmath::Vector3::new(x as f32, y as f32, 0.0) - INSTANCE_DISPLACEMENT,
+            )
+            .with_tint([1.0, 0.5 + 0.5 * fraction(x), 0.5 + 0.5 * fraction(y), 1.0])
+        })
+    })
+}
+
+fn orbit(world: &World) {
+    let dt = world.resource::<FrameTime>().fixed_dt;
+    for (_, orbit) in world.query::<&mut Orbit>().iter() {
+        orbit.previous = orbit.current;
+        orbit.current += cgmath::Deg(orbit.degrees_per_second * dt);
+    }
+}
+
+fn interpolate_orbits(world: &World) {
+    let alpha = world.resource::<FrameTime>().alpha;
+    for (_, (transform, orbit)) in world.query::<(&mut Transform, &Orbit)>().iter() {
+        let angle = orbit.previous + (orbit.current - orbit.previous) * alpha;
+        transform.rotation = cgmath::Quaternion::from_angle_z(angle);
+    }
+}
+
+fn interpolate_camera(world: &World) {
+    let alpha = world.resource::<FrameTime>().alpha;
+    for (_, (camera, motion)) in world.query::<(&mut Camera, &CameraMotion)>().iter() {
+        let offset = camera.target - camera.eye;
+        camera.eye = motion.previous + (motion.current - motion.previous) * alpha;
+        camera.target = camera.eye + offset;
+    }
+}
+
+impl Demo {
+    fn spawn_instances(&mut self, world: &mut World, instances: impl Iterator<Item = Instance>) {
+        for entity in self.instances.drain(..) {
+            world.despawn(entity);
+        }
+        self.instances = instances.map(|instance| world.spawn((instance,))).collect();
+        self.instances_to_draw = self.instances_to_draw.min(self.instances.len());
+        for &entity in &self.instances[self.instances_to_draw..] {
+            world.insert(entity, Hidden);
+        }
+    }
+
+    /// Replaces every entity with the ones in `file`. Orbits are not saved, so
+    /// the lantern stops moving.
+    fn load_scene(&mut self, world: &mut World, file: &SceneFile) {
+        for entity in world.entities().collect::<Vec<_>>() {
+            world.despawn(entity);
+        }
+        if let Err(e) = file.spawn_with(world, &mut self.prefabs) {
+            log::error!("Failed to load scene: {}", e);
+            return;
+        }
+        let cameras = world
+            .query::<(&Camera, With<ActiveCamera>)>()
+            .iter()
+            .map(|(entity, (camera, ()))| (entity, CameraMotion::new(camera)))
+            .collect::<Vec<_>>();
+        for (entity, motion) in cameras {
+            world.insert(entity, motion);
+        }
+        self.instances = world
+            .query::<(&Instance, Without<Parent>)>()
+            .iter()
+            .map(|(entity, _)| entity)
+            .collect();
+        self.instances
+            .sort_by_key(|&entity| world.has::<Hidden>(entity));
+        self.instances_to_draw = self
+            .instances
+            .iter()
+            .filter(|&&entity| !world.has::<Hidden>(entity))
+            .count();
+        log::info!("Loaded scene from {}", SCENE_PATH);
+    }
+}
+
+impl App for Demo {
+    fn init(engine: &mut Engine) -> Self {
+        engine.renderer.set_sample_count(4);
+
+        let world = &mut engine.world;
+        let size = engine.renderer.get_size();
+        let camera = Camera::basic(size.width, size.height, cgmath::Deg(45.0));
+        world.spawn((CameraMotion::new(&camera), camera, ActiveCamera));
+
+        // A lantern circling the origin, carrying a light and a quad with it,
+        // and a second one standing still without its quad
+        let mut prefabs = PrefabLibrary::new();
+        let lantern = world.spawn((Transform::default(), Orbit::new(LIGHT_DEGREES_PER_SECOND)));
+        let lanterns = prefabs
+            .instantiate_into(world, lantern, PrefabInstance::new(LANTERN_PREFAB))
+            .and_then(|()| {
+                prefabs.instantiate(
+                    world,
+                    PrefabInstance::new(LANTERN_PREFAB)
+                        .with_override(Override::new("light").translation([-2.0, -2.0, -0.1]))
+                        .with_override(Override::new("light/quad").hidden(true)),
+                )
+            });
+        if let Err(e) = lanterns {
+            log::error!("Failed to instantiate the lanterns: {}", e);
+        }
+
+        let mut demo = Self {
+            camera_controller: CameraController::new(CAMERA_SPEED),
+            prefabs,
+            instances: Vec::new(),
+            instances_to_draw: usize::MAX,
+            update_schedule: Schedule::new().with_system(system(
+                "orbit",
+                Access::new().write::<Orbit>().read::<FrameTime>(),
+                orbit,
+            )),
+            render_schedule: Schedule::new()
+                .with_system(system(
+                    "interpolate_orbits",
+                    Access::new()
+                        .write::<Transform>()
+                        .read::<Orbit>()
+                        .read::<FrameTime>(),
+                    interpolate_orbits,
+                ))
+                .with_system(system(
+                    "interpolate_camera",
+                    Access::new()
+                        .write::<Camera>()
+                        .read::<CameraMotion>()
+                        .read::<FrameTime>(),
+                    interpolate_camera,
+                )),
+        };
+        demo.spawn_instances(world, instance_grid());
+        demo
+    }
+
+    fn update(&mut self, engine: &mut Engine, dt: f32) {
+        let input = &engine.input;
+        let world = &mut engine.world;
+        if input.clicked(Key::Up) && self.instances_to_draw < self.instances.len() {
+            world.remove::<Hidden>(self.instances[self.instances_to_draw]);
+            self.instances_to_draw += 1;
+        }
+        if input.clicked(Key::Down) && self.instances_to_draw > 0 {
+            self.instances_to_draw -= 1;
+            world.insert(self.instances[self.instances_to_draw], Hidden);
+        }
+        if input.clicked(Key::Space) {
+            self.spawn_instances(world, instance_grid().filter(|_| rand::random()));
+        }
+        if input.clicked(Key::F5) {
+            match scene_file::save(world, SCENE_PATH) {
+                Ok(()) => log::info!("Saved scene to {}", SCENE_PATH),
+                Err(e) => log::error!("Failed to save scene: {}", e),
             }
         }
-        Event::MainEventsCleared => window.request_redraw(),
-        Event::WindowEvent {
-            ref event,
-            window_id,
-        } if window_id == window.id() => match event {
-            WindowEvent::CloseRequested
-            | WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(VirtualKeyCode::Escape),
-                        ..
-                    },
-                ..
-            } => *control_flow = ControlFlow::Exit,
-            &WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        state: key_state,
-                        virtual_keycode: Some(key),
-                        ..
-                    },
-                ..
-            } => state.input().update_key(key, key_state),
-            &WindowEvent::MouseWheel { delta, .. } => state.input().update_wheel(delta),
-            &WindowEvent::MouseInput {
-                state: button_state,
-                button,
-                ..
-            } => state.input().update_button(button, button_state),
-            &WindowEvent::CursorMoved { position, .. } => {
-                state.input().update_cursor(position)
+        if input.clicked(Key::F9) {
+            match SceneFile::load(SCENE_PATH) {
+                Ok(file) => self.load_scene(world, &file),
+                Err(e) => log::error!("Failed to load scene: {}", e),
             }
-            WindowEvent::Resized(size) => state.resize(*size),
-            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                state.resize(**new_inner_size)
+        }
+
+        if input.clicked(Key::V) {
+            // Skip the modes the backend falls back from
+            let modes = [
+                wgpu::PresentMode::Fifo,
+                wgpu::PresentMode::Mailbox,
+                wgpu::PresentMode::Immediate,
+            ];
+            let current = engine.renderer.present_mode();
+            let start = modes.iter().position(|&mode| mode == current).unwrap_or(0);
+            for offset in 1..modes.len() {
+                engine
+                    .renderer
+                    .set_present_mode(modes[(start + offset) % modes.len()]);
+                if engine.renderer.present_mode() != current {
+                    break;
+                }
             }
-            _ => {}
-        },
-        _ => {}
-    });
+            log::info!("Present mode: {:?}", engine.renderer.present_mode());
+        }
+
+        // Move from the last simulated position rather than the interpolated
+        // one the camera was drawn at
+        for (_, (camera, motion, ())) in world
+            .query::<(&mut Camera, &mut CameraMotion, With<ActiveCamera>)>()
+            .iter()
+        {
+            let offset = camera.target - camera.eye;
+            camera.eye = motion.current;
+            camera.target = motion.current + offset;
+            self.camera_controller.update(camera, input, dt);
+            motion.previous = motion.current;
+            motion.current = camera.eye;
+        }
+
+        self.update_schedule.run(world);
+    }
+
+    fn render(&mut self, engine: &mut Engine, _alpha: f32) {
+        if cfg!(debug_assertions) {
+            if let Err(e) = self.prefabs.reload_changed(&mut engine.world) {
+                log::error!("Failed to reload prefabs: {}", e);
+            }
+        }
+        self.render_schedule.run(&mut engine.world);
+    }
+}
+
+fn main() {
+    env_logger::init();
+    game_engine::run::<Demo>();
 }
@@ -0,0 +1,320 @@
+use std::borrow::Cow;
+
+use crate::reflect::{BindGroupResource, LayoutError, ShaderLayout};
+use crate::renderer::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Replace,
+    Alpha,
+    Additive,
+    Multiply,
+    Premultiplied,
+    Custom(wgpu::BlendState),
+}
+
+impl BlendMode {
+    pub fn state(self) -> Option<wgpu::BlendState> {
+        match self {
+            BlendMode::Replace => None,
+            BlendMode::Alpha => Some(wgpu::BlendState::ALPHA_BLENDING),
+            BlendMode::Additive => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+            BlendMode::Multiply => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::Zero,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+            BlendMode::Premultiplied => Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            BlendMode::Custom(state) => Some(state),
+        }
+    }
+}
+
+pub struct PipelineBuilder<'a> {
+    context: &'a Context,
+    label: Option<&'a str>,
+    shader: Cow<'a, str>,
+    vertex_entry: &'a str,
+    fragment_entry: &'a str,
+    bind_group_layouts: Vec<&'a wgpu::BindGroupLayout>,
+    resources: Vec<&'a dyn BindGroupResource>,
+    reflection: Option<&'a ShaderLayout>,
+    vertex_layouts: Vec<wgpu::VertexBufferLayout<'a>>,
+    targets: Vec<wgpu::ColorTargetState>,
+    primitive: wgpu::PrimitiveState,
+    strip_index_format: wgpu::IndexFormat,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    sample_count: u32,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    pub fn new(context: &'a Context, shader: impl Into<Cow<'a, str>>) -> Self {
+        Self {
+            context,
+            label: Some("Render Pipeline"),
+            shader: shader.into(),
+            vertex_entry: "vs_main",
+            fragment_entry: "fs_main",
+            bind_group_layouts: Vec::new(),
+            resources: Vec::new(),
+            reflection: None,
+            vertex_layouts: Vec::new(),
+            targets: vec![wgpu::ColorTargetState {
+                format: context.config().format,
+                blend: BlendMode::Alpha.state(),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            strip_index_format: wgpu::IndexFormat::Uint16,
+            depth_stencil: None,
+            sample_count: context.sample_count(),
+        }
+    }
+
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn vertex_entry(mut self, entry_point: &'a str) -> Self {
+        self.vertex_entry = entry_point;
+        self
+    }
+
+    pub fn fragment_entry(mut self, entry_point: &'a str) -> Self {
+        self.fragment_entry = entry_point;
+        self
+    }
+
+    pub fn bind_group_layouts(mut self, layouts: &[&'a wgpu::BindGroupLayout]) -> Self {
+        self.bind_group_layouts = layouts.to_vec();
+        self.resources.clear();
+        self
+    }
+
+    /// Uses the layouts of `resources` for groups `0..resources.len()`. With a
+    /// reflected shader layout, `try_build` checks them against the shader.
+    pub fn bind_groups(mut self, resources: &[&'a dyn BindGroupResource]) -> Self {
+        self.bind_group_layouts = resources.iter().map(|r| r.layout()).collect();
+        self.resources = resources.to_vec();
+        self
+    }
+
+    /// Validates the bind groups against the bindings the shader declares, and
+    /// creates layouts from the shader for groups no layout was given for.
+    pub fn reflect(mut self, layout: &'a ShaderLayout) -> Self {
+        self.reflection = Some(layout);
+        self
+    }
+
+    pub fn vertex_layouts(mut self, layouts: &[wgpu::VertexBufferLayout<'a>]) -> Self {
+        self.vertex_layouts = layouts.to_vec();
+        self
+    }
+
+    /// Sets the blend mode of every color target.
+    pub fn blend(mut self, mode: BlendMode) -> Self {
+        for target in &mut self.targets {
+            target.blend = mode.state();
+        }
+        self
+    }
+
+    /// Replaces the default single color target, e.g. to render into multiple
+    /// targets at once.
+    pub fn color_targets(mut self, targets: &[(wgpu::TextureFormat, BlendMode)]) -> Self {
+        self.targets = targets
+            .iter()
+            .map(|&(format, blend)| wgpu::ColorTargetState {
+                format,
+                blend: blend.state(),
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+            .collect();
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        self.update_strip_index_format();
+        self
+    }
+
+    /// The index format strip topologies are drawn with, `Uint16` by default.
+    /// It has to match the format of the index buffer.
+    pub fn strip_index_format(mut self, format: wgpu::IndexFormat) -> Self {
+        self.strip_index_format = format;
+        self.update_strip_index_format();
+        self
+    }
+
+    fn update_strip_index_format(&mut self) {
+        self.primitive.strip_index_format = match self.primitive.topology {
+            wgpu::PrimitiveTopology::LineStrip | wgpu::PrimitiveTopology::TriangleStrip => {
+                Some(self.strip_index_format)
+            }
+            _ => None,
+        };
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.primitive.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+        self.primitive.front_face = front_face;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.primitive.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// Enables depth testing against a depth attachment of the given format,
+    /// with `Less` comparison and depth writes on.
+    pub fn depth(mut self, format: wgpu::TextureFormat) -> Self {
+        self.depth_stencil = Some(wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        });
+        self
+    }
+
+    pub fn depth_format(self, format: Option<wgpu::TextureFormat>) -> Self {
+        match format {
+            Some(format) => self.depth(format),
+            None => self.no_depth(),
+        }
+    }
+
+    pub fn no_depth(mut self) -> Self {
+        self.depth_stencil = None;
+        self
+    }
+
+    /// Only has an effect once a depth format is set.
+    pub fn depth_test(mut self, enabled: bool) -> Self {
+        if let Some(depth) = &mut self.depth_stencil {
+            depth.depth_compare = if enabled {
+                wgpu::CompareFunction::Less
+            } else {
+                wgpu::CompareFunction::Always
+            };
+        }
+        self
+    }
+
+    /// Only has an effect once a depth format is set.
+    pub fn depth_compare(mut self, compare: wgpu::CompareFunction) -> Self {
+        if let Some(depth) = &mut self.depth_stencil {
+            depth.depth_compare = compare;
+        }
+        self
+    }
+
+    /// Only has an effect once a depth format is set.
+    pub fn depth_write(mut self, enabled: bool) -> Self {
+        if let Some(depth) = &mut self.depth_stencil {
+            depth.depth_write_enabled = enabled;
+        }
+        self
+    }
+
+    /// Defaults to the sample count of the context.
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn build(self) -> wgpu::RenderPipeline {
+        self.try_build()
+            .unwrap_or_else(|e| panic!("bind groups do not match the shader: {}", e))
+    }
+
+    pub fn try_build(self) -> Result<wgpu::RenderPipeline, LayoutError> {
+        let device = self.context.device();
+
+        let mut reflected_layouts = Vec::new();
+        if let Some(reflection) = self.reflection {
+            for (group, resource) in self.resources.iter().enumerate() {
+                reflection.check(group as u32, *resource)?;
+            }
+            reflected_layouts = (self.bind_group_layouts.len() as u32..reflection.group_count())
+                .map(|group| reflection.create_bind_group_layout(device, group))
+                .collect();
+        }
+        let bind_group_layouts = self
+            .bind_group_layouts
+            .iter()
+            .copied()
+            .chain(&reflected_layouts)
+            .collect::<Vec<_>>();
+
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: self.label,
+            source: wgpu::ShaderSource::Wgsl(self.shader),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: self.label,
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        Ok(
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: self.label,
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: self.vertex_entry,
+                    buffers: &self.vertex_layouts,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: self.fragment_entry,
+                    targets: &self.targets,
+                }),
+                primitive: self.primitive,
+                depth_stencil: self.depth_stencil,
+                multisample: wgpu::MultisampleState {
+                    count: self.sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            }),
+        )
+    }
+}
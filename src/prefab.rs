@@ -0,0 +1,507 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use cgmath::{Deg, Euler, Quaternion};
+use serde::{Deserialize, Serialize};
+
+use crate::ecs::{Entity, World};
+use crate::light::Light;
+use crate::scene::{Hidden, Name, Sprite};
+use crate::scene_file::{SceneFile, SceneFileError};
+use crate::transform::{self, Children, Parent, Transform};
+
+/// An instance of the prefab at `path`. The prefab's entities are spawned as
+/// children of the entity holding this, then `overrides` are applied to them.
+///
+/// Overrides are kept apart from the prefab, so reinstantiating after the
+/// prefab file changed picks up its edits without losing them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrefabInstance {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<Override>,
+}
+
+impl PrefabInstance {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            overrides: Vec::new(),
+        }
+    }
+
+    pub fn with_override(mut self, with_override: Override) -> Self {
+        self.overrides.push(with_override);
+        self
+    }
+}
+
+/// Replaces single properties of one entity in a prefab instance. Properties
+/// left as `None` keep the prefab's value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Override {
+    /// The `Name`s leading from the prefab's top-level entities to the
+    /// overridden entity, separated by `/`, like `lantern/flame`. Names in
+    /// nested prefabs can be reached the same way.
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translation: Option<[f32; 3]>,
+    /// Euler angles in degrees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light_position: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light_color: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+}
+
+impl Override {
+    pub fn new(target: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn translation(mut self, translation: [f32; 3]) -> Self {
+        self.translation = Some(translation);
+        self
+    }
+
+    pub fn rotation(mut self, rotation: [f32; 3]) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    pub fn scale(mut self, scale: [f32; 3]) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    pub fn texture(mut self, texture: impl Into<PathBuf>) -> Self {
+        self.texture = Some(texture.into());
+        self
+    }
+
+    pub fn light_position(mut self, position: [f32; 3]) -> Self {
+        self.light_position = Some(position);
+        self
+    }
+
+    pub fn light_color(mut self, color: [f32; 3]) -> Self {
+        self.light_color = Some(color);
+        self
+    }
+
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = Some(hidden);
+        self
+    }
+
+    /// Sets the overridden properties on `entity`, adding the components they
+    /// belong to if it is missing them.
+    pub fn apply(&self, world: &mut World, entity: Entity) {
+        if self.translation.is_some() || self.rotation.is_some() || self.scale.is_some() {
+            let mut transform = world
+                .get::<Transform>(entity)
+                .map_or_else(Transform::default, |transform| *transform);
+            if let Some(translation) = self.translation {
+                transform.translation = translation.into();
+            }
+            if let Some([x, y, z]) = self.rotation {
+                transform.rotation = Quaternion::from(Euler::new(Deg(x), Deg(y), Deg(z)));
+            }
+            if let Some(scale) = self.scale {
+                transform.scale = scale.into();
+            }
+            world.insert(entity, transform);
+        }
+        if let Some(texture) = &self.texture {
+            let mut sprite = world
+                .get::<Sprite>(entity)
+                .map_or_else(Sprite::default, |sprite| sprite.clone());
+            sprite.texture = Some(texture.clone());
+            world.insert(entity, sprite);
+        }
+        if self.light_position.is_some() || self.light_color.is_some() {
+            let mut light = world
+                .get::<Light>(entity)
+                .map_or_else(|| Light::new([0.0; 3], [1.0; 3]), |light| light.clone());
+            if let Some(position) = self.light_position {
+                light.position = position.into();
+            }
+            if let Some(color) = self.light_color {
+                light.color = color.into();
+            }
+            world.insert(entity, light);
+        }
+        match self.hidden {
+            Some(true) => {
+                world.insert(entity, Hidden);
+            }
+            Some(false) => {
+                world.remove::<Hidden>(entity);
+            }
+            None => {}
+        }
+    }
+}
+
+struct LoadedPrefab {
+    file: Rc<SceneFile>,
+    modified: Option<SystemTime>,
+}
+
+/// Loads prefab files once and instantiates them into worlds.
+#[derive(Default)]
+pub struct PrefabLibrary {
+    prefabs: HashMap<PathBuf, LoadedPrefab>,
+    instantiating: Vec<PathBuf>,
+}
+
+impl PrefabLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The prefab at `path`, loading it on first use.
+    pub fn get(&mut self, path: &Path) -> Result<Rc<SceneFile>, SceneFileError> {
+        if let Some(prefab) = self.prefabs.get(path) {
+            return Ok(prefab.file.clone());
+        }
+        let file = Rc::new(SceneFile::load(path)?);
+        self.prefabs.insert(
+            path.to_owned(),
+            LoadedPrefab {
+                file: file.clone(),
+                modified: modified(path),
+            },
+        );
+        Ok(file)
+    }
+
+    /// Spawns a new entity with a default `Transform` and instantiates the
+    /// prefab under it.
+    pub fn instantiate(
+        &mut self,
+        world: &mut World,
+        instance: PrefabInstance,
+    ) -> Result<Entity, SceneFileError> {
+        let root = world.spawn((Transform::default(),));
+        match self.instantiate_into(world, root, instance) {
+            Ok(()) => Ok(root),
+            Err(e) => {
+                transform::despawn_recursive(world, root);
+                Err(e)
+            }
+        }
+    }
+
+    /// Instantiates the prefab as children of `root` and adds `instance` to
+    /// it. Overrides whose target does not exist, for example because it was
+    /// renamed in the prefab, are skipped with a warning.
+    ///
+    /// Relative paths of prefabs nested in a prefab file are relative to that
+    /// file.
+    pub fn instantiate_into(
+        &mut self,
+        world: &mut World,
+        root: Entity,
+        mut instance: PrefabInstance,
+    ) -> Result<(), SceneFileError> {
+        instance.path = resolve(self.instantiating.last(), &instance.path);
+        let path = instance.path.clone();
+        if self.instantiating.contains(&path) {
+            return Err(SceneFileError::PrefabCycle { path });
+        }
+        let prefab = self.get(&path)?;
+
+        self.instantiating.push(path.clone());
+        let entities = prefab.spawn_with(world, self);
+        self.instantiating.pop();
+        let entities = entities.map_err(|source| SceneFileError::Prefab {
+            path: path.clone(),
+            source: Box::new(source),
+        })?;
+
+        for (data, &entity) in prefab.entities.iter().zip(&entities) {
+            if data.parent.is_none() {
+                transform::set_parent(world, entity, root);
+            }
+        }
+        for with_override in &instance.overrides {
+            match find(world, root, &with_override.target) {
+                Some(entity) => with_override.apply(world, entity),
+                None => log::warn!(
+                    "Prefab {} has no entity `{}` to override",
+                    path.display(),
+                    with_override.target
+                ),
+            }
+        }
+        world.insert(root, instance);
+        Ok(())
+    }
+
+    /// Replaces the children of `root`, which its prefab spawned, with a new
+    /// instance of the prefab with the same overrides. If the prefab fails to
+    /// instantiate, the old children are kept.
+    pub fn reinstantiate(&mut self, world: &mut World, root: Entity) -> Result<(), SceneFileError> {
+        let instance = match world.get::<PrefabInstance>(root) {
+            Some(instance) => instance.clone(),
+            None => return Ok(()),
+        };
+        let staging = world.spawn((Transform::default(),));
+        if let Err(e) = self.instantiate_into(world, staging, instance) {
+            transform::despawn_recursive(world, staging);
+            return Err(e);
+        }
+
+        for child in children(world, root) {
+            transform::despawn_recursive(world, child);
+        }
+        for child in children(world, staging) {
+            transform::set_parent(world, child, root);
+        }
+        if let Some(instance) = world.remove::<PrefabInstance>(staging) {
+            world.insert(root, instance);
+        }
+        world.despawn(staging);
+        Ok(())
+    }
+
+    /// Reloads the prefab files that changed on disk since they were loaded.
+    /// Files that fail to load are logged and keep their previous contents.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for (path, prefab) in &mut self.prefabs {
+            let modified = modified(path);
+            if modified == prefab.modified {
+                continue;
+            }
+            prefab.modified = modified;
+            match SceneFile::load(path) {
+                Ok(file) => {
+                    prefab.file = Rc::new(file);
+                    changed.push(path.clone());
+                }
+                Err(e) => log::error!("Failed to reload prefab: {}", e),
+            }
+        }
+        changed
+    }
+
+    /// Reloads changed prefab files and reinstantiates every outermost
+    /// instance that uses one of them, directly or through nested prefabs.
+    /// Returns how many instances were reinstantiated.
+    pub fn reload_changed(&mut self, world: &mut World) -> Result<usize, SceneFileError> {
+        let changed = self.poll();
+        if changed.is_empty() {
+            return Ok(0);
+        }
+        let instances = world
+            .query::<&PrefabInstance>()
+            .iter()
+            .map(|(entity, instance)| (entity, instance.path.clone()))
+            .collect::<Vec<_>>();
+        let mut count = 0;
+        for (root, path) in instances {
+            if !is_instantiated(world, root) && self.uses_any(&path, &changed)? {
+                self.reinstantiate(world, root)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn uses_any(&mut self, path: &Path, changed: &[PathBuf]) -> Result<bool, SceneFileError> {
+        let mut visited = HashSet::new();
+        let mut stack = vec![path.to_owned()];
+        while let Some(path) = stack.pop() {
+            if changed.contains(&path) {
+                return Ok(true);
+            }
+            if !visited.insert(path.clone()) {
+                continue;
+            }
+            let prefab = self.get(&path)?;
+            stack.extend(
+                prefab
+                    .entities
+                    .iter()
+                    .filter_map(|data| Some(resolve(Some(&path), &data.prefab.as_ref()?.path))),
+            );
+        }
+        Ok(false)
+    }
+}
+
+/// Resolves a prefab path found in the file at `containing` relative to it.
+fn resolve(containing: Option<&PathBuf>, path: &Path) -> PathBuf {
+    match containing.and_then(|containing| containing.parent()) {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_owned(),
+    }
+}
+
+fn children(world: &World, entity: Entity) -> Vec<Entity> {
+    world
+        .get::<Children>(entity)
+        .map(|children| children.0.clone())
+        .unwrap_or_default()
+}
+
+/// Whether `entity` was spawned by a prefab instance above it.
+pub fn is_instantiated(world: &World, entity: Entity) -> bool {
+    let mut ancestor = world.get::<Parent>(entity).map(|parent| parent.0);
+    while let Some(entity) = ancestor {
+        if world.has::<PrefabInstance>(entity) {
+            return true;
+        }
+        ancestor = world.get::<Parent>(entity).map(|parent| parent.0);
+    }
+    false
+}
+
+/// Follows the `/` separated `Name`s in `path` down the children of `root`.
+pub fn find(world: &World, root: Entity, path: &str) -> Option<Entity> {
+    path.split('/').try_fold(root, |entity, name| {
+        let children = world.get::<Children>(entity)?;
+        children.0.iter().copied().find(|&child| {
+            world
+                .get::<Name>(child)
+                .is_some_and(|child_name| child_name.0 == name)
+        })
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_file::Format;
+    use crate::test_util::TempDir;
+
+    fn write_prefab(path: &Path, world: &World) {
+        let source = SceneFile::from_world(world).to_string(Format::Ron).unwrap();
+        std::fs::write(path, source).unwrap();
+    }
+
+    fn write_lamp(path: &Path, color: [f32; 3]) {
+        let mut world = World::new();
+        world.spawn((
+            Name("lamp".to_string()),
+            Transform::default(),
+            Light::new([0.0; 3], color),
+        ));
+        write_prefab(path, &world);
+    }
+
+    /// Makes the next `poll` reload `path` whatever its modification time.
+    fn touch(library: &mut PrefabLibrary, path: &Path) {
+        library.prefabs.get_mut(path).unwrap().modified = None;
+    }
+
+    #[test]
+    fn overrides_survive_a_reload() {
+        let dir = TempDir::new("prefab_reload");
+        let path = dir.join("lamp.ron");
+        write_lamp(&path, [1.0, 1.0, 1.0]);
+
+        let mut world = World::new();
+        let mut library = PrefabLibrary::new();
+        let instance = PrefabInstance::new(&path)
+            .with_override(Override::new("lamp").translation([5.0, 0.0, 0.0]));
+        let root = library.instantiate(&mut world, instance).unwrap();
+
+        write_lamp(&path, [0.0, 1.0, 0.0]);
+        touch(&mut library, &path);
+        assert_eq!(library.reload_changed(&mut world).unwrap(), 1);
+
+        let lamp = find(&world, root, "lamp").unwrap();
+        assert_eq!(world.get::<Transform>(lamp).unwrap().translation.x, 5.0);
+        assert_eq!(
+            world.get::<Light>(lamp).unwrap().color,
+            [0.0, 1.0, 0.0].into()
+        );
+        assert_eq!(
+            world.get::<PrefabInstance>(root).unwrap().overrides.len(),
+            1
+        );
+        assert_eq!(world.get::<Children>(root).unwrap().0.len(), 1);
+    }
+
+    #[test]
+    fn broken_prefab_keeps_the_old_instance() {
+        let dir = TempDir::new("prefab_broken");
+        let path = dir.join("lamp.ron");
+        write_lamp(&path, [1.0, 1.0, 1.0]);
+
+        let mut world = World::new();
+        let mut library = PrefabLibrary::new();
+        let instance = PrefabInstance::new(&path)
+            .with_override(Override::new("lamp").translation([5.0, 0.0, 0.0]));
+        let root = library.instantiate(&mut world, instance).unwrap();
+        let entities = world.query::<&Transform>().iter().count();
+
+        let mut broken = World::new();
+        broken.spawn((
+            Name("lamp".to_string()),
+            Transform::default(),
+            PrefabInstance::new("missing.ron"),
+        ));
+        write_prefab(&path, &broken);
+        touch(&mut library, &path);
+        assert!(library.reload_changed(&mut world).is_err());
+
+        let lamp = find(&world, root, "lamp").unwrap();
+        assert_eq!(world.get::<Transform>(lamp).unwrap().translation.x, 5.0);
+        assert!(world.has::<Light>(lamp));
+        assert!(world.has::<PrefabInstance>(root));
+        assert_eq!(world.query::<&Transform>().iter().count(), entities);
+    }
+
+    #[test]
+    fn nested_paths_are_relative_to_their_file() {
+        let dir = TempDir::new("prefab_nested");
+        std::fs::create_dir_all(dir.join("props")).unwrap();
+        write_lamp(&dir.join("props/lamp.ron"), [1.0, 1.0, 1.0]);
+        let mut desk = World::new();
+        desk.spawn((
+            Name("corner".to_string()),
+            Transform::default(),
+            PrefabInstance::new("lamp.ron"),
+        ));
+        write_prefab(&dir.join("props/desk.ron"), &desk);
+
+        let mut world = World::new();
+        let mut library = PrefabLibrary::new();
+        let root = library
+            .instantiate(&mut world, PrefabInstance::new(dir.join("props/desk.ron")))
+            .unwrap();
+        assert!(find(&world, root, "corner/lamp").is_some());
+
+        // Editing the nested file reaches the outer instance.
+        let lamp = dir.join("props/lamp.ron");
+        write_lamp(&lamp, [0.0, 0.0, 1.0]);
+        touch(&mut library, &lamp);
+        assert_eq!(library.reload_changed(&mut world).unwrap(), 1);
+        let lamp = find(&world, root, "corner/lamp").unwrap();
+        assert_eq!(
+            world.get::<Light>(lamp).unwrap().color,
+            [0.0, 0.0, 1.0].into()
+        );
+    }
+}
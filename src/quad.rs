@@ -1,6 +1,8 @@
 use std::ops::Range;
 
+use crate::atlas::UvRect;
 use crate::buffers::{self, ToData};
+use crate::transform::Transform;
 use crate::vertex::Vertex;
 
 pub const VERTICES: &[Vertex] = &[
@@ -52,14 +54,87 @@ where
     }
 }
 
+/// A quad to draw. On an entity, `model` is relative to the entity's
+/// `GlobalTransform`.
+///
+/// The quad is scaled by `scale` and rotated by `rotation` around its center
+/// before `model` places it, shows the `uv` part of its texture multiplied by
+/// `tint`, and is moved `depth` along the world z axis.
+#[derive(Debug, Clone, Copy)]
 pub struct Instance {
-    pub position: cgmath::Vector3<f32>,
+    pub model: cgmath::Matrix4<f32>,
+    pub rotation: cgmath::Rad<f32>,
+    pub scale: cgmath::Vector2<f32>,
+    /// Linear RGBA.
+    pub tint: [f32; 4],
+    pub uv: UvRect,
+    pub depth: f32,
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            model: cgmath::One::one(),
+            rotation: cgmath::Rad(0.0),
+            scale: cgmath::Vector2::new(1.0, 1.0),
+            tint: [1.0; 4],
+            uv: UvRect::FULL,
+            depth: 0.0,
+        }
+    }
+}
+
+impl Instance {
+    pub fn from_position(position: impl Into<cgmath::Vector3<f32>>) -> Self {
+        Self {
+            model: cgmath::Matrix4::from_translation(position.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_transform(transform: &Transform) -> Self {
+        Self {
+            model: transform.matrix(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: impl Into<cgmath::Rad<f32>>) -> Self {
+        self.rotation = rotation.into();
+        self
+    }
+
+    pub fn with_scale(mut self, scale: impl Into<cgmath::Vector2<f32>>) -> Self {
+        self.scale = scale.into();
+        self
+    }
+
+    pub fn with_tint(mut self, tint: [f32; 4]) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn with_uv(mut self, uv: UvRect) -> Self {
+        self.uv = uv;
+        self
+    }
+
+    pub fn with_depth(mut self, depth: f32) -> Self {
+        self.depth = depth;
+        self
+    }
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
+    scale: [f32; 2],
+    rotation: f32,
+    depth: f32,
+    tint: [f32; 4],
+    /// `min` in `xy`, `max` in `zw`.
+    uv: [f32; 4],
 }
 
 impl ToData for Instance {
@@ -67,40 +142,138 @@ impl ToData for Instance {
 
     fn to_data(&self) -> Self::Data {
         Self::Data {
-            model: cgmath::Matrix4::from_translation(self.position).into(),
+            model: self.model.into(),
+            scale: self.scale.into(),
+            rotation: self.rotation.0,
+            depth: self.depth,
+            tint: self.tint,
+            uv: self.uv.to_data(),
         }
     }
 }
 
 impl InstanceRaw {
-    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
+    /// Matches `InstanceInput` in `common.wgsl`.
+    const ATTRIBUTES: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
+        5 => Float32x4,
+        6 => Float32x4,
+        7 => Float32x4,
+        8 => Float32x4,
+        9 => Float32x2,
+        10 => Float32,
+        11 => Float32,
+        12 => Float32x4,
+        13 => Float32x4,
+    ];
 
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
+            attributes: &Self::ATTRIBUTES,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use super::*;
+
+    #[test]
+    fn attributes_match_the_raw_layout() {
+        let offsets = [
+            offset_of!(InstanceRaw, model),
+            offset_of!(InstanceRaw, model) + 16,
+            offset_of!(InstanceRaw, model) + 32,
+            offset_of!(InstanceRaw, model) + 48,
+            offset_of!(InstanceRaw, scale),
+            offset_of!(InstanceRaw, rotation),
+            offset_of!(InstanceRaw, depth),
+            offset_of!(InstanceRaw, tint),
+            offset_of!(InstanceRaw, uv),
+        ];
+        for (attribute, offset) in InstanceRaw::ATTRIBUTES.iter().zip(offsets) {
+            assert_eq!(attribute.offset, offset as u64, "{:?}", attribute);
+        }
+        let last = InstanceRaw::ATTRIBUTES.last().unwrap();
+        assert_eq!(
+            last.offset + last.format.size(),
+            size_of::<InstanceRaw>() as u64
+        );
+        assert_eq!(
+            InstanceRaw::desc().array_stride,
+            size_of::<InstanceRaw>() as u64
+        );
+    }
+
+    #[test]
+    fn attributes_match_instance_input() {
+        let module = naga::front::wgsl::parse_str(include_str!("common.wgsl")).unwrap();
+        let members = module
+            .types
+            .iter()
+            .find_map(|(_, ty)| match &ty.inner {
+                naga::TypeInner::Struct { members, .. }
+                    if ty.name.as_deref() == Some("InstanceInput") =>
+                {
+                    Some(members)
+                }
+                _ => None,
+            })
+            .expect("common.wgsl declares InstanceInput");
+        assert_eq!(members.len(), InstanceRaw::ATTRIBUTES.len());
+
+        for (member, attribute) in members.iter().zip(&InstanceRaw::ATTRIBUTES) {
+            let location = match member.binding {
+                Some(naga::Binding::Location { location, .. }) => location,
+                ref other => panic!("{:?} has binding {:?}", member.name, other),
+            };
+            let format = match module.types[member.ty].inner {
+                naga::TypeInner::Scalar {
+                    kind: naga::ScalarKind::Float,
+                    width: 4,
+                } => wgpu::VertexFormat::Float32,
+                naga::TypeInner::Vector {
+                    size: naga::VectorSize::Bi,
+                    kind: naga::ScalarKind::Float,
+                    width: 4,
+                } => wgpu::VertexFormat::Float32x2,
+                naga::TypeInner::Vector {
+                    size: naga::VectorSize::Quad,
+                    kind: naga::ScalarKind::Float,
+                    width: 4,
+                } => wgpu::VertexFormat::Float32x4,
+                ref other => panic!("{:?} has unexpected type {:?}", member.name, other),
+            };
+            assert_eq!(
+                (location, format),
+                (attribute.shader_location, attribute.format),
+                "{:?}",
+                member.name
+            );
+        }
+    }
+
+    #[test]
+    fn to_data_carries_every_field() {
+        let instance = Instance::from_position([1.0, 2.0, 3.0])
+            .with_scale([4.0, 5.0])
+            .with_rotation(cgmath::Rad(0.5))
+            .with_depth(-0.25)
+            .with_tint([0.1, 0.2, 0.3, 0.4])
+            .with_uv(UvRect {
+                min: [0.125, 0.25],
+                max: [0.5, 0.75],
+            });
+        let raw = instance.to_data();
+        assert_eq!(raw.model[0], [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(raw.model[3], [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(raw.scale, [4.0, 5.0]);
+        assert_eq!(raw.rotation, 0.5);
+        assert_eq!(raw.depth, -0.25);
+        assert_eq!(raw.tint, [0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(raw.uv, [0.125, 0.25, 0.5, 0.75]);
+    }
+}
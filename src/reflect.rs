@@ -0,0 +1,667 @@
+use std::collections::BTreeMap;
+
+use naga::valid::ModuleInfo;
+
+/// One binding of a Rust-side resource, as it appears in the resource's bind
+/// group layout.
+#[derive(Debug, Clone)]
+pub struct BindingLayout {
+    pub entry: wgpu::BindGroupLayoutEntry,
+    /// Size in bytes of a uniform, or of one element of a storage array.
+    pub size: Option<u64>,
+}
+
+/// A resource that owns a bind group and can describe the layout of it, so a
+/// pipeline can check it against what its shader declares.
+pub trait BindGroupResource {
+    fn layout(&self) -> &wgpu::BindGroupLayout;
+
+    fn bind_group(&self) -> &wgpu::BindGroup;
+
+    fn bindings(&self) -> Vec<BindingLayout>;
+
+    fn name(&self) -> String;
+}
+
+#[derive(Debug, Clone)]
+pub struct ShaderBinding {
+    pub group: u32,
+    pub binding: u32,
+    pub name: Option<String>,
+    pub ty: wgpu::BindingType,
+    pub visibility: wgpu::ShaderStages,
+    /// Size in bytes of a uniform, or of one element of a runtime-sized
+    /// storage array.
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("group {group}, binding {binding}{}: {message}", .name.as_ref().map(|n| format!(" (`{}`)", n)).unwrap_or_default())]
+pub struct LayoutError {
+    pub group: u32,
+    pub binding: u32,
+    pub name: Option<String>,
+    pub message: String,
+}
+
+impl LayoutError {
+    fn new(binding: &ShaderBinding, message: String) -> Self {
+        Self {
+            group: binding.group,
+            binding: binding.binding,
+            name: binding.name.clone(),
+            message,
+        }
+    }
+}
+
+/// The resource bindings a shader module declares, grouped by bind group.
+#[derive(Debug, Clone, Default)]
+pub struct ShaderLayout {
+    groups: BTreeMap<u32, Vec<ShaderBinding>>,
+}
+
+impl ShaderLayout {
+    pub fn reflect(module: &naga::Module, info: &ModuleInfo) -> Result<Self, LayoutError> {
+        let mut groups: BTreeMap<u32, Vec<ShaderBinding>> = BTreeMap::new();
+
+        for (handle, variable) in module.global_variables.iter() {
+            let binding = match &variable.binding {
+                Some(binding) => binding,
+                None => continue,
+            };
+
+            let mut visibility = wgpu::ShaderStages::NONE;
+            for (i, entry_point) in module.entry_points.iter().enumerate() {
+                if !info.get_entry_point(i)[handle].is_empty() {
+                    visibility |= match entry_point.stage {
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    };
+                }
+            }
+
+            let mut shader_binding = ShaderBinding {
+                group: binding.group,
+                binding: binding.binding,
+                name: variable.name.clone(),
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                visibility,
+                size: None,
+            };
+            let inner = &module.types[variable.ty].inner;
+            let (ty, size) = match variable.class {
+                naga::StorageClass::Uniform => (
+                    buffer_type(wgpu::BufferBindingType::Uniform),
+                    Some(inner.span(&module.constants) as u64),
+                ),
+                naga::StorageClass::Storage { access } => (
+                    buffer_type(wgpu::BufferBindingType::Storage {
+                        read_only: !access.contains(naga::StorageAccess::STORE),
+                    }),
+                    Some(storage_element_size(module, inner)),
+                ),
+                naga::StorageClass::Handle => (
+                    handle_type(inner)
+                        .map_err(|message| LayoutError::new(&shader_binding, message))?,
+                    None,
+                ),
+                class => {
+                    return Err(LayoutError::new(
+                        &shader_binding,
+                        format!("unsupported storage class {:?}", class),
+                    ))
+                }
+            };
+            shader_binding.ty = ty;
+            shader_binding.size = size;
+
+            groups
+                .entry(binding.group)
+                .or_default()
+                .push(shader_binding);
+        }
+
+        for bindings in groups.values_mut() {
+            bindings.sort_by_key(|b| b.binding);
+        }
+
+        Ok(Self { groups })
+    }
+
+    /// One past the highest bind group index the shader uses.
+    pub fn group_count(&self) -> u32 {
+        self.groups.keys().next_back().map_or(0, |group| group + 1)
+    }
+
+    pub fn group(&self, group: u32) -> &[ShaderBinding] {
+        self.groups.get(&group).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn create_bind_group_layout(
+        &self,
+        device: &wgpu::Device,
+        group: u32,
+    ) -> wgpu::BindGroupLayout {
+        let entries = self
+            .group(group)
+            .iter()
+            .map(|binding| wgpu::BindGroupLayoutEntry {
+                binding: binding.binding,
+                visibility: binding.visibility,
+                ty: binding.ty,
+                count: None,
+            })
+            .collect::<Vec<_>>();
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &entries,
+        })
+    }
+
+    /// Checks that `resource` provides every binding the shader declares in
+    /// `group`, with a matching type, sufficient visibility and the same size.
+    pub fn check(&self, group: u32, resource: &dyn BindGroupResource) -> Result<(), LayoutError> {
+        let provided = resource.bindings();
+        for binding in self.group(group) {
+            let error = |message: String| LayoutError::new(binding, message);
+
+            let layout = provided
+                .iter()
+                .find(|layout| layout.entry.binding == binding.binding)
+                .ok_or_else(|| {
+                    error(format!(
+                        "the shader expects {}, but `{}` has no such binding",
+                        describe(&binding.ty),
+                        resource.name()
+                    ))
+                })?;
+
+            if !compatible(&binding.ty, &layout.entry.ty) {
+                return Err(error(format!(
+                    "the shader expects {}, but `{}` provides {}",
+                    describe(&binding.ty),
+                    resource.name(),
+                    describe(&layout.entry.ty)
+                )));
+            }
+
+            if !layout.entry.visibility.contains(binding.visibility) {
+                return Err(error(format!(
+                    "used in {:?}, but `{}` is only visible to {:?}",
+                    binding.visibility,
+                    resource.name(),
+                    layout.entry.visibility
+                )));
+            }
+
+            if let (Some(expected), Some(actual)) = (binding.size, layout.size) {
+                if expected != actual {
+                    return Err(error(format!(
+                        "the shader expects {} bytes, but `{}` provides {} bytes",
+                        expected,
+                        resource.name(),
+                        actual
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn buffer_type(ty: wgpu::BufferBindingType) -> wgpu::BindingType {
+    wgpu::BindingType::Buffer {
+        ty,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    }
+}
+
+/// Storage buffers usually wrap a runtime-sized array, whose element size is
+/// what the Rust side has to match.
+fn storage_element_size(module: &naga::Module, inner: &naga::TypeInner) -> u64 {
+    let array = match inner {
+        naga::TypeInner::Struct { members, .. } => members
+            .last()
+            .map(|member| &module.types[member.ty].inner)
+            .unwrap_or(inner),
+        _ => inner,
+    };
+    match *array {
+        naga::TypeInner::Array {
+            size: naga::ArraySize::Dynamic,
+            stride,
+            ..
+        } => stride as u64,
+        _ => inner.span(&module.constants) as u64,
+    }
+}
+
+fn handle_type(inner: &naga::TypeInner) -> Result<wgpu::BindingType, String> {
+    match *inner {
+        naga::TypeInner::Sampler { comparison } => Ok(wgpu::BindingType::Sampler(if comparison {
+            wgpu::SamplerBindingType::Comparison
+        } else {
+            wgpu::SamplerBindingType::Filtering
+        })),
+        naga::TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => {
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+            };
+            match class {
+                naga::ImageClass::Sampled { kind, multi } => Ok(wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        _ => wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                }),
+                naga::ImageClass::Depth { multi } => Ok(wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                }),
+                naga::ImageClass::Storage { format, access } => {
+                    Ok(wgpu::BindingType::StorageTexture {
+                        access: match (
+                            access.contains(naga::StorageAccess::LOAD),
+                            access.contains(naga::StorageAccess::STORE),
+                        ) {
+                            (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                            (true, false) => wgpu::StorageTextureAccess::ReadOnly,
+                            _ => wgpu::StorageTextureAccess::WriteOnly,
+                        },
+                        format: storage_format(format)?,
+                        view_dimension,
+                    })
+                }
+            }
+        }
+        ref other => Err(format!("unsupported resource type {:?}", other)),
+    }
+}
+
+fn storage_format(format: naga::StorageFormat) -> Result<wgpu::TextureFormat, String> {
+    use naga::StorageFormat as S;
+    use wgpu::TextureFormat as T;
+
+    Ok(match format {
+        S::R32Uint => T::R32Uint,
+        S::R32Sint => T::R32Sint,
+        S::R32Float => T::R32Float,
+        S::Rg32Uint => T::Rg32Uint,
+        S::Rg32Sint => T::Rg32Sint,
+        S::Rg32Float => T::Rg32Float,
+        S::Rgba8Unorm => T::Rgba8Unorm,
+        S::Rgba8Snorm => T::Rgba8Snorm,
+        S::Rgba8Uint => T::Rgba8Uint,
+        S::Rgba8Sint => T::Rgba8Sint,
+        S::Rgba16Uint => T::Rgba16Uint,
+        S::Rgba16Sint => T::Rgba16Sint,
+        S::Rgba16Float => T::Rgba16Float,
+        S::Rgba32Uint => T::Rgba32Uint,
+        S::Rgba32Sint => T::Rgba32Sint,
+        S::Rgba32Float => T::Rgba32Float,
+        other => return Err(format!("unsupported storage texture format {:?}", other)),
+    })
+}
+
+/// Filtering and multisampling are properties of the resource, not something
+/// the shader declares, so they are not compared.
+fn compatible(shader: &wgpu::BindingType, resource: &wgpu::BindingType) -> bool {
+    use wgpu::BindingType as B;
+
+    match (shader, resource) {
+        (B::Buffer { ty: a, .. }, B::Buffer { ty: b, .. }) => match (a, b) {
+            (
+                wgpu::BufferBindingType::Storage { read_only: true },
+                wgpu::BufferBindingType::Storage { .. },
+            ) => true,
+            _ => a == b,
+        },
+        (B::Sampler(wgpu::SamplerBindingType::Comparison), B::Sampler(b)) => {
+            *b == wgpu::SamplerBindingType::Comparison
+        }
+        (B::Sampler(_), B::Sampler(b)) => *b != wgpu::SamplerBindingType::Comparison,
+        (
+            B::Texture {
+                sample_type: a,
+                view_dimension: a_dim,
+                multisampled: a_multi,
+            },
+            B::Texture {
+                sample_type: b,
+                view_dimension: b_dim,
+                multisampled: b_multi,
+            },
+        ) => {
+            let sample_types_match = match (a, b) {
+                (
+                    wgpu::TextureSampleType::Float { .. },
+                    wgpu::TextureSampleType::Float { .. } | wgpu::TextureSampleType::Depth,
+                ) => true,
+                _ => a == b,
+            };
+            sample_types_match && a_dim == b_dim && a_multi == b_multi
+        }
+        (a @ B::StorageTexture { .. }, b @ B::StorageTexture { .. }) => a == b,
+        _ => false,
+    }
+}
+
+fn describe(ty: &wgpu::BindingType) -> String {
+    match ty {
+        wgpu::BindingType::Buffer { ty, .. } => match ty {
+            wgpu::BufferBindingType::Uniform => "a uniform buffer".to_string(),
+            wgpu::BufferBindingType::Storage { read_only: true } => {
+                "a read-only storage buffer".to_string()
+            }
+            wgpu::BufferBindingType::Storage { read_only: false } => "a storage buffer".to_string(),
+        },
+        wgpu::BindingType::Sampler(ty) => format!("a {:?} sampler", ty),
+        wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension,
+            multisampled,
+        } => format!(
+            "a {}{:?} {:?} texture",
+            if *multisampled { "multisampled " } else { "" },
+            view_dimension,
+            sample_type
+        ),
+        wgpu::BindingType::StorageTexture {
+            format,
+            view_dimension,
+            ..
+        } => format!("a {:?} {:?} storage texture", view_dimension, format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = r#"
+struct Globals {
+    color: vec4<f32>;
+    scale: f32;
+};
+
+struct Points {
+    points: array<vec4<f32>>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> globals: Globals;
+[[group(0), binding(1)]]
+var<storage, read> points: Points;
+[[group(1), binding(0)]]
+var t_diffuse: texture_2d<f32>;
+[[group(1), binding(1)]]
+var s_diffuse: sampler;
+[[group(1), binding(2)]]
+var t_shadow: texture_depth_2d;
+[[group(1), binding(3)]]
+var s_shadow: sampler_comparison;
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    return points.points[index] * globals.scale;
+}
+
+[[stage(fragment)]]
+fn fs_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    let shadow = textureSampleCompare(t_shadow, s_shadow, position.xy, position.z);
+    return textureSample(t_diffuse, s_diffuse, position.xy) * globals.color * shadow;
+}
+"#;
+
+    fn layout() -> ShaderLayout {
+        let module = naga::front::wgsl::parse_str(SHADER).unwrap();
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        .unwrap();
+        ShaderLayout::reflect(&module, &info).unwrap()
+    }
+
+    /// Only describes its bindings, `check` never touches the wgpu objects.
+    struct FakeResource(Vec<BindingLayout>);
+
+    impl BindGroupResource for FakeResource {
+        fn layout(&self) -> &wgpu::BindGroupLayout {
+            unreachable!()
+        }
+
+        fn bind_group(&self) -> &wgpu::BindGroup {
+            unreachable!()
+        }
+
+        fn bindings(&self) -> Vec<BindingLayout> {
+            self.0.clone()
+        }
+
+        fn name(&self) -> String {
+            "fake".to_string()
+        }
+    }
+
+    fn entry(
+        binding: u32,
+        visibility: wgpu::ShaderStages,
+        ty: wgpu::BindingType,
+        size: Option<u64>,
+    ) -> BindingLayout {
+        BindingLayout {
+            entry: wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility,
+                ty,
+                count: None,
+            },
+            size,
+        }
+    }
+
+    fn buffers() -> Vec<BindingLayout> {
+        vec![
+            entry(
+                0,
+                wgpu::ShaderStages::VERTEX_FRAGMENT,
+                buffer_type(wgpu::BufferBindingType::Uniform),
+                Some(32),
+            ),
+            entry(
+                1,
+                wgpu::ShaderStages::VERTEX,
+                buffer_type(wgpu::BufferBindingType::Storage { read_only: false }),
+                Some(16),
+            ),
+        ]
+    }
+
+    fn textures() -> Vec<BindingLayout> {
+        vec![
+            entry(
+                0,
+                wgpu::ShaderStages::FRAGMENT,
+                wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            ),
+            entry(
+                1,
+                wgpu::ShaderStages::FRAGMENT,
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                None,
+            ),
+            entry(
+                2,
+                wgpu::ShaderStages::FRAGMENT,
+                wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            ),
+            entry(
+                3,
+                wgpu::ShaderStages::FRAGMENT,
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                None,
+            ),
+        ]
+    }
+
+    #[test]
+    fn reflects_bindings_sizes_and_visibility() {
+        let layout = layout();
+        assert_eq!(layout.group_count(), 2);
+
+        let group = layout.group(0);
+        assert_eq!(group.len(), 2);
+        assert_eq!(group[0].name.as_deref(), Some("globals"));
+        assert_eq!(group[0].size, Some(32));
+        assert_eq!(group[0].visibility, wgpu::ShaderStages::VERTEX_FRAGMENT);
+        // The element size of the runtime-sized array, not the whole struct
+        assert_eq!(group[1].size, Some(16));
+        assert_eq!(group[1].visibility, wgpu::ShaderStages::VERTEX);
+        assert_eq!(
+            group[1].ty,
+            buffer_type(wgpu::BufferBindingType::Storage { read_only: true })
+        );
+
+        let group = layout.group(1);
+        assert_eq!(group.len(), 4);
+        assert!(group
+            .iter()
+            .all(|binding| binding.visibility == wgpu::ShaderStages::FRAGMENT));
+        assert_eq!(
+            group[3].ty,
+            wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison)
+        );
+        assert!(layout.group(2).is_empty());
+    }
+
+    #[test]
+    fn matching_resources_pass() {
+        let layout = layout();
+        layout.check(0, &FakeResource(buffers())).unwrap();
+        layout.check(1, &FakeResource(textures())).unwrap();
+    }
+
+    #[test]
+    fn missing_bindings_are_errors() {
+        let mut bindings = buffers();
+        bindings.pop();
+        let error = layout().check(0, &FakeResource(bindings)).unwrap_err();
+        assert_eq!((error.group, error.binding), (0, 1));
+        assert_eq!(error.name.as_deref(), Some("points"));
+        assert!(error.message.contains("no such binding"), "{}", error);
+    }
+
+    #[test]
+    fn type_mismatches_are_errors() {
+        let mut bindings = textures();
+        bindings[3].entry.ty = wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering);
+        let error = layout().check(1, &FakeResource(bindings)).unwrap_err();
+        assert_eq!((error.group, error.binding), (1, 3));
+        assert!(
+            error.message.contains("provides a Filtering sampler"),
+            "{}",
+            error
+        );
+
+        let mut bindings = buffers();
+        bindings[0].entry.ty = buffer_type(wgpu::BufferBindingType::Storage { read_only: true });
+        let error = layout().check(0, &FakeResource(bindings)).unwrap_err();
+        assert_eq!(error.binding, 0);
+    }
+
+    #[test]
+    fn visibility_mismatches_are_errors() {
+        let mut bindings = buffers();
+        bindings[0].entry.visibility = wgpu::ShaderStages::FRAGMENT;
+        let error = layout().check(0, &FakeResource(bindings)).unwrap_err();
+        assert_eq!(error.binding, 0);
+        assert!(error.message.contains("only visible to"), "{}", error);
+    }
+
+    #[test]
+    fn size_mismatches_are_errors() {
+        let mut bindings = buffers();
+        bindings[1].size = Some(12);
+        let error = layout().check(0, &FakeResource(bindings)).unwrap_err();
+        assert_eq!(error.binding, 1);
+        assert_eq!(
+            error.message,
+            "the shader expects 16 bytes, but `fake` provides 12 bytes"
+        );
+    }
+
+    #[test]
+    fn compatible_accepts_looser_resources() {
+        let read_only = buffer_type(wgpu::BufferBindingType::Storage { read_only: true });
+        let read_write = buffer_type(wgpu::BufferBindingType::Storage { read_only: false });
+        assert!(compatible(&read_only, &read_write));
+        assert!(!compatible(&read_write, &read_only));
+
+        let float = |filterable| wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        };
+        assert!(compatible(&float(true), &float(false)));
+        let cube = wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::Cube,
+            multisampled: false,
+        };
+        assert!(!compatible(&float(true), &cube));
+    }
+
+    #[test]
+    fn handle_types_reject_unsupported_resources() {
+        let storage = handle_type(&naga::TypeInner::Image {
+            dim: naga::ImageDimension::D2,
+            arrayed: true,
+            class: naga::ImageClass::Storage {
+                format: naga::StorageFormat::Rgba8Unorm,
+                access: naga::StorageAccess::STORE,
+            },
+        });
+        assert_eq!(
+            storage.unwrap(),
+            wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+            }
+        );
+        assert!(handle_type(&naga::TypeInner::Scalar {
+            kind: naga::ScalarKind::Float,
+            width: 4
+        })
+        .is_err());
+    }
+}
@@ -1,38 +1,242 @@
-use crate::buffers::{InstanceBuffer, Storage, Uniform};
-use crate::camera::{Camera, CameraController};
-use crate::input::{InputHandler, Key};
+use crate::buffers::{Storage, Uniform};
+use crate::camera::{Camera, ScreenCamera};
+use crate::graph::{
+    ColorAttachment, DepthAttachment, GraphResources, PassDesc, RenderGraph, RenderNode, ResourceId,
+};
 use crate::light::Light;
-use crate::quad::{DrawQuad, Instance, InstanceRaw, Quad};
-use crate::texture::{self, DepthTexture, Texture};
+use crate::pipeline::PipelineBuilder;
+use crate::quad::{DrawQuad, InstanceRaw, Quad};
+use crate::scene::Scene;
+use crate::shader::{self, Loader, Preprocessor, ShaderError, ShaderSource, ShaderWatcher};
+use crate::shader_source;
+use crate::sprite::{SpriteBatcher, SpriteBlend, TextureId};
+use crate::texture::{self, DepthTexture, MultisampleTexture, Texture};
+use crate::tilemap::{TileVertex, TilemapRenderer};
 use crate::vertex::Vertex;
-use cgmath::Rotation3;
+use anyhow::Context as _;
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use winit::dpi::PhysicalSize;
 use winit::window::Window;
 
 pub struct Renderer {
     context: Context,
-    input_handler: InputHandler,
-    render_pipeline: wgpu::RenderPipeline,
+    graph: RenderGraph<FrameData>,
+    backbuffer: ResourceId,
+    color: ResourceId,
+    depth: ResourceId,
     depth_texture: DepthTexture,
-    quad: Quad,
-    diffuse_texture: Texture,
-    camera: Camera,
-    camera_controller: CameraController,
-    camera_uniform: Uniform<Camera>,
-    instances: Vec<Instance>,
-    instance_buffer: InstanceBuffer<Instance>,
-    instances_to_draw: usize,
-    lights_uniform: Uniform<Light>,
-    lights_storage: Storage<Light>,
-    lights: Vec<Light>,
-    light_render_pipeline: wgpu::RenderPipeline,
+    multisample_texture: Option<MultisampleTexture>,
+    frame: FrameData,
+    shader_watcher: Option<ShaderWatcher>,
+}
+
+pub struct FrameData {
+    pub pipelines: Pipelines,
+    pub quad: Quad,
+    pub diffuse_texture: Texture,
+    pub camera_uniform: Uniform<Camera>,
+    pub screen_uniform: Uniform<ScreenCamera>,
+    pub sprite_batcher: SpriteBatcher,
+    pub text_batcher: SpriteBatcher,
+    pub screen_text_batcher: SpriteBatcher,
+    pub tilemaps: TilemapRenderer,
+    /// Textures loaded for sprites by path, `None` if loading failed.
+    pub textures: HashMap<PathBuf, Option<Texture>>,
+    /// The revisions of the scene images uploaded into `textures`.
+    pub image_revisions: HashMap<PathBuf, u64>,
+    /// The paths of the current scene's textures, indexed by `TextureId`.
+    pub texture_paths: Vec<PathBuf>,
+    pub lights_storage: Storage<Light>,
+    pub num_lights: usize,
+}
+
+impl FrameData {
+    /// The texture for `id`, or the default texture if it failed to load.
+    pub fn texture(&self, id: TextureId) -> &Texture {
+        id.0.checked_sub(1)
+            .and_then(|index| self.texture_paths.get(index as usize))
+            .and_then(|path| self.textures.get(path)?.as_ref())
+            .unwrap_or(&self.diffuse_texture)
+    }
+}
+
+pub struct Pipelines {
+    /// One sprite pipeline per `SpriteBlend`, in the order of `SpriteBlend::ALL`.
+    pub sprites: Vec<wgpu::RenderPipeline>,
+    pub tilemap: wgpu::RenderPipeline,
+    pub text: wgpu::RenderPipeline,
+    /// Draws over everything, ignoring depth.
+    pub screen_text: wgpu::RenderPipeline,
+    pub light_debug: wgpu::RenderPipeline,
+}
+
+const SPRITE_SHADER: ShaderSource = shader_source!("shader.wgsl");
+const TILEMAP_SHADER: ShaderSource = shader_source!("tilemap.wgsl");
+const TEXT_SHADER: ShaderSource = shader_source!("text.wgsl");
+const LIGHT_SHADER: ShaderSource = shader_source!("light.wgsl");
+
+impl Pipelines {
+    /// Builds the pipelines from the shaders on disk in debug builds, falling
+    /// back to the embedded shaders if those fail to compile.
+    fn new(
+        context: &Context,
+        diffuse_texture: &Texture,
+        camera_uniform: &Uniform<Camera>,
+        lights_storage: &Storage<Light>,
+        tilemaps: &TilemapRenderer,
+    ) -> Self {
+        Self::build(
+            context,
+            diffuse_texture,
+            camera_uniform,
+            lights_storage,
+            tilemaps,
+            ShaderSource::load,
+        )
+        .unwrap_or_else(|e| {
+            log::error!("{}", e);
+            Self::build(
+                context,
+                diffuse_texture,
+                camera_uniform,
+                lights_storage,
+                tilemaps,
+                |source| source.embedded().into(),
+            )
+            .expect("embedded shaders failed to compile")
+        })
+    }
+
+    fn build(
+        context: &Context,
+        diffuse_texture: &Texture,
+        camera_uniform: &Uniform<Camera>,
+        lights_storage: &Storage<Light>,
+        tilemaps: &TilemapRenderer,
+        load: Loader,
+    ) -> Result<Self, ShaderError> {
+        let preprocessor = Preprocessor::new();
+
+        let sprite_source = preprocessor.process(&SPRITE_SHADER, load)?;
+        let sprite_layout = shader::reflect(&sprite_source)?;
+        let sprites = SpriteBlend::ALL
+            .iter()
+            .map(|&blend| {
+                shader::catch_pipeline_errors(context, SPRITE_SHADER.path(), || {
+                    context
+                        .pipeline(sprite_source.code.as_str())
+                        .label("Sprite Pipeline")
+                        .bind_groups(&[diffuse_texture, camera_uniform, lights_storage])
+                        .reflect(&sprite_layout)
+                        .vertex_layouts(&[Vertex::desc(), InstanceRaw::desc()])
+                        .blend(blend.mode())
+                        .depth(DepthTexture::DEPTH_FORMAT)
+                        .try_build()
+                })?
+                .map_err(|error| ShaderError::Layout {
+                    file: SPRITE_SHADER.path().display().to_string(),
+                    error,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tilemap_source = preprocessor.process(&TILEMAP_SHADER, load)?;
+        let tilemap_layout = shader::reflect(&tilemap_source)?;
+        let tilemap = shader::catch_pipeline_errors(context, TILEMAP_SHADER.path(), || {
+            context
+                .pipeline(tilemap_source.code)
+                .label("Tilemap Pipeline")
+                .bind_groups(&[
+                    diffuse_texture,
+                    camera_uniform,
+                    tilemaps.uvs_layout(),
+                    tilemaps.model_layout(),
+                ])
+                .reflect(&tilemap_layout)
+                .vertex_layouts(&[TileVertex::desc()])
+                .depth(DepthTexture::DEPTH_FORMAT)
+                .depth_write(false)
+                .try_build()
+        })?
+        .map_err(|error| ShaderError::Layout {
+            file: TILEMAP_SHADER.path().display().to_string(),
+            error,
+        })?;
+
+        let text_source = preprocessor.process(&TEXT_SHADER, load)?;
+        let text_layout = shader::reflect(&text_source)?;
+        let text_pipeline = |label, depth_test| {
+            shader::catch_pipeline_errors(context, TEXT_SHADER.path(), || {
+                context
+                    .pipeline(text_source.code.as_str())
+                    .label(label)
+                    .bind_groups(&[diffuse_texture, camera_uniform])
+                    .reflect(&text_layout)
+                    .vertex_layouts(&[Vertex::desc(), InstanceRaw::desc()])
+                    .blend(SpriteBlend::Alpha.mode())
+                    .cull_mode(None)
+                    .depth(DepthTexture::DEPTH_FORMAT)
+                    .depth_test(depth_test)
+                    .depth_write(false)
+                    .try_build()
+            })?
+            .map_err(|error| ShaderError::Layout {
+                file: TEXT_SHADER.path().display().to_string(),
+                error,
+            })
+        };
+        let text = text_pipeline("Text Pipeline", true)?;
+        let screen_text = text_pipeline("Screen Text Pipeline", false)?;
+
+        let light_source = preprocessor.process(&LIGHT_SHADER, load)?;
+        let light_layout = shader::reflect(&light_source)?;
+        let light_debug = shader::catch_pipeline_errors(context, LIGHT_SHADER.path(), || {
+            context
+                .pipeline(light_source.code)
+                .label("Light Debug Pipeline")
+                .bind_groups(&[camera_uniform, lights_storage])
+                .reflect(&light_layout)
+                .vertex_layouts(&[Vertex::desc()])
+                .depth(DepthTexture::DEPTH_FORMAT)
+                .try_build()
+        })?
+        .map_err(|error| ShaderError::Layout {
+            file: LIGHT_SHADER.path().display().to_string(),
+            error,
+        })?;
+
+        Ok(Self {
+            sprites,
+            tilemap,
+            text,
+            screen_text,
+            light_debug,
+        })
+    }
+
+    pub fn sprite(&self, blend: SpriteBlend) -> &wgpu::RenderPipeline {
+        &self.sprites[blend as usize]
+    }
 }
 
 impl Renderer {
-    pub async fn new(window: &Window) -> Self {
-        let context = Context::new(window).await;
+    pub async fn new(window: &Window, options: &ContextOptions) -> Result<Self, ContextError> {
+        let context = Context::new(window, options).await?;
+        Ok(Self::from_context(context))
+    }
+
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        options: &ContextOptions,
+    ) -> Result<Self, ContextError> {
+        let context = Context::new_headless(width, height, options).await?;
+        Ok(Self::from_context(context))
+    }
 
+    fn from_context(context: Context) -> Self {
         let device = &context.device;
         let config = &context.config;
 
@@ -41,132 +245,191 @@ impl Renderer {
 
         let camera = Camera::basic(config.width, config.height, cgmath::Deg(45.0));
         let camera_uniform = Uniform::new(&context, &camera);
+        let screen_uniform = Uniform::new(&context, screen_camera(&context));
 
-        let light1 = Light::new([2.0, 2.0, -0.1], [1.0, 1.0, 1.0]);
-        let light2 = Light::new([-2.0, -2.0, -0.1], [1.0, 1.0, 1.0]);
-        let lights_uniform = Uniform::new(&context, &light1);
-        let lights = vec![light1, light2];
-
-        let lights_storage = Storage::new(&context, &lights);
-        let _num_lights_uniform = Uniform::new(&context, 0u32);
-
-        let render_pipeline = context.create_render_pipeline(
-            include_str!("shader.wgsl").into(),
-            &[
-                &diffuse_texture.layout(),
-                &camera_uniform.layout(),
-                &lights_storage.layout(),
-            ],
-            &[Vertex::desc(), InstanceRaw::desc()],
-            Some(texture::DepthTexture::DEPTH_FORMAT),
-        );
-
-        let light_render_pipeline = context.create_render_pipeline(
-            include_str!("light.wgsl").into(),
-            &[&camera_uniform.layout(), &lights_storage.layout()],
-            &[Vertex::desc()],
-            Some(texture::DepthTexture::DEPTH_FORMAT),
-        );
+        let lights_storage = Storage::new(&context, []);
 
-        let depth_texture = DepthTexture::create_depth_texture(&device, &config);
+        let tilemaps = TilemapRenderer::new(&context);
 
-        let quad = Quad::new(&device);
+        let pipelines = Pipelines::new(
+            &context,
+            &diffuse_texture,
+            &camera_uniform,
+            &lights_storage,
+            &tilemaps,
+        );
 
-        let camera_controller = CameraController::new(0.2);
+        let depth_texture =
+            DepthTexture::create_depth_texture(device, config, context.sample_count());
+        let multisample_texture = (context.sample_count() > 1)
+            .then(|| MultisampleTexture::create(device, config, context.sample_count()));
 
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|y| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| Instance {
-                    position: cgmath::Vector3 {
-                        x: x as f32,
-                        y: y as f32,
-                        z: 0.0,
-                    } - INSTANCE_DISPLACEMENT,
-                })
-            })
-            .collect::<Vec<_>>();
+        let quad = Quad::new(device);
 
-        let instance_buffer = InstanceBuffer::new(&context, &instances);
+        let sprite_batcher = SpriteBatcher::new(&context);
+        let text_batcher = SpriteBatcher::new(&context);
+        let screen_text_batcher = SpriteBatcher::new(&context);
 
-        let input_handler = InputHandler::new();
+        // With multisampling, `color` is the multisampled texture that gets
+        // resolved into the backbuffer; otherwise both are the same view.
+        let mut graph = RenderGraph::new();
+        let backbuffer = graph.import("backbuffer");
+        let color = graph.import("color");
+        let depth = graph.import("depth");
+        graph.add_pass(
+            PassDesc::new("Tilemap Pass")
+                .with_color(ColorAttachment::clear(
+                    color,
+                    wgpu::Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.3,
+                        a: 1.0,
+                    },
+                ))
+                .with_depth(DepthAttachment::clear(depth, 1.0)),
+            TilemapPass,
+        );
+        graph.add_pass(
+            PassDesc::new("Sprite Pass")
+                .with_color(ColorAttachment::load(color))
+                .with_depth(DepthAttachment::load(depth)),
+            SpritePass,
+        );
+        graph.add_pass(
+            PassDesc::new("Light Debug Pass")
+                .with_color(ColorAttachment::load(color))
+                .with_depth(DepthAttachment::load(depth)),
+            LightDebugPass,
+        );
+        graph.add_pass(
+            PassDesc::new("Text Pass")
+                .with_color(ColorAttachment::load(color).resolve(backbuffer))
+                .with_depth(DepthAttachment::load(depth)),
+            TextPass,
+        );
 
         Self {
             context,
-            input_handler,
-            render_pipeline,
+            graph,
+            backbuffer,
+            color,
+            depth,
             depth_texture,
-            quad,
-            diffuse_texture,
-            camera,
-            camera_controller,
-            camera_uniform,
-            instance_buffer,
-            instances_to_draw: instances.len(),
-            instances,
-            lights_uniform,
-            lights_storage,
-            lights,
-            light_render_pipeline,
+            multisample_texture,
+            shader_watcher: cfg!(debug_assertions).then(|| {
+                let mut watcher = ShaderWatcher::new([
+                    SPRITE_SHADER.path(),
+                    TILEMAP_SHADER.path(),
+                    TEXT_SHADER.path(),
+                    LIGHT_SHADER.path(),
+                ]);
+                for module in Preprocessor::new().modules() {
+                    watcher.watch(module.path());
+                }
+                watcher
+            }),
+            frame: FrameData {
+                pipelines,
+                quad,
+                diffuse_texture,
+                camera_uniform,
+                screen_uniform,
+                sprite_batcher,
+                text_batcher,
+                screen_text_batcher,
+                tilemaps,
+                textures: HashMap::new(),
+                image_revisions: HashMap::new(),
+                texture_paths: Vec::new(),
+                lights_storage,
+                num_lights: 0,
+            },
         }
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
-            self.context.size = new_size;
-            self.context.config.width = new_size.width;
-            self.context.config.height = new_size.height;
-            self.camera.resize(new_size.width, new_size.height);
-            self.depth_texture =
-                DepthTexture::create_depth_texture(&self.context.device, &self.context.config);
-            self.context
-                .surface
-                .configure(&self.context.device, &self.context.config)
+            self.context.resize(new_size);
+            self.recreate_targets();
         }
     }
 
-    pub fn update(&mut self) {
-        if self.input().clicked(Key::Up) {
-            self.instances_to_draw = (self.instances_to_draw + 1).clamp(0, self.instances.len());
-        }
-        if self.input().clicked(Key::Down) {
-            self.instances_to_draw = self.instances_to_draw.saturating_sub(1);
-        }
-        if self.input().clicked(Key::Space) {
-            self.instances = (0..NUM_INSTANCES_PER_ROW)
-                .flat_map(|y| {
-                    (0..NUM_INSTANCES_PER_ROW).map(move |x| Instance {
-                        position: cgmath::Vector3 {
-                            x: x as f32,
-                            y: y as f32,
-                            z: 0.0,
-                        } - INSTANCE_DISPLACEMENT,
-                    })
-                })
-                .filter(|_| rand::random())
-                .collect::<Vec<_>>();
-            self.instances_to_draw = self.instances_to_draw.clamp(0, self.instances.len() - 1);
+    pub fn sample_count(&self) -> u32 {
+        self.context.sample_count()
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.context.present_mode()
+    }
+
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        self.context.set_present_mode(present_mode);
+    }
+
+    /// Changes the number of MSAA samples, rebuilding the built-in pipelines
+    /// and render targets. Pipelines of custom passes have to be rebuilt by
+    /// their owners. Only 1 and 4 samples are supported, other counts are
+    /// clamped as described in `Context::set_sample_count`. Returns the sample
+    /// count that was applied.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+        let previous = self.context.sample_count();
+        let applied = self.context.set_sample_count(sample_count);
+        if applied == previous {
+            return applied;
         }
+        self.recreate_targets();
+        self.frame.pipelines = Pipelines::new(
+            &self.context,
+            &self.frame.diffuse_texture,
+            &self.frame.camera_uniform,
+            &self.frame.lights_storage,
+            &self.frame.tilemaps,
+        );
+        applied
+    }
 
-        self.camera_controller
-            .update(&mut self.camera, &self.input_handler);
+    fn recreate_targets(&mut self) {
+        let sample_count = self.context.sample_count();
+        self.depth_texture = DepthTexture::create_depth_texture(
+            &self.context.device,
+            &self.context.config,
+            sample_count,
+        );
+        self.multisample_texture = (sample_count > 1).then(|| {
+            MultisampleTexture::create(&self.context.device, &self.context.config, sample_count)
+        });
+    }
 
-        let old_position: cgmath::Vector3<_> = self.lights[0].position.into();
-        self.lights[0].position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 0.0, 1.0).into(), cgmath::Deg(1.0))
-                * old_position)
-                .into();
-        //let old_position: cgmath::Vector3<_> = self.lights[1].position.into();
-        //self.lights[1].position =
-        //    (cgmath::Quaternion::from_axis_angle((0.0, 0.0, -1.0).into(), cgmath::Deg(1.0))
-        //        * old_position)
-        //        .into();
+    fn reload_shaders(&mut self) {
+        if !self.shader_watcher.as_mut().is_some_and(|w| w.poll()) {
+            return;
+        }
+        match Pipelines::build(
+            &self.context,
+            &self.frame.diffuse_texture,
+            &self.frame.camera_uniform,
+            &self.frame.lights_storage,
+            &self.frame.tilemaps,
+            ShaderSource::load,
+        ) {
+            Ok(pipelines) => {
+                log::info!("Reloaded shaders");
+                self.frame.pipelines = pipelines;
+            }
+            Err(e) => log::error!(
+                "Shader reload failed, keeping the previous pipelines: {}",
+                e
+            ),
+        }
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.context.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    /// Uploads the scene and draws it to the window or offscreen target.
+    pub fn render(&mut self, scene: &Scene) -> Result<(), wgpu::SurfaceError> {
+        self.reload_shaders();
+        self.prepare(scene);
+
+        let frame = self.context.current_frame()?;
 
         let mut encoder =
             self.context
@@ -175,185 +438,668 @@ impl Renderer {
                     label: Some("Render Encoder"),
                 });
 
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render Pass"),
-            color_attachments: &[wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
-                    store: true,
-                },
-            }],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: true,
-                }),
-                stencil_ops: None,
-            }),
+        self.draw(&mut encoder, &frame.view);
+
+        self.context.queue.submit(std::iter::once(encoder.finish()));
+        frame.present();
+
+        Ok(())
+    }
+
+    /// Reads back the offscreen target as last rendered. Surface textures
+    /// cannot be copied from, so with a window the scene uploaded by the last
+    /// `render` is drawn again into a separate texture instead of reading the
+    /// presented frame.
+    pub fn capture(&mut self) -> anyhow::Result<image::RgbaImage> {
+        let config = &self.context.config;
+        if let Some(target) = self.context.offscreen_texture() {
+            return texture::read_texture(
+                &self.context,
+                target,
+                config.width,
+                config.height,
+                config.format,
+            );
+        }
+
+        // Surface textures can only be rendered to, so draw the frame again
+        // into a copyable texture of the same size and format.
+        let target = self.context.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Capture Target"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
         });
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
 
-        let instances = &self.instances[..self.instances_to_draw];
-        self.instance_buffer.update(&self.context, instances);
-        self.camera_uniform.update(&self.context, &self.camera);
-        self.lights_storage.update(&self.context, &self.lights);
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Capture Encoder"),
+                });
+        self.draw(&mut encoder, &view);
+        self.context.queue.submit(std::iter::once(encoder.finish()));
 
-        // Draw everything
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_bind_group(0, &self.diffuse_texture.bind_group(), &[]);
-        render_pass.set_bind_group(1, &self.camera_uniform.bind_group(), &[]);
-        render_pass.set_bind_group(2, &self.lights_storage.bind_group(), &[]);
+        let config = &self.context.config;
+        texture::read_texture(
+            &self.context,
+            &target,
+            config.width,
+            config.height,
+            config.format,
+        )
+    }
 
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.draw_quad_indexed(&self.quad, 0..self.instances.len() as _);
+    pub fn save_screenshot(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        self.capture()?
+            .save(path)
+            .with_context(|| format!("failed to write screenshot to {}", path.display()))
+    }
 
-        // Debug draw lights
-        self.lights_uniform.update(&self.context, &self.lights[1]);
-        render_pass.set_pipeline(&self.light_render_pipeline);
-        render_pass.set_bind_group(0, &self.camera_uniform.bind_group(), &[]);
-        render_pass.set_bind_group(1, &self.lights_storage.bind_group(), &[]);
-        render_pass.draw_quad_indexed(&self.quad, 0..self.lights.len() as _);
+    fn prepare(&mut self, scene: &Scene) {
+        self.frame
+            .camera_uniform
+            .update(&self.context, scene.active_camera());
+        self.frame
+            .screen_uniform
+            .update(&self.context, screen_camera(&self.context));
+        self.load_textures(scene);
+        self.frame
+            .sprite_batcher
+            .prepare(&self.context, &scene.sprites);
+        self.frame.text_batcher.prepare(&self.context, &scene.text);
+        self.frame
+            .screen_text_batcher
+            .prepare(&self.context, &scene.screen_text);
+        self.frame.tilemaps.prepare(&self.context, &scene.tilemaps);
+        self.upload_lights(&scene.lights);
+    }
 
-        drop(render_pass);
+    fn draw(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let depth = (self.depth, &self.depth_texture.view);
+        let result = match &self.multisample_texture {
+            Some(multisampled) => self.graph.execute(
+                &self.context,
+                encoder,
+                &[
+                    (self.backbuffer, view),
+                    (self.color, &multisampled.view),
+                    depth,
+                ],
+                &self.frame,
+            ),
+            // The same view for both skips the resolve into the backbuffer
+            None => self.graph.execute(
+                &self.context,
+                encoder,
+                &[(self.backbuffer, view), (self.color, view), depth],
+                &self.frame,
+            ),
+        };
+        if let Err(e) = result {
+            log::error!("{}", e);
+        }
+    }
 
-        self.context.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    /// Loads the scene's textures that are not loaded yet. Textures that fail
+    /// to load are logged once and drawn with the default texture.
+    fn load_textures(&mut self, scene: &Scene) {
+        let frame = &mut self.frame;
+        for (path, image) in scene.images() {
+            if frame.image_revisions.get(path) == Some(&image.revision) {
+                continue;
+            }
+            let label = path.display().to_string();
+            let texture = Texture::from_image(
+                &self.context,
+                &image::DynamicImage::ImageRgba8((*image.image).clone()),
+                Some(&label),
+            );
+            frame.textures.insert(path.to_owned(), texture.ok());
+            frame
+                .image_revisions
+                .insert(path.to_owned(), image.revision);
+        }
+        frame.texture_paths.clear();
+        for (_, path) in scene.textures() {
+            frame.texture_paths.push(path.to_owned());
+            if frame.textures.contains_key(path) {
+                continue;
+            }
+            let label = path.display().to_string();
+            let texture = image::open(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|img| Texture::from_image(&self.context, &img, Some(&label)));
+            let texture = match texture {
+                Ok(texture) => Some(texture),
+                Err(e) => {
+                    log::error!("Failed to load texture {}: {}", label, e);
+                    None
+                }
+            };
+            frame.textures.insert(path.to_owned(), texture);
+        }
+    }
 
-        Ok(())
+    fn upload_lights(&mut self, lights: &[Light]) {
+        let frame = &mut self.frame;
+        if lights.len() > frame.lights_storage.capacity() {
+            frame.lights_storage = Storage::new(&self.context, lights);
+        }
+        frame.lights_storage.update(&self.context, lights);
+        frame.num_lights = lights.len();
+    }
+
+    pub fn set_texture(&mut self, texture: Texture) {
+        self.frame.diffuse_texture = texture;
+    }
+
+    /// Draws sprites whose texture is `path` with `texture` instead of loading
+    /// it from disk, for textures built at runtime like an `Atlas`.
+    pub fn insert_texture(&mut self, path: impl Into<PathBuf>, texture: Texture) {
+        self.frame.textures.insert(path.into(), Some(texture));
+    }
+
+    pub fn graph_mut(&mut self) -> &mut RenderGraph<FrameData> {
+        &mut self.graph
+    }
+
+    pub fn backbuffer(&self) -> ResourceId {
+        self.backbuffer
+    }
+
+    /// The color target the built-in passes draw into. It is multisampled when
+    /// the sample count is above one and resolved into the backbuffer by the
+    /// last built-in pass.
+    pub fn color(&self) -> ResourceId {
+        self.color
+    }
+
+    pub fn depth(&self) -> ResourceId {
+        self.depth
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
     }
 
     pub fn get_size(&self) -> PhysicalSize<u32> {
         self.context.size
     }
+}
 
-    pub fn input(&mut self) -> &mut InputHandler {
-        &mut self.input_handler
+struct TilemapPass;
+
+impl RenderNode<FrameData> for TilemapPass {
+    fn run<'a>(
+        &'a self,
+        frame: &'a FrameData,
+        _resources: &'a GraphResources,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        frame.tilemaps.draw(
+            pass,
+            &frame.pipelines.tilemap,
+            &frame.camera_uniform,
+            |texture| frame.texture(texture),
+        );
     }
 }
 
+struct SpritePass;
+
+impl RenderNode<FrameData> for SpritePass {
+    fn run<'a>(
+        &'a self,
+        frame: &'a FrameData,
+        _resources: &'a GraphResources,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        let batcher = &frame.sprite_batcher;
+        if batcher.is_empty() {
+            return;
+        }
+        pass.set_bind_group(1, frame.camera_uniform.bind_group(), &[]);
+        pass.set_bind_group(2, frame.lights_storage.bind_group(), &[]);
+        pass.set_vertex_buffer(1, batcher.buffer().slice(..));
+
+        let mut blend = None;
+        let mut texture = None;
+        for batch in batcher.batches() {
+            if blend != Some(batch.blend) {
+                blend = Some(batch.blend);
+                pass.set_pipeline(frame.pipelines.sprite(batch.blend));
+            }
+            if texture != Some(batch.texture) {
+                texture = Some(batch.texture);
+                pass.set_bind_group(0, frame.texture(batch.texture).bind_group(), &[]);
+            }
+            pass.draw_quad_indexed(&frame.quad, batch.instances.clone());
+        }
+    }
+}
+
+fn screen_camera(context: &Context) -> ScreenCamera {
+    ScreenCamera {
+        width: context.config.width as f32,
+        height: context.config.height as f32,
+    }
+}
+
+/// Draws world-space text, then screen-space text over it.
+struct TextPass;
+
+impl RenderNode<FrameData> for TextPass {
+    fn run<'a>(
+        &'a self,
+        frame: &'a FrameData,
+        _resources: &'a GraphResources,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        let layers = [
+            (
+                &frame.text_batcher,
+                &frame.pipelines.text,
+                frame.camera_uniform.bind_group(),
+            ),
+            (
+                &frame.screen_text_batcher,
+                &frame.pipelines.screen_text,
+                frame.screen_uniform.bind_group(),
+            ),
+        ];
+        for (batcher, pipeline, camera) in layers {
+            if batcher.is_empty() {
+                continue;
+            }
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(1, camera, &[]);
+            pass.set_vertex_buffer(1, batcher.buffer().slice(..));
+
+            let mut texture = None;
+            for batch in batcher.batches() {
+                if texture != Some(batch.texture) {
+                    texture = Some(batch.texture);
+                    pass.set_bind_group(0, frame.texture(batch.texture).bind_group(), &[]);
+                }
+                pass.draw_quad_indexed(&frame.quad, batch.instances.clone());
+            }
+        }
+    }
+}
+
+struct LightDebugPass;
+
+impl RenderNode<FrameData> for LightDebugPass {
+    fn run<'a>(
+        &'a self,
+        frame: &'a FrameData,
+        _resources: &'a GraphResources,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        pass.set_pipeline(&frame.pipelines.light_debug);
+        pass.set_bind_group(0, frame.camera_uniform.bind_group(), &[]);
+        pass.set_bind_group(1, frame.lights_storage.bind_group(), &[]);
+        pass.draw_quad_indexed(&frame.quad, 0..frame.num_lights as _);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContextOptions {
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    pub force_fallback_adapter: bool,
+    pub present_mode: wgpu::PresentMode,
+}
+
+/// Honors the `WGPU_BACKEND` and `WGPU_POWER_PREF` environment variables.
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all),
+            power_preference: wgpu::util::power_preference_from_env().unwrap_or_default(),
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            force_fallback_adapter: false,
+            present_mode: wgpu::PresentMode::Fifo,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContextError {
+    #[error("no graphics adapter found for backends {backends:?} (fallback adapter: {force_fallback_adapter})")]
+    NoAdapter {
+        backends: wgpu::Backends,
+        force_fallback_adapter: bool,
+    },
+    #[error("adapter \"{adapter}\" does not support the required features {missing:?}")]
+    MissingFeatures {
+        adapter: String,
+        missing: wgpu::Features,
+    },
+    #[error(
+        "adapter \"{adapter}\" could not create a device with the requested features and limits"
+    )]
+    RequestDevice { adapter: String },
+    #[error("the window surface is not compatible with the adapter")]
+    IncompatibleSurface,
+}
+
+/// The present modes the surfaces of `backend` can support. wgpu does not
+/// expose the modes of a particular surface, and silently falls back to `Fifo`
+/// for unsupported ones, so this is checked up front to know the mode in use.
+/// Vulkan drivers differ, so any mode is attempted there.
+fn supported_present_modes(backend: wgpu::Backend) -> &'static [wgpu::PresentMode] {
+    use wgpu::PresentMode::*;
+    match backend {
+        wgpu::Backend::Vulkan => &[Fifo, Mailbox, Immediate],
+        wgpu::Backend::Metal | wgpu::Backend::Dx12 => &[Fifo, Immediate],
+        _ => &[Fifo],
+    }
+}
+
+fn supported_present_mode(
+    backend: wgpu::Backend,
+    present_mode: wgpu::PresentMode,
+) -> wgpu::PresentMode {
+    if supported_present_modes(backend).contains(&present_mode) {
+        return present_mode;
+    }
+    log::warn!(
+        "Present mode {:?} is not supported by the {:?} backend, using Fifo",
+        present_mode,
+        backend
+    );
+    wgpu::PresentMode::Fifo
+}
+
 pub struct Context {
-    surface: wgpu::Surface,
+    target: RenderTarget,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: PhysicalSize<u32>,
+    sample_count: u32,
+    backend: wgpu::Backend,
+}
+
+enum RenderTarget {
+    Surface(wgpu::Surface),
+    Offscreen(wgpu::Texture),
+}
+
+pub struct Frame {
+    surface_texture: Option<wgpu::SurfaceTexture>,
+    pub view: wgpu::TextureView,
+}
+
+impl Frame {
+    pub fn present(self) {
+        if let Some(surface_texture) = self.surface_texture {
+            surface_texture.present();
+        }
+    }
 }
 
 impl Context {
-    pub async fn new(window: &Window) -> Self {
+    pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    pub async fn new(window: &Window, options: &ContextOptions) -> Result<Self, ContextError> {
         let size = window.inner_size();
 
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let instance = wgpu::Instance::new(options.backends);
         let surface = unsafe { instance.create_surface(window) };
+        let adapter = Self::request_adapter(&instance, options, Some(&surface)).await?;
+        let (device, queue) = Self::request_device(&adapter, options).await?;
+        let backend = adapter.get_info().backend;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface
+                .get_preferred_format(&adapter)
+                .ok_or(ContextError::IncompatibleSurface)?,
+            width: size.width,
+            height: size.height,
+            present_mode: supported_present_mode(backend, options.present_mode),
+        };
+        surface.configure(&device, &config);
+
+        Ok(Self {
+            target: RenderTarget::Surface(surface),
+            device,
+            queue,
+            config,
+            size,
+            sample_count: 1,
+            backend,
+        })
+    }
+
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        options: &ContextOptions,
+    ) -> Result<Self, ContextError> {
+        let size = PhysicalSize::new(width, height);
+
+        let instance = wgpu::Instance::new(options.backends);
+        let adapter = Self::request_adapter(&instance, options, None).await?;
+        let (device, queue) = Self::request_device(&adapter, options).await?;
+        let backend = adapter.get_info().backend;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: Self::OFFSCREEN_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        let texture = Self::create_offscreen_texture(&device, &config);
+
+        Ok(Self {
+            target: RenderTarget::Offscreen(texture),
+            device,
+            queue,
+            config,
+            size,
+            sample_count: 1,
+            backend,
+        })
+    }
+
+    async fn request_adapter(
+        instance: &wgpu::Instance,
+        options: &ContextOptions,
+        compatible_surface: Option<&wgpu::Surface>,
+    ) -> Result<wgpu::Adapter, ContextError> {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                power_preference: options.power_preference,
+                compatible_surface,
+                force_fallback_adapter: options.force_fallback_adapter,
             })
             .await
-            .unwrap();
+            .ok_or(ContextError::NoAdapter {
+                backends: options.backends,
+                force_fallback_adapter: options.force_fallback_adapter,
+            })?;
+
+        let info = adapter.get_info();
+        log::info!(
+            "Using adapter \"{}\" ({:?}, {:?} backend, vendor {:#06x}, device {:#06x})",
+            info.name,
+            info.device_type,
+            info.backend,
+            info.vendor,
+            info.device
+        );
+
+        let missing = options.features - adapter.features();
+        if !missing.is_empty() {
+            return Err(ContextError::MissingFeatures {
+                adapter: info.name,
+                missing,
+            });
+        }
+
+        Ok(adapter)
+    }
 
-        let (device, queue) = adapter
+    async fn request_device(
+        adapter: &wgpu::Adapter,
+        options: &ContextOptions,
+    ) -> Result<(wgpu::Device, wgpu::Queue), ContextError> {
+        adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
+                    features: options.features,
+                    limits: options.limits.clone(),
                     label: None,
                 },
                 None,
             )
             .await
-            .unwrap();
+            .map_err(|_| ContextError::RequestDevice {
+                adapter: adapter.get_info().name,
+            })
+    }
 
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_preferred_format(&adapter).unwrap(),
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-        };
-        surface.configure(&device, &config);
+    fn create_offscreen_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+        })
+    }
 
-        Self {
-            surface,
-            device,
-            queue,
-            config,
-            size,
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.size = new_size;
+        self.config.width = new_size.width;
+        self.config.height = new_size.height;
+        match &mut self.target {
+            RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+            RenderTarget::Offscreen(texture) => {
+                *texture = Self::create_offscreen_texture(&self.device, &self.config)
+            }
         }
     }
 
+    pub fn current_frame(&self) -> Result<Frame, wgpu::SurfaceError> {
+        match &self.target {
+            RenderTarget::Surface(surface) => {
+                let surface_texture = surface.get_current_texture()?;
+                let view = surface_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                Ok(Frame {
+                    surface_texture: Some(surface_texture),
+                    view,
+                })
+            }
+            RenderTarget::Offscreen(texture) => Ok(Frame {
+                surface_texture: None,
+                view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            }),
+        }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        matches!(self.target, RenderTarget::Offscreen(_))
+    }
+
+    pub fn offscreen_texture(&self) -> Option<&wgpu::Texture> {
+        match &self.target {
+            RenderTarget::Surface(_) => None,
+            RenderTarget::Offscreen(texture) => Some(texture),
+        }
+    }
+
+    pub fn pipeline<'a>(&'a self, shader: impl Into<Cow<'a, str>>) -> PipelineBuilder<'a> {
+        PipelineBuilder::new(self, shader)
+    }
+
     pub fn create_render_pipeline<'a>(
-        &self,
+        &'a self,
         shader: Cow<'a, str>,
-        bind_group_layouts: &[&wgpu::BindGroupLayout],
-        vertex_layouts: &[wgpu::VertexBufferLayout],
+        bind_group_layouts: &[&'a wgpu::BindGroupLayout],
+        vertex_layouts: &[wgpu::VertexBufferLayout<'a>],
         depth_format: Option<wgpu::TextureFormat>,
     ) -> wgpu::RenderPipeline {
-        let shader = wgpu::ShaderModuleDescriptor {
-            label: None,
-            source: wgpu::ShaderSource::Wgsl(shader),
-        };
+        self.pipeline(shader)
+            .bind_group_layouts(bind_group_layouts)
+            .vertex_layouts(vertex_layouts)
+            .depth_format(depth_format)
+            .build()
+    }
 
-        let layout = self
-            .device
-            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts,
-                push_constant_ranges: &[],
-            });
+    #[inline]
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
 
-        let shader = self.device.create_shader_module(&shader);
+    /// Reconfigures the surface. Modes the backend does not support fall back
+    /// to `Fifo`, and `present_mode()` returns the mode actually applied.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        let present_mode = supported_present_mode(self.backend, present_mode);
+        if present_mode == self.config.present_mode {
+            return;
+        }
+        self.config.present_mode = present_mode;
+        if let RenderTarget::Surface(surface) = &self.target {
+            surface.configure(&self.device, &self.config);
+        }
+    }
 
-        self.device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Render Pipeline"),
-                layout: Some(&layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: vertex_layouts,
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[wgpu::ColorTargetState {
-                        format: self.config.format,
-                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    }],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false,
-                },
-                depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-                    format,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
-                    stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
-                }),
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None,
-            })
+    /// Only 1 and 4 samples are supported by every adapter, and wgpu 0.12
+    /// cannot query other counts, so 0 becomes 1 and every count above 1,
+    /// like 2 or 8, becomes 4, with a warning. Returns the sample count that
+    /// was applied.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+        let supported = if sample_count > 1 { 4 } else { 1 };
+        if supported != sample_count {
+            log::warn!(
+                "Unsupported sample count {}, using {} instead",
+                sample_count,
+                supported
+            );
+        }
+        self.sample_count = supported;
+        supported
+    }
+
+    #[inline]
+    pub fn config(&self) -> &wgpu::SurfaceConfiguration {
+        &self.config
     }
 
     #[inline]
@@ -367,9 +1113,19 @@ impl Context {
     }
 }
 
-pub const NUM_INSTANCES_PER_ROW: u32 = 10;
-pub const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(
-    NUM_INSTANCES_PER_ROW as f32 * 0.5 - 0.5,
-    NUM_INSTANCES_PER_ROW as f32 * 0.5 - 0.5,
-    0.0,
-);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_present_modes_fall_back_to_fifo() {
+        use wgpu::Backend::*;
+        use wgpu::PresentMode::*;
+        assert_eq!(supported_present_mode(Vulkan, Mailbox), Mailbox);
+        assert_eq!(supported_present_mode(Dx12, Immediate), Immediate);
+        assert_eq!(supported_present_mode(Dx12, Mailbox), Fifo);
+        assert_eq!(supported_present_mode(Metal, Mailbox), Fifo);
+        assert_eq!(supported_present_mode(Gl, Immediate), Fifo);
+        assert_eq!(supported_present_mode(Gl, Fifo), Fifo);
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use cgmath::{EuclideanSpace, Point3};
+use image::RgbaImage;
+
+use crate::animation;
+use crate::camera::Camera;
+use crate::ecs::{system, Access, Schedule, With, Without, World};
+use crate::light::Light;
+use crate::quad::Instance;
+use crate::sprite::{SceneSprite, SpriteBlend, TextureId};
+use crate::text;
+use crate::tilemap::{self, SceneTilemap};
+use crate::transform::{self, GlobalTransform};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CameraId(usize);
+
+/// Everything the game wants drawn this frame. The game mutates it and the
+/// renderer only reads it, once per frame.
+pub struct Scene {
+    cameras: Vec<Camera>,
+    active_camera: CameraId,
+    pub sprites: Vec<SceneSprite>,
+    pub tilemaps: Vec<SceneTilemap>,
+    /// Glyphs of world-space text.
+    pub text: Vec<SceneSprite>,
+    /// Glyphs of screen-space text, placed in pixels from the top left corner.
+    pub screen_text: Vec<SceneSprite>,
+    pub lights: Vec<Light>,
+    texture_paths: Vec<PathBuf>,
+    texture_ids: HashMap<PathBuf, TextureId>,
+    images: HashMap<PathBuf, SceneImage>,
+}
+
+/// An image built at runtime, drawn by sprites using the texture at its path.
+#[derive(Debug, Clone)]
+pub struct SceneImage {
+    /// Changes whenever the image does, so the renderer uploads it again.
+    pub revision: u64,
+    pub image: Rc<RgbaImage>,
+}
+
+impl Scene {
+    pub fn new(camera: Camera) -> Self {
+        Self {
+            cameras: vec![camera],
+            active_camera: CameraId(0),
+            sprites: Vec::new(),
+            tilemaps: Vec::new(),
+            text: Vec::new(),
+            screen_text: Vec::new(),
+            lights: Vec::new(),
+            texture_paths: Vec::new(),
+            texture_ids: HashMap::new(),
+            images: HashMap::new(),
+        }
+    }
+
+    /// The id sprites use to refer to the texture at `path`. The renderer
+    /// loads it the first time it draws the scene.
+    pub fn texture(&mut self, path: impl AsRef<Path>) -> TextureId {
+        let path = path.as_ref();
+        if let Some(&id) = self.texture_ids.get(path) {
+            return id;
+        }
+        self.texture_paths.push(path.to_owned());
+        let id = TextureId(self.texture_paths.len() as u32);
+        self.texture_ids.insert(path.to_owned(), id);
+        id
+    }
+
+    /// Every texture interned with `texture`.
+    pub fn textures(&self) -> impl Iterator<Item = (TextureId, &Path)> {
+        self.texture_paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| (TextureId(i as u32 + 1), path.as_path()))
+    }
+
+    /// Makes the texture at `path` show `image` instead of a file on disk.
+    pub fn set_image(&mut self, path: impl AsRef<Path>, image: SceneImage) -> TextureId {
+        let path = path.as_ref();
+        let id = self.texture(path);
+        self.images.insert(path.to_owned(), image);
+        id
+    }
+
+    pub fn images(&self) -> impl Iterator<Item = (&Path, &SceneImage)> {
+        self.images
+            .iter()
+            .map(|(path, image)| (path.as_path(), image))
+    }
+
+    pub fn add_camera(&mut self, camera: Camera) -> CameraId {
+        self.cameras.push(camera);
+        CameraId(self.cameras.len() - 1)
+    }
+
+    pub fn camera(&self, id: CameraId) -> &Camera {
+        &self.cameras[id.0]
+    }
+
+    pub fn camera_mut(&mut self, id: CameraId) -> &mut Camera {
+        &mut self.cameras[id.0]
+    }
+
+    pub fn active_camera_id(&self) -> CameraId {
+        self.active_camera
+    }
+
+    pub fn set_active_camera(&mut self, id: CameraId) {
+        assert!(id.0 < self.cameras.len(), "no camera with id {:?}", id);
+        self.active_camera = id;
+    }
+
+    /// The camera the scene is rendered from.
+    pub fn active_camera(&self) -> &Camera {
+        self.camera(self.active_camera)
+    }
+
+    pub fn active_camera_mut(&mut self) -> &mut Camera {
+        self.camera_mut(self.active_camera)
+    }
+
+    /// Updates the aspect ratio of every camera to match the render target.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        for camera in &mut self.cameras {
+            camera.resize(width, height);
+        }
+    }
+}
+
+/// Marks the entity whose `Camera` the scene is rendered from.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ActiveCamera;
+
+/// Identifies an entity to prefab overrides and in saved scenes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Name(pub String);
+
+/// How an entity's `Instance` is drawn. Instances without one use the default
+/// texture on layer 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sprite {
+    /// Asset path of the texture, or `None` for the renderer's default.
+    pub texture: Option<PathBuf>,
+    /// Lower layers are drawn first.
+    pub layer: i32,
+    pub blend: SpriteBlend,
+}
+
+impl Sprite {
+    pub fn new(texture: impl Into<PathBuf>) -> Self {
+        Self {
+            texture: Some(texture.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: SpriteBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+}
+
+/// Keeps an entity's `Instance` from being drawn.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hidden;
+
+/// The built-in systems that propagate transforms, advance sprite animations
+/// and then copy the `Instance`, `Sprite`, `Tilemap`, `Text`, `Light` and
+/// active `Camera` components of a world into its `Scene` resource before
+/// rendering. Components on entities with a `GlobalTransform` are placed
+/// relative to it.
+pub fn extract_schedule() -> Schedule {
+    Schedule::new()
+        .with_system(transform::propagate_transforms())
+        .with_system(animation::animate_sprites())
+        .with_system(system(
+            "extract_camera",
+            Access::new()
+                .read::<Camera>()
+                .read::<ActiveCamera>()
+                .read::<GlobalTransform>()
+                .write::<Scene>(),
+            extract_camera,
+        ))
+        .with_system(system(
+            "extract_sprites",
+            Access::new()
+                .read::<Instance>()
+                .read::<Sprite>()
+                .read::<Hidden>()
+                .read::<GlobalTransform>()
+                .write::<Scene>(),
+            extract_sprites,
+        ))
+        .with_system(tilemap::extract_tilemaps())
+        .with_system(text::extract_text())
+        .with_system(system(
+            "extract_lights",
+            Access::new()
+                .read::<Light>()
+                .read::<GlobalTransform>()
+                .write::<Scene>(),
+            extract_lights,
+        ))
+}
+
+/// Without a camera entity the scene keeps rendering from its own camera.
+fn extract_camera(world: &World) {
+    let mut cameras = world.query::<(&Camera, Option<&GlobalTransform>, With<ActiveCamera>)>();
+    if let Some((_, (camera, global, ()))) = cameras.iter().next() {
+        let mut scene = world.resource_mut::<Scene>();
+        let active = scene.active_camera_mut();
+        active.clone_from(camera);
+        if let Some(global) = global {
+            active.eye = global.transform_point(camera.eye);
+            active.target = global.transform_point(camera.target);
+        }
+    }
+}
+
+fn extract_sprites(world: &World) {
+    let mut scene = world.resource_mut::<Scene>();
+    let scene = &mut *scene;
+    scene.sprites.clear();
+    let mut sprites = world.query::<(
+        &Instance,
+        Option<&Sprite>,
+        Option<&GlobalTransform>,
+        Without<Hidden>,
+    )>();
+    for (_, (instance, sprite, global, ())) in sprites.iter() {
+        let mut scene_sprite = SceneSprite::from(match global {
+            Some(global) => Instance {
+                model: global.0 * instance.model,
+                ..*instance
+            },
+            None => *instance,
+        });
+        if let Some(sprite) = sprite {
+            if let Some(texture) = &sprite.texture {
+                scene_sprite.texture = scene.texture(texture);
+            }
+            scene_sprite.layer = sprite.layer;
+            scene_sprite.blend = sprite.blend;
+        }
+        scene.sprites.push(scene_sprite);
+    }
+}
+
+fn extract_lights(world: &World) {
+    let mut scene = world.resource_mut::<Scene>();
+    scene.lights.clear();
+    scene.lights.extend(
+        world
+            .query::<(&Light, Option<&GlobalTransform>)>()
+            .iter()
+            .map(|(_, (light, global))| {
+                let mut light = light.clone();
+                if let Some(global) = global {
+                    light.position = global
+                        .transform_point(Point3::from_vec(light.position))
+                        .to_vec();
+                }
+                light
+            }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Vector3};
+
+    use super::*;
+    use crate::time::FrameTime;
+    use crate::transform::Transform;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.insert_resource(FrameTime::default());
+        world.insert_resource(Scene::new(Camera::basic(4, 3, Deg(45.0))));
+        world
+    }
+
+    fn extract(world: &mut World) {
+        extract_schedule().run(world);
+    }
+
+    fn sprite_positions(world: &World) -> Vec<Vector3<f32>> {
+        let scene = world.resource::<Scene>();
+        scene
+            .sprites
+            .iter()
+            .map(|sprite| sprite.instance.model.w.truncate())
+            .collect()
+    }
+
+    #[test]
+    fn instances_are_placed_by_their_transform() {
+        let mut world = world();
+        world.spawn((Instance::from_position([1.0, 0.0, 0.0]),));
+        world.spawn((
+            Instance::from_position([1.0, 0.0, 0.0]),
+            Transform::from_translation([0.0, 2.0, 0.0]),
+        ));
+        extract(&mut world);
+
+        let mut positions = sprite_positions(&world);
+        positions.sort_by(|a, b| a.y.total_cmp(&b.y));
+        assert_eq!(
+            positions,
+            [Vector3::new(1.0, 0.0, 0.0), Vector3::new(1.0, 2.0, 0.0)]
+        );
+    }
+
+    #[test]
+    fn hidden_instances_are_skipped() {
+        let mut world = world();
+        let shown = world.spawn((Instance::from_position([1.0, 0.0, 0.0]),));
+        world.spawn((Instance::from_position([2.0, 0.0, 0.0]), Hidden));
+        extract(&mut world);
+        assert_eq!(sprite_positions(&world), [Vector3::new(1.0, 0.0, 0.0)]);
+
+        // Last frame's sprites do not linger once an entity is hidden
+        world.insert(shown, Hidden);
+        extract(&mut world);
+        assert!(world.resource::<Scene>().sprites.is_empty());
+    }
+
+    #[test]
+    fn sprites_set_texture_layer_and_blend() {
+        let mut world = world();
+        world.spawn((
+            Instance::default(),
+            Sprite::new("tree.png")
+                .with_layer(3)
+                .with_blend(SpriteBlend::Additive),
+        ));
+        world.spawn((Instance::default(),));
+        extract(&mut world);
+
+        let mut scene = world.resource_mut::<Scene>();
+        let tree = scene.texture("tree.png");
+        let mut sprites = scene
+            .sprites
+            .iter()
+            .map(|sprite| (sprite.texture, sprite.layer, sprite.blend))
+            .collect::<Vec<_>>();
+        sprites.sort();
+        assert_eq!(
+            sprites,
+            [
+                (TextureId::DEFAULT, 0, SpriteBlend::Alpha),
+                (tree, 3, SpriteBlend::Additive),
+            ]
+        );
+    }
+
+    #[test]
+    fn lights_are_placed_by_their_transform() {
+        let mut world = world();
+        world.spawn((Light::new([0.0, 0.0, 1.0], [1.0, 0.5, 0.0]),));
+        world.spawn((
+            Light::new([0.0, 0.0, 1.0], [0.0, 0.0, 1.0]),
+            Transform::from_translation([2.0, 0.0, 0.0]),
+        ));
+        extract(&mut world);
+
+        let scene = world.resource::<Scene>();
+        let mut lights = scene
+            .lights
+            .iter()
+            .map(|light| (light.position, light.color))
+            .collect::<Vec<_>>();
+        lights.sort_by(|a, b| a.0.x.total_cmp(&b.0.x));
+        assert_eq!(
+            lights,
+            [
+                (Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 0.5, 0.0)),
+                (Vector3::new(2.0, 0.0, 1.0), Vector3::new(0.0, 0.0, 1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_active_camera_entity_drives_the_scene_camera() {
+        let mut world = world();
+
+        // A camera without `ActiveCamera` is not rendered from
+        let mut inactive = Camera::new(2.0, Deg(90.0), 0.5, 10.0);
+        inactive.eye = (5.0, 5.0, 5.0).into();
+        world.spawn((inactive,));
+        extract(&mut world);
+        let scene = world.resource::<Scene>();
+        assert_eq!(scene.active_camera().eye, Point3::new(0.0, 0.0, 1.0));
+        assert_eq!(scene.active_camera().aspect, 4.0 / 3.0);
+        drop(scene);
+
+        world.spawn((
+            Camera::new(2.0, Deg(60.0), 0.5, 50.0),
+            ActiveCamera,
+            Transform::from_translation([3.0, 1.0, 0.0]),
+        ));
+        extract(&mut world);
+        let scene = world.resource::<Scene>();
+        let active = scene.active_camera();
+        assert_eq!(active.fovy, Deg(60.0).into());
+        assert_eq!(active.zfar, 50.0);
+        assert_eq!(active.eye, Point3::new(3.0, 1.0, 1.0));
+        assert_eq!(active.target, Point3::new(3.0, 1.0, 0.0));
+    }
+}
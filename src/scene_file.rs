@@ -0,0 +1,708 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use cgmath::{Deg, Euler, Quaternion};
+use serde::{Deserialize, Serialize};
+
+use crate::atlas::UvRect;
+use crate::camera::Camera;
+use crate::ecs::{Entity, World};
+use crate::light::Light;
+use crate::prefab::{self, PrefabInstance, PrefabLibrary};
+use crate::quad::Instance;
+use crate::scene::{ActiveCamera, Hidden, Name, Scene, Sprite};
+use crate::sprite::SpriteBlend;
+use crate::transform::{self, Parent, Transform};
+
+/// The version files are written with. Bump it when the format changes in a
+/// way `#[serde(default)]` cannot absorb, and add a step to `MIGRATIONS` that
+/// upgrades the previous version.
+pub const SCENE_FILE_VERSION: u32 = 1;
+
+/// `MIGRATIONS[i]` upgrades a file from version `i` to the next one. Version 0
+/// is a file without a `version`, e.g. one written by hand.
+const MIGRATIONS: [fn(&mut SceneFile); SCENE_FILE_VERSION as usize] = [from_unversioned];
+
+/// Unversioned files have the layout of version 1.
+fn from_unversioned(_file: &mut SceneFile) {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ron,
+    Json,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "ron" => Some(Format::Ron),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SceneFileError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to write {path}: {source}")]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{path} is not a .ron or .json file")]
+    UnknownFormat { path: PathBuf },
+    #[error("{0}")]
+    Parse(String),
+    #[error("failed to serialize the scene: {0}")]
+    Serialize(String),
+    #[error("scene file version {version} is newer than the supported {SCENE_FILE_VERSION}")]
+    NewerVersion { version: u32 },
+    #[error("prefab {path} contains itself")]
+    PrefabCycle { path: PathBuf },
+    #[error("in prefab {path}: {source}")]
+    Prefab {
+        path: PathBuf,
+        source: Box<SceneFileError>,
+    },
+    #[error("entity {entity} has parent {parent}, but the file has {count} entities")]
+    InvalidParent {
+        entity: usize,
+        parent: usize,
+        count: usize,
+    },
+    #[error("entity {entity} is its own ancestor")]
+    ParentCycle { entity: usize },
+}
+
+/// A saved world: every entity with a name, transform, sprite, light, camera
+/// or prefab. The entities a prefab instantiated are saved as the reference to
+/// the prefab, with its overrides, so edits to the prefab show up on load.
+/// Prefab files use the same format.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub entities: Vec<EntityData>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EntityData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Index of the parent in `SceneFile::entities`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transform: Option<TransformData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sprite: Option<SpriteData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light: Option<LightData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera: Option<CameraData>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub hidden: bool,
+    /// A prefab to instantiate as the entity's children.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefab: Option<PrefabInstance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformData {
+    pub translation: [f32; 3],
+    /// Euler angles in degrees.
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformData {
+    fn default() -> Self {
+        Self::from(&Transform::default())
+    }
+}
+
+/// An `Instance`, and how it is drawn if it has a `Sprite`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpriteData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<PathBuf>,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub layer: i32,
+    #[serde(skip_serializing_if = "is_default")]
+    pub blend: SpriteBlend,
+    /// The instance's model matrix, relative to the entity's transform.
+    #[serde(skip_serializing_if = "is_identity")]
+    pub model: [[f32; 4]; 4],
+    /// In degrees.
+    #[serde(skip_serializing_if = "is_default")]
+    pub rotation: f32,
+    #[serde(skip_serializing_if = "is_unit_scale")]
+    pub scale: [f32; 2],
+    #[serde(skip_serializing_if = "is_white")]
+    pub tint: [f32; 4],
+    #[serde(skip_serializing_if = "is_default")]
+    pub uv: UvRect,
+    #[serde(skip_serializing_if = "is_default")]
+    pub depth: f32,
+}
+
+impl SpriteData {
+    fn instance(&self) -> Instance {
+        Instance {
+            model: self.model.into(),
+            rotation: Deg(self.rotation).into(),
+            scale: self.scale.into(),
+            tint: self.tint,
+            uv: self.uv,
+            depth: self.depth,
+        }
+    }
+}
+
+impl Default for SpriteData {
+    fn default() -> Self {
+        Self {
+            texture: None,
+            layer: 0,
+            blend: SpriteBlend::default(),
+            model: Instance::default().model.into(),
+            rotation: 0.0,
+            scale: [1.0; 2],
+            tint: [1.0; 4],
+            uv: UvRect::FULL,
+            depth: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightData {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+/// Camera parameters. The aspect ratio follows the window, so it is not saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraData {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    /// Vertical field of view in degrees.
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    #[serde(skip_serializing_if = "is_false")]
+    pub active: bool,
+}
+
+impl Default for CameraData {
+    fn default() -> Self {
+        Self::from(&Camera::new(1.0, Deg(45.0), 0.1, 100.0))
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_zero(value: &i32) -> bool {
+    *value == 0
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn is_identity(model: &[[f32; 4]; 4]) -> bool {
+    *model == SpriteData::default().model
+}
+
+fn is_unit_scale(scale: &[f32; 2]) -> bool {
+    *scale == [1.0; 2]
+}
+
+fn is_white(tint: &[f32; 4]) -> bool {
+    *tint == [1.0; 4]
+}
+
+impl From<&Transform> for TransformData {
+    fn from(transform: &Transform) -> Self {
+        let Euler { x, y, z } = Euler::from(transform.rotation);
+        Self {
+            translation: transform.translation.into(),
+            rotation: [Deg::from(x).0, Deg::from(y).0, Deg::from(z).0],
+            scale: transform.scale.into(),
+        }
+    }
+}
+
+impl From<&TransformData> for Transform {
+    fn from(data: &TransformData) -> Self {
+        let [x, y, z] = data.rotation;
+        Self {
+            translation: data.translation.into(),
+            rotation: Quaternion::from(Euler::new(Deg(x), Deg(y), Deg(z))),
+            scale: data.scale.into(),
+        }
+    }
+}
+
+impl From<&Camera> for CameraData {
+    fn from(camera: &Camera) -> Self {
+        Self {
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            up: camera.up.into(),
+            fovy: Deg::from(camera.fovy).0,
+            znear: camera.znear,
+            zfar: camera.zfar,
+            active: false,
+        }
+    }
+}
+
+impl CameraData {
+    fn to_camera(&self, aspect: f32) -> Camera {
+        let mut camera = Camera::new(aspect, Deg(self.fovy), self.znear, self.zfar);
+        camera.eye = self.eye.into();
+        camera.target = self.target.into();
+        camera.up = self.up.into();
+        camera
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default)]
+    version: u32,
+}
+
+impl SceneFile {
+    /// Collects every entity in `world` that has something to save.
+    pub fn from_world(world: &World) -> Self {
+        let entities = world
+            .entities()
+            .filter(|&entity| {
+                (world.has::<Name>(entity)
+                    || world.has::<Transform>(entity)
+                    || world.has::<Instance>(entity)
+                    || world.has::<Light>(entity)
+                    || world.has::<Camera>(entity)
+                    || world.has::<PrefabInstance>(entity))
+                    && !prefab::is_instantiated(world, entity)
+            })
+            .collect::<Vec<_>>();
+        let indices = entities
+            .iter()
+            .enumerate()
+            .map(|(i, &entity)| (entity, i))
+            .collect::<HashMap<_, _>>();
+
+        let entities = entities
+            .iter()
+            .map(|&entity| EntityData {
+                name: world.get::<Name>(entity).map(|name| name.0.clone()),
+                parent: world
+                    .get::<Parent>(entity)
+                    .and_then(|parent| indices.get(&parent.0).copied()),
+                transform: world.get::<Transform>(entity).map(|t| (&*t).into()),
+                sprite: world.get::<Instance>(entity).map(|instance| {
+                    let sprite = world
+                        .get::<Sprite>(entity)
+                        .map_or_else(Sprite::default, |sprite| sprite.clone());
+                    SpriteData {
+                        texture: sprite.texture,
+                        layer: sprite.layer,
+                        blend: sprite.blend,
+                        model: instance.model.into(),
+                        rotation: Deg::from(instance.rotation).0,
+                        scale: instance.scale.into(),
+                        tint: instance.tint,
+                        uv: instance.uv,
+                        depth: instance.depth,
+                    }
+                }),
+                light: world.get::<Light>(entity).map(|light| LightData {
+                    position: light.position.into(),
+                    color: light.color.into(),
+                }),
+                camera: world.get::<Camera>(entity).map(|camera| CameraData {
+                    active: world.has::<ActiveCamera>(entity),
+                    ..(&*camera).into()
+                }),
+                hidden: world.has::<Hidden>(entity),
+                prefab: world
+                    .get::<PrefabInstance>(entity)
+                    .map(|prefab| prefab.clone()),
+            })
+            .collect();
+
+        Self {
+            version: SCENE_FILE_VERSION,
+            entities,
+        }
+    }
+
+    /// Spawns the saved entities into `world`, in file order. Cameras take
+    /// their aspect ratio from the world's `Scene` resource, if it has one.
+    pub fn spawn(&self, world: &mut World) -> Result<Vec<Entity>, SceneFileError> {
+        self.spawn_with(world, &mut PrefabLibrary::new())
+    }
+
+    /// Like `spawn`, loading referenced prefabs through `library`.
+    pub fn spawn_with(
+        &self,
+        world: &mut World,
+        library: &mut PrefabLibrary,
+    ) -> Result<Vec<Entity>, SceneFileError> {
+        let count = self.entities.len();
+        for (entity, data) in self.entities.iter().enumerate() {
+            match data.parent {
+                Some(parent) if parent >= count || parent == entity => {
+                    return Err(SceneFileError::InvalidParent {
+                        entity,
+                        parent,
+                        count,
+                    })
+                }
+                _ => {}
+            }
+        }
+        self.check_parent_cycles()?;
+
+        let aspect = world
+            .get_resource::<Scene>()
+            .map_or(1.0, |scene| scene.active_camera().aspect);
+        let entities = self
+            .entities
+            .iter()
+            .map(|data| {
+                let entity = world.spawn(());
+                if let Some(name) = &data.name {
+                    world.insert(entity, Name(name.clone()));
+                }
+                if let Some(transform) = &data.transform {
+                    world.insert(entity, Transform::from(transform));
+                }
+                if let Some(sprite) = &data.sprite {
+                    world.insert(entity, sprite.instance());
+                    let component = Sprite {
+                        texture: sprite.texture.clone(),
+                        layer: sprite.layer,
+                        blend: sprite.blend,
+                    };
+                    if component != Sprite::default() {
+                        world.insert(entity, component);
+                    }
+                }
+                if let Some(light) = &data.light {
+                    world.insert(entity, Light::new(light.position, light.color));
+                }
+                if let Some(camera) = &data.camera {
+                    world.insert(entity, camera.to_camera(aspect));
+                    if camera.active {
+                        world.insert(entity, ActiveCamera);
+                    }
+                }
+                if data.hidden {
+                    world.insert(entity, Hidden);
+                }
+                entity
+            })
+            .collect::<Vec<_>>();
+
+        for (data, &entity) in self.entities.iter().zip(&entities) {
+            if let Some(parent) = data.parent {
+                transform::set_parent(world, entity, entities[parent]);
+            }
+        }
+        for (data, &entity) in self.entities.iter().zip(&entities) {
+            if let Some(prefab) = &data.prefab {
+                if let Err(e) = library.instantiate_into(world, entity, prefab.clone()) {
+                    let roots = self.entities.iter().zip(&entities);
+                    for (_, &root) in roots.filter(|(data, _)| data.parent.is_none()) {
+                        transform::despawn_recursive(world, root);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(entities)
+    }
+
+    /// Walks every entity's parent chain, so a hand-edited cycle is an error
+    /// here instead of a panic in `transform::set_parent`. Expects every parent
+    /// to be in range.
+    fn check_parent_cycles(&self) -> Result<(), SceneFileError> {
+        let count = self.entities.len();
+        let mut checked = vec![false; count];
+        let mut on_path = vec![false; count];
+        let mut path = Vec::new();
+        for start in 0..count {
+            let mut current = Some(start);
+            while let Some(entity) = current.filter(|&entity| !checked[entity]) {
+                if on_path[entity] {
+                    return Err(SceneFileError::ParentCycle { entity });
+                }
+                on_path[entity] = true;
+                path.push(entity);
+                current = self.entities[entity].parent;
+            }
+            for entity in path.drain(..) {
+                on_path[entity] = false;
+                checked[entity] = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses a scene file of any version up to `SCENE_FILE_VERSION`,
+    /// upgrading it to the current version.
+    pub fn parse(source: &str, format: Format) -> Result<Self, SceneFileError> {
+        let version = deserialize::<Header>(source, format)?.version;
+        if version > SCENE_FILE_VERSION {
+            return Err(SceneFileError::NewerVersion { version });
+        }
+
+        let mut file: Self = deserialize(source, format)?;
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut file);
+        }
+        file.version = SCENE_FILE_VERSION;
+        Ok(file)
+    }
+
+    pub fn to_string(&self, format: Format) -> Result<String, SceneFileError> {
+        match format {
+            Format::Ron => {
+                let config = ron::ser::PrettyConfig::default()
+                    .extensions(ron::extensions::Extensions::IMPLICIT_SOME);
+                ron::ser::to_string_pretty(self, config)
+                    .map_err(|e| SceneFileError::Serialize(e.to_string()))
+            }
+            Format::Json => serde_json::to_string_pretty(self)
+                .map_err(|e| SceneFileError::Serialize(e.to_string())),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneFileError> {
+        let path = path.as_ref();
+        let format = format_of(path)?;
+        let source = std::fs::read_to_string(path).map_err(|source| SceneFileError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        Self::parse(&source, format).map_err(|e| match e {
+            SceneFileError::Parse(message) => {
+                SceneFileError::Parse(format!("{}:{}", path.display(), message))
+            }
+            e => e,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SceneFileError> {
+        let path = path.as_ref();
+        let source = self.to_string(format_of(path)?)?;
+        std::fs::write(path, source).map_err(|source| SceneFileError::Write {
+            path: path.to_owned(),
+            source,
+        })
+    }
+}
+
+/// Saves every entity in `world` with something to save to a `.ron` or
+/// `.json` file.
+pub fn save(world: &World, path: impl AsRef<Path>) -> Result<(), SceneFileError> {
+    SceneFile::from_world(world).save(path)
+}
+
+/// Spawns the entities saved in `path` into `world`.
+pub fn load(world: &mut World, path: impl AsRef<Path>) -> Result<Vec<Entity>, SceneFileError> {
+    SceneFile::load(path)?.spawn(world)
+}
+
+fn format_of(path: &Path) -> Result<Format, SceneFileError> {
+    Format::from_path(path).ok_or_else(|| SceneFileError::UnknownFormat {
+        path: path.to_owned(),
+    })
+}
+
+fn deserialize<T: serde::de::DeserializeOwned>(
+    source: &str,
+    format: Format,
+) -> Result<T, SceneFileError> {
+    match format {
+        // Hand-written files may leave out `Some(..)` without enabling the
+        // extension
+        Format::Ron => ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str(source)
+            .map_err(|e| SceneFileError::Parse(e.to_string())),
+        Format::Json => {
+            serde_json::from_str(source).map_err(|e| SceneFileError::Parse(e.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn sample_world() -> World {
+        let mut world = World::new();
+        world.spawn((
+            Name("camera".to_string()),
+            Camera::new(1.0, Deg(60.0), 0.5, 50.0),
+            ActiveCamera,
+        ));
+        let root = world.spawn((
+            Name("root".to_string()),
+            Transform::from_translation([1.0, 2.0, 3.0]).with_scale([2.0, 2.0, 1.0]),
+        ));
+        let child = world.spawn((
+            Transform::from_translation([0.5, 0.0, 0.0]),
+            Instance::from_position([0.0, 1.0, 0.0])
+                .with_scale([0.5, 2.0])
+                .with_tint([1.0, 0.5, 0.25, 1.0])
+                .with_uv(UvRect {
+                    min: [0.0, 0.5],
+                    max: [0.5, 1.0],
+                })
+                .with_depth(0.25),
+            Sprite {
+                texture: Some(PathBuf::from("textures/tree.png")),
+                layer: 2,
+                blend: SpriteBlend::Additive,
+            },
+            Light::new([0.0, 0.0, -0.1], [1.0, 0.5, 0.0]),
+            Hidden,
+        ));
+        transform::set_parent(&mut world, child, root);
+        world
+    }
+
+    #[test]
+    fn round_trips_through_both_formats() {
+        let file = SceneFile::from_world(&sample_world());
+        assert_eq!(file.version, SCENE_FILE_VERSION);
+        assert_eq!(file.entities.len(), 3);
+
+        for format in [Format::Ron, Format::Json] {
+            let source = file.to_string(format).unwrap();
+            let parsed = SceneFile::parse(&source, format).unwrap();
+            assert_eq!(parsed, file, "{:?} round trip", format);
+
+            let mut world = World::new();
+            let entities = parsed.spawn(&mut world).unwrap();
+            assert_eq!(entities.len(), 3);
+            assert_eq!(SceneFile::from_world(&world), file);
+
+            let child = entities[2];
+            assert_eq!(world.get::<Parent>(child).unwrap().0, entities[1]);
+            assert!(world.has::<Hidden>(child));
+            assert!(world.has::<ActiveCamera>(entities[0]));
+            assert_eq!(world.get::<Sprite>(child).unwrap().layer, 2);
+        }
+    }
+
+    #[test]
+    fn saves_and_loads_files() {
+        let dir = TempDir::new("scene_file");
+        let file = SceneFile::from_world(&sample_world());
+        for name in ["scene.ron", "scene.json"] {
+            let path = dir.join(name);
+            file.save(&path).unwrap();
+            assert_eq!(SceneFile::load(&path).unwrap(), file);
+        }
+        assert!(matches!(
+            file.save(dir.join("scene.txt")),
+            Err(SceneFileError::UnknownFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_components_are_errors() {
+        let source = "(version: 1, entities: [(name: \"a\", rigid_body: (mass: 1.0))])";
+        match SceneFile::parse(source, Format::Ron) {
+            Err(SceneFileError::Parse(message)) => assert!(message.contains("rigid_body")),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        let source = r#"{"version": 1, "entities": [{"lihgt": {}}]}"#;
+        match SceneFile::parse(source, Format::Json) {
+            Err(SceneFileError::Parse(message)) => assert!(message.contains("lihgt")),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let source = format!("(version: {}, entities: [])", SCENE_FILE_VERSION + 1);
+        assert!(matches!(
+            SceneFile::parse(&source, Format::Ron),
+            Err(SceneFileError::NewerVersion { version }) if version == SCENE_FILE_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn unversioned_files_are_upgraded() {
+        for source in [
+            "(entities: [(name: \"a\")])",
+            "(version: 0, entities: [(name: \"a\")])",
+        ] {
+            let file = SceneFile::parse(source, Format::Ron).unwrap();
+            assert_eq!(file.version, SCENE_FILE_VERSION);
+            assert_eq!(file.entities[0].name.as_deref(), Some("a"));
+        }
+        let file = SceneFile::parse(r#"{"entities": []}"#, Format::Json).unwrap();
+        assert_eq!(file.version, SCENE_FILE_VERSION);
+    }
+
+    #[test]
+    fn invalid_parents_are_errors() {
+        let source = "(version: 1, entities: [(parent: 1)])";
+        let file = SceneFile::parse(source, Format::Ron).unwrap();
+        assert!(matches!(
+            file.spawn(&mut World::new()),
+            Err(SceneFileError::InvalidParent {
+                entity: 0,
+                parent: 1,
+                count: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn parent_cycles_are_errors() {
+        let source = "(version: 1, entities: [(), (parent: 2), (parent: 3), (parent: 1)])";
+        let file = SceneFile::parse(source, Format::Ron).unwrap();
+        let mut world = World::new();
+        assert!(matches!(
+            file.spawn(&mut world),
+            Err(SceneFileError::ParentCycle { entity: 1 })
+        ));
+        assert!(world.is_empty());
+
+        let source = "(version: 1, entities: [(parent: 1), (parent: 0)])";
+        let file = SceneFile::parse(source, Format::Ron).unwrap();
+        assert!(matches!(
+            file.spawn(&mut World::new()),
+            Err(SceneFileError::ParentCycle { entity: 0 })
+        ));
+
+        let source = "(version: 1, entities: [(parent: 2), (parent: 2), (), (parent: 0)])";
+        let file = SceneFile::parse(source, Format::Ron).unwrap();
+        assert_eq!(file.spawn(&mut World::new()).unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,573 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::reflect::{LayoutError, ShaderLayout};
+use crate::renderer::Context;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShaderSource {
+    embedded: &'static str,
+    path: &'static str,
+}
+
+impl ShaderSource {
+    pub const fn new(embedded: &'static str, path: &'static str) -> Self {
+        Self { embedded, path }
+    }
+
+    pub fn path(&self) -> &Path {
+        Path::new(self.path)
+    }
+
+    pub fn embedded(&self) -> &'static str {
+        self.embedded
+    }
+
+    /// Debug builds read the shader from disk so edits are picked up without
+    /// recompiling. Release builds, or a missing file, use the embedded source.
+    pub fn load(&self) -> Cow<'static, str> {
+        if cfg!(debug_assertions) {
+            match std::fs::read_to_string(self.path) {
+                Ok(source) => return Cow::Owned(source),
+                Err(e) => log::warn!("Could not read shader {}: {}", self.path, e),
+            }
+        }
+        Cow::Borrowed(self.embedded)
+    }
+}
+
+#[macro_export]
+macro_rules! shader_source {
+    ($file:literal) => {
+        $crate::shader::ShaderSource::new(
+            include_str!($file),
+            concat!(env!("CARGO_MANIFEST_DIR"), "/src/", $file),
+        )
+    };
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ShaderError {
+    #[error("{file}:{line}:{column}: {message}")]
+    Compile {
+        file: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("{file}:{line}: {message}")]
+    Preprocess {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    #[error("{file}: {error}")]
+    Layout {
+        file: String,
+        #[source]
+        error: LayoutError,
+    },
+    #[error("{file}: {message}")]
+    Pipeline { file: String, message: String },
+}
+
+pub type Loader = fn(&ShaderSource) -> Cow<'static, str>;
+
+/// Expands `#include "name"`, `#define NAME [value]`, `#undef NAME`,
+/// `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` in WGSL sources.
+///
+/// Includes refer to registered modules by name and are only expanded once
+/// per shader, so shared structs can be included from several modules.
+/// Defines with a value replace matching identifiers in the following lines.
+pub struct Preprocessor {
+    modules: HashMap<&'static str, ShaderSource>,
+    defines: HashMap<String, String>,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Preprocessor {
+    /// Creates a preprocessor with the engine's `common.wgsl` module registered.
+    pub fn new() -> Self {
+        Self {
+            modules: HashMap::new(),
+            defines: HashMap::new(),
+        }
+        .module("common.wgsl", crate::shader_source!("common.wgsl"))
+    }
+
+    pub fn module(mut self, name: &'static str, source: ShaderSource) -> Self {
+        self.modules.insert(name, source);
+        self
+    }
+
+    pub fn define(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn modules(&self) -> impl Iterator<Item = &ShaderSource> {
+        self.modules.values()
+    }
+
+    pub fn process(
+        &self,
+        source: &ShaderSource,
+        load: Loader,
+    ) -> Result<ProcessedShader, ShaderError> {
+        let mut shader = ProcessedShader {
+            file: source.path,
+            code: String::new(),
+            lines: Vec::new(),
+        };
+        let mut defines = self.defines.clone();
+        let mut included = HashSet::from([source.path]);
+        self.expand(source, load, &mut defines, &mut included, &mut shader)?;
+        Ok(shader)
+    }
+
+    fn expand(
+        &self,
+        source: &ShaderSource,
+        load: Loader,
+        defines: &mut HashMap<String, String>,
+        included: &mut HashSet<&'static str>,
+        shader: &mut ProcessedShader,
+    ) -> Result<(), ShaderError> {
+        struct Condition {
+            active: bool,
+            has_else: bool,
+        }
+
+        let code = load(source);
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut line_number = 0;
+        for line in code.lines() {
+            line_number += 1;
+            let error = |message: String| ShaderError::Preprocess {
+                file: source.path.to_string(),
+                line: line_number,
+                message,
+            };
+            let active = conditions.iter().all(|c| c.active);
+
+            let directive = match line.trim_start().strip_prefix('#') {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        shader.push(&substitute(line, defines), source.path, line_number);
+                    }
+                    continue;
+                }
+            };
+            let (name, argument) = directive
+                .split_once(char::is_whitespace)
+                .unwrap_or((directive, ""));
+            let argument = argument.trim();
+
+            match name {
+                "ifdef" | "ifndef" => {
+                    if argument.is_empty() {
+                        return Err(error(format!("#{} requires a name", name)));
+                    }
+                    conditions.push(Condition {
+                        active: defines.contains_key(argument) == (name == "ifdef"),
+                        has_else: false,
+                    });
+                }
+                "else" => {
+                    let condition = conditions
+                        .last_mut()
+                        .ok_or_else(|| error("#else without #ifdef".to_string()))?;
+                    if condition.has_else {
+                        return Err(error("duplicate #else".to_string()));
+                    }
+                    condition.active = !condition.active;
+                    condition.has_else = true;
+                }
+                "endif" => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef".to_string()))?;
+                }
+                "define" | "undef" | "include" if !active => {}
+                "define" => {
+                    let (define, value) = argument
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((argument, ""));
+                    if define.is_empty() {
+                        return Err(error("#define requires a name".to_string()));
+                    }
+                    defines.insert(define.to_string(), value.trim().to_string());
+                }
+                "undef" => {
+                    defines.remove(argument);
+                }
+                "include" => {
+                    let module_name = argument
+                        .strip_prefix('"')
+                        .and_then(|a| a.strip_suffix('"'))
+                        .ok_or_else(|| {
+                            error(format!(
+                                "expected a quoted module name, found `{}`",
+                                argument
+                            ))
+                        })?;
+                    let module = self
+                        .modules
+                        .get(module_name)
+                        .ok_or_else(|| error(format!("unknown module `{}`", module_name)))?;
+                    if included.insert(module.path) {
+                        self.expand(module, load, defines, included, shader)?;
+                    }
+                }
+                _ => return Err(error(format!("unknown directive `#{}`", name))),
+            }
+        }
+
+        if !conditions.is_empty() {
+            return Err(ShaderError::Preprocess {
+                file: source.path.to_string(),
+                line: line_number,
+                message: "missing #endif".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn substitute<'a>(line: &'a str, defines: &HashMap<String, String>) -> Cow<'a, str> {
+    if defines.values().all(String::is_empty) {
+        return Cow::Borrowed(line);
+    }
+
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        let (before, from_start) = rest.split_at(start);
+        result.push_str(before);
+        let end = from_start
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(from_start.len());
+        let (identifier, after) = from_start.split_at(end);
+        match defines.get(identifier) {
+            Some(value) if !value.is_empty() => result.push_str(value),
+            _ => result.push_str(identifier),
+        }
+        rest = after;
+    }
+    result.push_str(rest);
+    Cow::Owned(result)
+}
+
+/// Preprocessed WGSL together with the file and line every output line came
+/// from, so errors can point at the original sources.
+pub struct ProcessedShader {
+    pub file: &'static str,
+    pub code: String,
+    lines: Vec<(&'static str, usize)>,
+}
+
+impl ProcessedShader {
+    fn push(&mut self, line: &str, file: &'static str, line_number: usize) {
+        self.code.push_str(line);
+        self.code.push('\n');
+        self.lines.push((file, line_number));
+    }
+
+    /// Maps a 1-based line of the processed code back to its source.
+    pub fn source_location(&self, line: usize) -> (&'static str, usize) {
+        self.lines
+            .get(line.saturating_sub(1))
+            .or_else(|| self.lines.last())
+            .copied()
+            .unwrap_or(("<empty>", line))
+    }
+
+    fn compile_error(&self, line: usize, column: usize, message: String) -> ShaderError {
+        let (file, line) = self.source_location(line);
+        ShaderError::Compile {
+            file: file.to_string(),
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+/// Parses and validates preprocessed WGSL with naga, reporting the first error
+/// with its location in the original source file.
+pub fn validate(
+    shader: &ProcessedShader,
+) -> Result<(naga::Module, naga::valid::ModuleInfo), ShaderError> {
+    let source = &shader.code;
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| {
+        let (line, column) = e.location(source);
+        shader.compile_error(line, column, e.to_string())
+    })?;
+
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|e| {
+        let offset = e
+            .spans()
+            .find_map(|(span, _)| span.to_range())
+            .map_or(0, |range| range.start);
+        let (line, column) = line_column(source, offset);
+        shader.compile_error(line, column, e.to_string())
+    })?;
+
+    Ok((module, info))
+}
+
+/// Validates preprocessed WGSL and reflects the bind groups it declares.
+pub fn reflect(shader: &ProcessedShader) -> Result<ShaderLayout, ShaderError> {
+    let (module, info) = validate(shader)?;
+    ShaderLayout::reflect(&module, &info).map_err(|error| ShaderError::Layout {
+        file: shader.file.to_string(),
+        error,
+    })
+}
+
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// Runs `create` inside a wgpu validation error scope, turning errors that
+/// would otherwise panic in the uncaptured error handler into a `ShaderError`.
+pub fn catch_pipeline_errors<T>(
+    context: &Context,
+    file: &Path,
+    create: impl FnOnce() -> T,
+) -> Result<T, ShaderError> {
+    context
+        .device()
+        .push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(context.device().pop_error_scope()) {
+        None => Ok(value),
+        Some(error) => Err(ShaderError::Pipeline {
+            file: file.display().to_string(),
+            message: error.to_string(),
+        }),
+    }
+}
+
+pub struct ShaderWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new<P: Into<PathBuf>>(paths: impl IntoIterator<Item = P>) -> Self {
+        let mut watcher = Self {
+            files: Vec::new(),
+            interval: Duration::from_millis(250),
+            last_poll: Instant::now(),
+        };
+        for path in paths {
+            watcher.watch(path);
+        }
+        watcher
+    }
+
+    pub fn watch(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        if self.files.iter().all(|(watched, _)| *watched != path) {
+            let modified = modified(&path);
+            self.files.push((path, modified));
+        }
+    }
+
+    /// Checks the watched files at most once per polling interval and returns
+    /// whether any of them changed since the last check.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < self.interval {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = false;
+        for (path, last_modified) in &mut self.files {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedded(source: &ShaderSource) -> Cow<'static, str> {
+        source.embedded().into()
+    }
+
+    fn process(preprocessor: &Preprocessor, code: &'static str) -> Result<String, ShaderError> {
+        preprocessor
+            .process(&ShaderSource::new(code, "main.wgsl"), embedded)
+            .map(|shader| shader.code)
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let code = "\
+#ifdef A
+a
+#ifdef B
+a_and_b
+#else
+a_not_b
+#endif
+#else
+not_a
+#ifdef B
+not_a_but_b
+#endif
+#endif
+#ifndef B
+not_b
+#endif
+";
+        let preprocessor = Preprocessor::new().define("A", "");
+        assert_eq!(process(&preprocessor, code).unwrap(), "a\na_not_b\nnot_b\n");
+        let preprocessor = Preprocessor::new().define("B", "");
+        assert_eq!(
+            process(&preprocessor, code).unwrap(),
+            "not_a\nnot_a_but_b\n"
+        );
+    }
+
+    #[test]
+    fn defines_replace_identifiers() {
+        let code = "#define SIZE 4u\nlet a = SIZE + SIZED;\n#undef SIZE\nlet b = SIZE;\n";
+        assert_eq!(
+            process(&Preprocessor::new(), code).unwrap(),
+            "let a = 4u + SIZED;\nlet b = SIZE;\n"
+        );
+    }
+
+    #[test]
+    fn include_cycles_expand_each_module_once() {
+        let preprocessor = Preprocessor::new()
+            .module(
+                "a.wgsl",
+                ShaderSource::new("#include \"b.wgsl\"\na\n", "a.wgsl"),
+            )
+            .module(
+                "b.wgsl",
+                ShaderSource::new("#include \"a.wgsl\"\nb\n", "b.wgsl"),
+            );
+        let code = "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain\n";
+        assert_eq!(process(&preprocessor, code).unwrap(), "b\na\nmain\n");
+    }
+
+    #[test]
+    fn unterminated_ifdef_is_an_error() {
+        let error = process(&Preprocessor::new(), "#ifdef A\nx\n").unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                ShaderError::Preprocess { file, line: 2, message }
+                    if file == "main.wgsl" && message == "missing #endif"
+            ),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn unbalanced_directives_are_errors() {
+        for code in [
+            "#endif\n",
+            "#else\n",
+            "#ifdef A\n#else\n#else\n#endif\n",
+            "#ifdef\n",
+        ] {
+            assert!(
+                matches!(
+                    process(&Preprocessor::new(), code),
+                    Err(ShaderError::Preprocess { .. })
+                ),
+                "{:?} was accepted",
+                code
+            );
+        }
+        assert!(matches!(
+            process(&Preprocessor::new(), "#include \"missing.wgsl\"\n"),
+            Err(ShaderError::Preprocess { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn compile_errors_point_at_the_original_file_and_line() {
+        let preprocessor = Preprocessor::new().module(
+            "helpers.wgsl",
+            ShaderSource::new(
+                "fn one() -> f32 {\n    return 1.0;\n}\n\nfn broken() -> f32 {\n    return 1.0 +;\n}\n",
+                "helpers.wgsl",
+            ),
+        );
+        let main = ShaderSource::new("// main\n#include \"helpers.wgsl\"\n", "main.wgsl");
+        let shader = preprocessor.process(&main, embedded).unwrap();
+        assert_eq!(shader.source_location(1), ("main.wgsl", 1));
+        assert_eq!(shader.source_location(2), ("helpers.wgsl", 1));
+
+        let error = validate(&shader).map(|_| ()).unwrap_err();
+        assert!(
+            matches!(&error, ShaderError::Compile { file, line: 6, .. } if file == "helpers.wgsl"),
+            "{}",
+            error
+        );
+
+        let main = ShaderSource::new(
+            "#include \"common.wgsl\"\n#ifdef UNUSED\n#endif\nfn main() {\n    let x: u32 = ;\n}\n",
+            "main.wgsl",
+        );
+        let preprocessor = Preprocessor::new();
+        let shader = preprocessor.process(&main, embedded).unwrap();
+        let error = validate(&shader).map(|_| ()).unwrap_err();
+        assert!(
+            matches!(&error, ShaderError::Compile { file, line: 5, .. } if file == "main.wgsl"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn engine_shaders_validate() {
+        let preprocessor = Preprocessor::new();
+        for source in [
+            crate::shader_source!("shader.wgsl"),
+            crate::shader_source!("tilemap.wgsl"),
+            crate::shader_source!("text.wgsl"),
+            crate::shader_source!("light.wgsl"),
+        ] {
+            let shader = preprocessor.process(&source, embedded).unwrap();
+            if let Err(error) = reflect(&shader) {
+                panic!("{}", error);
+            }
+        }
+    }
+}
@@ -1,32 +1,12 @@
-// Vertex shader
+#include "common.wgsl"
 
-struct InstanceInput {
-    [[location(5)]] model_matrix_0: vec4<f32>;
-    [[location(6)]] model_matrix_1: vec4<f32>;
-    [[location(7)]] model_matrix_2: vec4<f32>;
-    [[location(8)]] model_matrix_3: vec4<f32>;
-};
+// Vertex shader
 
-struct Light {
-    position: vec3<f32>;
-    color: vec3<f32>;
-};
-struct Lights {
-    data: array<Light>;
-};
 [[group(2), binding(0)]]
 var<storage, read> lights: Lights;
-struct LightCount {
-    data: u32;
-};
 [[group(2), binding(1)]]
 var<uniform> num_lights: LightCount;
 
-struct CameraUniform {
-    view_pos: vec4<f32>;
-    view_proj: mat4x4<f32>;
-};
-
 [[group(1), binding(0)]]
 var<uniform> camera: CameraUniform;
 
@@ -38,20 +18,16 @@ struct VertexInput {
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] tex_coords: vec2<f32>;
+    [[location(1)]] tint: vec4<f32>;
     [[location(2)]] world_position: vec3<f32>;
 };
 
 [[stage(vertex)]]
 fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    out.tex_coords = instance_tex_coords(instance, model.tex_coords);
+    out.tint = instance.tint;
+    let world_position = instance_world_position(instance, model.position);
     out.world_position = world_position.xyz;
     out.clip_position = camera.view_proj * world_position;
     return out;
@@ -66,7 +42,7 @@ var s_diffuse: sampler;
 
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
     var diffuse_color = vec3<f32>(0.1, 0.1, 0.1);
 
     //let num_lights: i32 = bitcast<i32>(num_lights.data);
@@ -0,0 +1,277 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::buffers::ToData;
+use crate::pipeline::BlendMode;
+use crate::quad::{Instance, InstanceRaw};
+use crate::renderer::Context;
+
+/// A texture known to a `Scene`. `TextureId::DEFAULT` is the renderer's
+/// default texture, the others are loaded from the path the scene interned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TextureId(pub(crate) u32);
+
+impl TextureId {
+    pub const DEFAULT: TextureId = TextureId(0);
+}
+
+/// Which sprite pipeline a sprite is drawn with.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum SpriteBlend {
+    #[default]
+    Alpha,
+    Additive,
+}
+
+impl SpriteBlend {
+    pub const ALL: [SpriteBlend; 2] = [SpriteBlend::Alpha, SpriteBlend::Additive];
+
+    pub fn mode(self) -> BlendMode {
+        match self {
+            SpriteBlend::Alpha => BlendMode::Alpha,
+            SpriteBlend::Additive => BlendMode::Additive,
+        }
+    }
+}
+
+/// A sprite for the renderer to draw this frame.
+#[derive(Debug, Clone, Copy)]
+pub struct SceneSprite {
+    pub instance: Instance,
+    pub texture: TextureId,
+    /// Lower layers are drawn first.
+    pub layer: i32,
+    pub blend: SpriteBlend,
+}
+
+impl From<Instance> for SceneSprite {
+    fn from(instance: Instance) -> Self {
+        Self {
+            instance,
+            texture: TextureId::DEFAULT,
+            layer: 0,
+            blend: SpriteBlend::default(),
+        }
+    }
+}
+
+/// Consecutive instances in the batcher's buffer sharing a pipeline and a
+/// texture, drawn with one call. Instances are drawn in order, so a batch may
+/// span several layers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpriteBatch {
+    pub blend: SpriteBlend,
+    pub texture: TextureId,
+    pub instances: Range<u32>,
+}
+
+/// Sorts sprites by layer, pipeline and texture, merges runs that can share a
+/// draw call into batches, and streams their instance data to the GPU once
+/// per frame. The instance buffer grows to the next power of two when a frame
+/// does not fit, and is reused otherwise.
+pub struct SpriteBatcher {
+    order: Vec<u32>,
+    instances: Vec<InstanceRaw>,
+    batches: Vec<SpriteBatch>,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl SpriteBatcher {
+    const INITIAL_CAPACITY: usize = 256;
+
+    pub fn new(context: &Context) -> Self {
+        Self {
+            order: Vec::new(),
+            instances: Vec::new(),
+            batches: Vec::new(),
+            buffer: Self::create_buffer(context, Self::INITIAL_CAPACITY),
+            capacity: Self::INITIAL_CAPACITY,
+        }
+    }
+
+    fn create_buffer(context: &Context, capacity: usize) -> wgpu::Buffer {
+        context.device().create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Batches `sprites` and uploads their instances. Sprites with equal keys
+    /// keep the order they were given in.
+    pub fn prepare(&mut self, context: &Context, sprites: &[SceneSprite]) {
+        batch_sprites(
+            sprites,
+            &mut self.order,
+            &mut self.instances,
+            &mut self.batches,
+        );
+
+        if self.instances.len() > self.capacity {
+            self.capacity = self.instances.len().next_power_of_two();
+            self.buffer = Self::create_buffer(context, self.capacity);
+        }
+        if !self.instances.is_empty() {
+            context
+                .queue()
+                .write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.instances));
+        }
+    }
+
+    pub fn batches(&self) -> &[SpriteBatch] {
+        &self.batches
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+}
+
+/// The CPU half of `SpriteBatcher::prepare`: sorts `sprites` into `order`,
+/// writes their instances in that order and merges consecutive instances
+/// sharing a blend mode and a texture. Clears the outputs first, so their
+/// allocations are reused from frame to frame.
+fn batch_sprites(
+    sprites: &[SceneSprite],
+    order: &mut Vec<u32>,
+    instances: &mut Vec<InstanceRaw>,
+    batches: &mut Vec<SpriteBatch>,
+) {
+    order.clear();
+    order.extend(0..sprites.len() as u32);
+    order.sort_by_key(|&i| {
+        let sprite = &sprites[i as usize];
+        (sprite.layer, sprite.blend, sprite.texture)
+    });
+
+    instances.clear();
+    batches.clear();
+    for &i in order.iter() {
+        let sprite = &sprites[i as usize];
+        let index = instances.len() as u32;
+        instances.push(sprite.instance.to_data());
+        match batches.last_mut() {
+            Some(batch) if batch.blend == sprite.blend && batch.texture == sprite.texture => {
+                batch.instances.end = index + 1
+            }
+            _ => batches.push(SpriteBatch {
+                blend: sprite.blend,
+                texture: sprite.texture,
+                instances: index..index + 1,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SpriteBlend::{Additive, Alpha};
+
+    fn sprite(x: f32, layer: i32, blend: SpriteBlend, texture: u32) -> SceneSprite {
+        SceneSprite {
+            instance: Instance::from_position([x, 0.0, 0.0]),
+            texture: TextureId(texture),
+            layer,
+            blend,
+        }
+    }
+
+    /// Batches `sprites` and returns the batches along with the x position
+    /// of every instance, in buffer order.
+    fn batch(sprites: &[SceneSprite]) -> (Vec<SpriteBatch>, Vec<f32>) {
+        let (mut order, mut instances, mut batches) = (Vec::new(), Vec::new(), Vec::new());
+        batch_sprites(sprites, &mut order, &mut instances, &mut batches);
+        let xs = instances
+            .iter()
+            .map(|&instance| bytemuck::cast::<InstanceRaw, [f32; 28]>(instance)[12])
+            .collect();
+        (batches, xs)
+    }
+
+    fn batch_of(blend: SpriteBlend, texture: u32, instances: Range<u32>) -> SpriteBatch {
+        SpriteBatch {
+            blend,
+            texture: TextureId(texture),
+            instances,
+        }
+    }
+
+    #[test]
+    fn sorts_by_layer_and_keeps_the_order_of_equal_keys() {
+        let (batches, xs) = batch(&[
+            sprite(0.0, 1, Alpha, 0),
+            sprite(1.0, 0, Alpha, 0),
+            sprite(2.0, 1, Alpha, 0),
+            sprite(3.0, -1, Alpha, 0),
+            sprite(4.0, 0, Alpha, 0),
+        ]);
+        assert_eq!(xs, [3.0, 1.0, 4.0, 0.0, 2.0]);
+        // One texture and blend mode, so the layers share a single draw call
+        assert_eq!(batches, [batch_of(Alpha, 0, 0..5)]);
+    }
+
+    #[test]
+    fn splits_on_blend_and_texture_changes() {
+        let (batches, xs) = batch(&[
+            sprite(0.0, 0, Additive, 0),
+            sprite(1.0, 0, Alpha, 1),
+            sprite(2.0, 0, Alpha, 0),
+            sprite(3.0, 0, Alpha, 1),
+            sprite(4.0, 1, Alpha, 1),
+            sprite(5.0, 1, Alpha, 0),
+        ]);
+        assert_eq!(xs, [2.0, 1.0, 3.0, 0.0, 5.0, 4.0]);
+        assert_eq!(
+            batches,
+            [
+                batch_of(Alpha, 0, 0..1),
+                batch_of(Alpha, 1, 1..3),
+                batch_of(Additive, 0, 3..4),
+                batch_of(Alpha, 0, 4..5),
+                batch_of(Alpha, 1, 5..6),
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_batches_across_layers() {
+        let (batches, _) = batch(&[
+            sprite(0.0, 0, Alpha, 0),
+            sprite(1.0, 0, Alpha, 1),
+            sprite(2.0, 1, Alpha, 1),
+            sprite(3.0, 2, Alpha, 1),
+        ]);
+        assert_eq!(
+            batches,
+            [batch_of(Alpha, 0, 0..1), batch_of(Alpha, 1, 1..4)]
+        );
+    }
+
+    #[test]
+    fn reuses_its_outputs() {
+        let (mut order, mut instances, mut batches) = (Vec::new(), Vec::new(), Vec::new());
+        let sprites = [sprite(0.0, 0, Alpha, 0), sprite(1.0, 0, Additive, 0)];
+        batch_sprites(&sprites, &mut order, &mut instances, &mut batches);
+        batch_sprites(&sprites[..1], &mut order, &mut instances, &mut batches);
+        assert_eq!(order, [0]);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(batches, [batch_of(Alpha, 0, 0..1)]);
+
+        batch_sprites(&[], &mut order, &mut instances, &mut batches);
+        assert!(order.is_empty() && instances.is_empty() && batches.is_empty());
+    }
+}
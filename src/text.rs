@@ -0,0 +1,1221 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use image::{Rgba, RgbaImage};
+
+use crate::atlas::{AtlasError, AtlasRect, AtlasRegion, Skyline, UvRect};
+use crate::ecs::{system, Access, FnSystem, Without, World};
+use crate::quad::Instance;
+use crate::scene::{Hidden, Scene, SceneImage};
+use crate::sprite::{SceneSprite, SpriteBlend};
+use crate::transform::GlobalTransform;
+
+/// The characters a TTF font rasterizes up front. Others are added the first
+/// time they are drawn.
+const PRINTABLE_ASCII: std::ops::RangeInclusive<char> = ' '..='~';
+
+/// Transparent pixels between the glyphs of a glyph atlas.
+const GLYPH_PADDING: u32 = 1;
+const GLYPH_ATLAS_SIZE: u32 = 128;
+const GLYPH_ATLAS_MAX_SIZE: u32 = 4096;
+
+static NEXT_GLYPH_ATLAS: AtomicU64 = AtomicU64::new(0);
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum FontError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid BMFont file {path}: {source}")]
+    Xml {
+        path: PathBuf,
+        source: roxmltree::Error,
+    },
+    #[error("{path}:{line}: {message}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    #[error("{path} is a binary BMFont file, export it as text or XML instead")]
+    Binary { path: PathBuf },
+    #[error("invalid font {path}: {message}")]
+    Ttf {
+        path: PathBuf,
+        message: &'static str,
+    },
+    #[error("failed to pack the glyphs of {path}: {source}")]
+    GlyphAtlas { path: PathBuf, source: AtlasError },
+}
+
+/// Where a character is in a font's pages and how it is placed, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glyph {
+    pub page: usize,
+    pub uv: UvRect,
+    pub size: [f32; 2],
+    /// From the pen position at the top of the line to the top left corner of
+    /// the glyph, with y pointing down.
+    pub offset: [f32; 2],
+    pub advance: f32,
+}
+
+/// Glyphs of a BMFont file, or of a TTF font rasterized into a glyph atlas.
+#[derive(Debug)]
+pub struct Font {
+    size: f32,
+    line_height: f32,
+    base: f32,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+    pages: Vec<PathBuf>,
+    rasterizer: Option<Rasterizer>,
+}
+
+/// Rasterizes the glyphs of a TTF font into a single page.
+struct Rasterizer {
+    path: PathBuf,
+    font: fontdue::Font,
+    atlas: GlyphAtlas,
+}
+
+/// Glyph bitmaps packed into one image as they are added. The image is only
+/// repacked when it has to grow.
+struct GlyphAtlas {
+    /// Every packed glyph and where it is in the image.
+    bitmaps: Vec<(char, RgbaImage, AtlasRect)>,
+    skyline: Skyline,
+    image: SceneImage,
+}
+
+impl GlyphAtlas {
+    fn new() -> Self {
+        Self {
+            bitmaps: Vec::new(),
+            skyline: Skyline::new(
+                GLYPH_ATLAS_SIZE - GLYPH_PADDING,
+                GLYPH_ATLAS_SIZE - GLYPH_PADDING,
+            ),
+            image: SceneImage {
+                revision: 0,
+                image: Rc::new(RgbaImage::new(GLYPH_ATLAS_SIZE, GLYPH_ATLAS_SIZE)),
+            },
+        }
+    }
+
+    fn pack(&mut self, c: char, bitmap: RgbaImage) -> Result<(), AtlasError> {
+        let (width, height) = bitmap.dimensions();
+        let position = self
+            .skyline
+            .insert(width + GLYPH_PADDING, height + GLYPH_PADDING);
+        let (x, y) = match position {
+            Some(position) => position,
+            None => {
+                let rect = AtlasRect {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                };
+                self.bitmaps.push((c, bitmap, rect));
+                return self.grow().inspect_err(|_| {
+                    self.bitmaps.pop();
+                });
+            }
+        };
+        let rect = AtlasRect {
+            x: x + GLYPH_PADDING,
+            y: y + GLYPH_PADDING,
+            width,
+            height,
+        };
+        let image = Rc::make_mut(&mut self.image.image);
+        image::imageops::replace(image, &bitmap, rect.x.into(), rect.y.into());
+        self.bitmaps.push((c, bitmap, rect));
+        Ok(())
+    }
+
+    /// Doubles the atlas until every glyph fits, tallest first.
+    fn grow(&mut self) -> Result<(), AtlasError> {
+        let mut order = (0..self.bitmaps.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.bitmaps[i].1.height()));
+        let (mut width, mut height) = self.image.image.dimensions();
+        loop {
+            if width <= height {
+                width *= 2;
+            } else {
+                height *= 2;
+            }
+            if width > GLYPH_ATLAS_MAX_SIZE || height > GLYPH_ATLAS_MAX_SIZE {
+                return Err(AtlasError::TooLarge {
+                    max_size: GLYPH_ATLAS_MAX_SIZE,
+                });
+            }
+            let mut skyline = Skyline::new(width - GLYPH_PADDING, height - GLYPH_PADDING);
+            let positions = order
+                .iter()
+                .map(|&i| {
+                    let (width, height) = self.bitmaps[i].1.dimensions();
+                    skyline.insert(width + GLYPH_PADDING, height + GLYPH_PADDING)
+                })
+                .collect::<Option<Vec<_>>>();
+            let positions = match positions {
+                Some(positions) => positions,
+                None => continue,
+            };
+
+            let mut image = RgbaImage::new(width, height);
+            for (&i, (x, y)) in order.iter().zip(positions) {
+                let (_, bitmap, rect) = &mut self.bitmaps[i];
+                rect.x = x + GLYPH_PADDING;
+                rect.y = y + GLYPH_PADDING;
+                image::imageops::replace(&mut image, bitmap, rect.x.into(), rect.y.into());
+            }
+            self.image.image = Rc::new(image);
+            self.skyline = skyline;
+            return Ok(());
+        }
+    }
+}
+
+impl fmt::Debug for Rasterizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rasterizer")
+            .field("path", &self.path)
+            .field("glyphs", &self.atlas.bitmaps.len())
+            .field("revision", &self.atlas.image.revision)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Font {
+    /// Loads a BMFont `.fnt` file in the text or XML format. Its pages are
+    /// loaded by the renderer like any other texture.
+    pub fn load_bmfont(path: impl AsRef<Path>) -> Result<Self, FontError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|source| FontError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        if bytes.starts_with(b"BMF") {
+            return Err(FontError::Binary {
+                path: path.to_owned(),
+            });
+        }
+        let text = String::from_utf8_lossy(&bytes);
+        let document;
+        let tags = if text.trim_start().starts_with('<') {
+            document = roxmltree::Document::parse(&text).map_err(|source| FontError::Xml {
+                path: path.to_owned(),
+                source,
+            })?;
+            xml_tags(&document)
+        } else {
+            text_tags(&text)
+        };
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        BmFont::default().build(path, dir, tags)
+    }
+
+    /// Loads a TTF or OTF font rasterized at `size` pixels. The printable
+    /// ASCII characters are rasterized right away.
+    pub fn from_ttf(path: impl AsRef<Path>, size: f32) -> Result<Self, FontError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|source| FontError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let settings = fontdue::FontSettings {
+            scale: size,
+            ..fontdue::FontSettings::default()
+        };
+        let font =
+            fontdue::Font::from_bytes(bytes, settings).map_err(|message| FontError::Ttf {
+                path: path.to_owned(),
+                message,
+            })?;
+        let metrics = font
+            .horizontal_line_metrics(size)
+            .ok_or_else(|| FontError::Ttf {
+                path: path.to_owned(),
+                message: "the font has no horizontal metrics",
+            })?;
+        let page = PathBuf::from(format!(
+            "{}@{}px#{}",
+            path.display(),
+            size,
+            NEXT_GLYPH_ATLAS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut font = Self {
+            size,
+            line_height: metrics.new_line_size,
+            base: metrics.ascent,
+            glyphs: HashMap::new(),
+            kerning: HashMap::new(),
+            pages: vec![page],
+            rasterizer: Some(Rasterizer {
+                path: path.to_owned(),
+                font,
+                atlas: GlyphAtlas::new(),
+            }),
+        };
+        font.add_glyphs(&PRINTABLE_ASCII.collect::<String>())?;
+        Ok(font)
+    }
+
+    /// The nominal size of the font in pixels.
+    pub fn size(&self) -> f32 {
+        self.size
+    }
+
+    /// The distance between the tops of two lines, in pixels.
+    pub fn line_height(&self) -> f32 {
+        self.line_height
+    }
+
+    /// The distance from the top of a line to its baseline, in pixels.
+    pub fn base(&self) -> f32 {
+        self.base
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c)
+    }
+
+    /// Added to the advance of `left` when `right` follows it, in pixels.
+    /// Negative amounts move them closer.
+    pub fn kerning(&self, left: char, right: char) -> f32 {
+        if let Some(&amount) = self.kerning.get(&(left, right)) {
+            return amount;
+        }
+        self.rasterizer
+            .as_ref()
+            .and_then(|rasterizer| rasterizer.font.horizontal_kern(left, right, self.size))
+            .unwrap_or(0.0)
+    }
+
+    /// The texture path of each page. TTF fonts have a single page, built at
+    /// runtime, whose path does not exist on disk.
+    pub fn pages(&self) -> &[PathBuf] {
+        &self.pages
+    }
+
+    /// The runtime page of a TTF font.
+    pub fn glyph_atlas(&self) -> Option<&SceneImage> {
+        self.rasterizer
+            .as_ref()
+            .map(|rasterizer| &rasterizer.atlas.image)
+    }
+
+    /// Rasterizes the characters of `text` a TTF font has not rasterized yet
+    /// into its glyph atlas. Returns whether any were added. BMFont fonts only
+    /// have the glyphs of their file.
+    ///
+    /// Glyphs that do not fit in the largest atlas keep their advance but are
+    /// not drawn.
+    pub fn add_glyphs(&mut self, text: &str) -> Result<bool, FontError> {
+        let rasterizer = match &mut self.rasterizer {
+            Some(rasterizer) => rasterizer,
+            None => return Ok(false),
+        };
+        let mut added = false;
+        let mut error = None;
+        for c in text.chars() {
+            if c.is_control() || self.glyphs.contains_key(&c) {
+                continue;
+            }
+            let (metrics, coverage) = rasterizer.font.rasterize(c, self.size);
+            let (width, height) = (metrics.width as u32, metrics.height as u32);
+            self.glyphs.insert(
+                c,
+                Glyph {
+                    page: 0,
+                    uv: UvRect::FULL,
+                    size: [width as f32, height as f32],
+                    offset: [
+                        metrics.xmin as f32,
+                        self.base - (metrics.ymin as f32 + height as f32),
+                    ],
+                    advance: metrics.advance_width,
+                },
+            );
+            added = true;
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let bitmap = RgbaImage::from_fn(width, height, |x, y| {
+                Rgba([255, 255, 255, coverage[(y * width + x) as usize]])
+            });
+            // A glyph that does not fit is drawn as nothing, the rest of the
+            // text still gets its glyphs and the first error is returned
+            if let Err(source) = rasterizer.atlas.pack(c, bitmap) {
+                if let Some(glyph) = self.glyphs.get_mut(&c) {
+                    glyph.size = [0.0, 0.0];
+                }
+                error.get_or_insert(FontError::GlyphAtlas {
+                    path: rasterizer.path.clone(),
+                    source,
+                });
+            }
+        }
+        if !added {
+            return Ok(false);
+        }
+
+        // Growing the atlas moves every glyph, so all UVs are updated
+        let (width, height) = rasterizer.atlas.image.image.dimensions();
+        for (c, _, rect) in &rasterizer.atlas.bitmaps {
+            if let Some(glyph) = self.glyphs.get_mut(c) {
+                glyph.uv = AtlasRegion::new(*rect, width, height).uv;
+            }
+        }
+        rasterizer.atlas.image.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
+        match error {
+            Some(error) => Err(error),
+            None => Ok(true),
+        }
+    }
+
+    /// Places the glyphs of `text`, in the units of its size.
+    pub fn layout(&self, text: &Text) -> TextLayout {
+        let scale = text.size / self.size;
+        let max_width = text.wrap_width.map(|width| width / scale);
+        let mut lines = vec![Vec::<LineGlyph>::new()];
+        // The index in the current line after which a wrap may move the rest
+        // of the line down.
+        let mut wrap_at = None;
+        let mut pen = 0.0;
+        let mut previous = None;
+        for span in &text.spans {
+            for c in span.text.chars() {
+                if c == '\n' {
+                    lines.push(Vec::new());
+                    wrap_at = None;
+                    pen = 0.0;
+                    previous = None;
+                    continue;
+                }
+                let glyph = match self.glyph_or_fallback(c) {
+                    Some(glyph) => glyph,
+                    None => continue,
+                };
+                let kerning = match previous {
+                    Some(previous) if text.kerning => self.kerning(previous, c),
+                    _ => 0.0,
+                };
+                let line = lines.last_mut().unwrap();
+                let overflows =
+                    max_width.is_some_and(|max_width| pen + kerning + glyph.advance > max_width);
+                if overflows && !c.is_whitespace() && line_width(line) > 0.0 {
+                    let split = wrap_at.filter(|&at| at < line.len()).unwrap_or(line.len());
+                    let mut moved = line.split_off(split);
+                    let start = moved.first().map_or(0.0, |glyph| glyph.x);
+                    for glyph in &mut moved {
+                        glyph.x -= start;
+                    }
+                    pen -= start;
+                    if moved.is_empty() {
+                        pen = 0.0;
+                    }
+                    lines.push(moved);
+                    wrap_at = None;
+                }
+                let x = if lines.last().unwrap().is_empty() {
+                    0.0
+                } else {
+                    pen + kerning
+                };
+                lines.last_mut().unwrap().push(LineGlyph {
+                    c,
+                    glyph: *glyph,
+                    x,
+                    color: span.color,
+                });
+                pen = x + glyph.advance;
+                previous = Some(c);
+                if c.is_whitespace() {
+                    wrap_at = Some(lines.last().unwrap().len());
+                }
+            }
+        }
+
+        let widths = lines
+            .iter()
+            .map(|line| line_width(line))
+            .collect::<Vec<_>>();
+        let width = max_width.unwrap_or_else(|| widths.iter().copied().fold(0.0, f32::max));
+        let line_advance = self.line_height * text.line_spacing;
+        let mut glyphs = Vec::new();
+        for (row, (line, line_width)) in lines.iter().zip(&widths).enumerate() {
+            let indent = match text.align {
+                TextAlign::Left => 0.0,
+                TextAlign::Center => (width - line_width) / 2.0,
+                TextAlign::Right => width - line_width,
+            };
+            let top = row as f32 * line_advance;
+            glyphs.extend(
+                line.iter()
+                    .filter(|glyph| glyph.glyph.size[0] > 0.0 && glyph.glyph.size[1] > 0.0)
+                    .map(|glyph| PositionedGlyph {
+                        c: glyph.c,
+                        page: glyph.glyph.page,
+                        uv: glyph.glyph.uv,
+                        position: [
+                            (indent + glyph.x + glyph.glyph.offset[0]) * scale,
+                            (top + glyph.glyph.offset[1]) * scale,
+                        ],
+                        size: [glyph.glyph.size[0] * scale, glyph.glyph.size[1] * scale],
+                        color: glyph.color,
+                    }),
+            );
+        }
+        let height = (lines.len() - 1) as f32 * line_advance + self.line_height;
+        TextLayout {
+            glyphs,
+            size: [width * scale, height * scale],
+        }
+    }
+
+    /// Characters without a glyph are drawn as `?`, or as a space if they are
+    /// whitespace.
+    fn glyph_or_fallback(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or_else(|| {
+            let fallback = if c.is_whitespace() { ' ' } else { '?' };
+            self.glyphs.get(&fallback)
+        })
+    }
+}
+
+struct LineGlyph {
+    c: char,
+    glyph: Glyph,
+    x: f32,
+    color: [f32; 4],
+}
+
+/// Up to the end of the last visible glyph, ignoring trailing whitespace.
+fn line_width(line: &[LineGlyph]) -> f32 {
+    line.iter()
+        .rev()
+        .find(|glyph| !glyph.c.is_whitespace())
+        .map_or(0.0, |glyph| glyph.x + glyph.glyph.advance)
+}
+
+/// One line of a BMFont file: the tag and its `key=value` pairs.
+struct Tag<'a> {
+    line: usize,
+    name: &'a str,
+    values: HashMap<&'a str, &'a str>,
+}
+
+fn text_tags(text: &str) -> Vec<Tag<'_>> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let text = text.trim();
+            let (name, mut rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+            if name.is_empty() {
+                return None;
+            }
+            let mut values = HashMap::new();
+            loop {
+                rest = rest.trim_start();
+                let (key, after) = match rest.split_once('=') {
+                    Some(pair) => pair,
+                    None => break,
+                };
+                let (value, after) = match after.strip_prefix('"') {
+                    Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+                    None => after.split_once(char::is_whitespace).unwrap_or((after, "")),
+                };
+                values.insert(key.trim(), value);
+                rest = after;
+            }
+            Some(Tag {
+                line: line + 1,
+                name,
+                values,
+            })
+        })
+        .collect()
+}
+
+fn xml_tags<'a>(document: &'a roxmltree::Document) -> Vec<Tag<'a>> {
+    document
+        .descendants()
+        .filter(|node| node.is_element())
+        .map(|node| Tag {
+            line: document.text_pos_at(node.range().start).row as usize,
+            name: node.tag_name().name(),
+            values: node
+                .attributes()
+                .iter()
+                .map(|attribute| (attribute.name(), attribute.value()))
+                .collect(),
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct BmFont {
+    size: Option<f32>,
+    line_height: Option<f32>,
+    base: Option<f32>,
+    scale: Option<[f32; 2]>,
+    pages: Vec<(usize, PathBuf)>,
+    chars: Vec<BmChar>,
+    kerning: HashMap<(char, char), f32>,
+}
+
+struct BmChar {
+    line: usize,
+    id: u32,
+    /// x, y, width and height on the page, in pixels.
+    rect: [f32; 4],
+    offset: [f32; 2],
+    advance: f32,
+    page: usize,
+}
+
+impl BmFont {
+    fn build(mut self, path: &Path, dir: &Path, tags: Vec<Tag>) -> Result<Font, FontError> {
+        for tag in &tags {
+            let number = |key: &str| -> Result<f32, FontError> {
+                let value = tag.values.get(key).ok_or_else(|| FontError::Parse {
+                    path: path.to_owned(),
+                    line: tag.line,
+                    message: format!("`{}` is missing {}", tag.name, key),
+                })?;
+                value.parse().map_err(|_| FontError::Parse {
+                    path: path.to_owned(),
+                    line: tag.line,
+                    message: format!("invalid {} `{}`", key, value),
+                })
+            };
+            match tag.name {
+                // The size is negative when it matches the height of the
+                // characters instead of the cells.
+                "info" => self.size = Some(number("size")?.abs()),
+                "common" => {
+                    self.line_height = Some(number("lineHeight")?);
+                    self.base = Some(number("base")?);
+                    self.scale = Some([number("scaleW")?, number("scaleH")?]);
+                }
+                "page" => {
+                    let file = tag.values.get("file").ok_or_else(|| FontError::Parse {
+                        path: path.to_owned(),
+                        line: tag.line,
+                        message: "`page` is missing file".to_owned(),
+                    })?;
+                    self.pages.push((number("id")? as usize, dir.join(file)));
+                }
+                "char" => self.chars.push(BmChar {
+                    line: tag.line,
+                    id: number("id")? as u32,
+                    rect: [
+                        number("x")?,
+                        number("y")?,
+                        number("width")?,
+                        number("height")?,
+                    ],
+                    offset: [number("xoffset")?, number("yoffset")?],
+                    advance: number("xadvance")?,
+                    page: match tag.values.get("page") {
+                        Some(_) => number("page")? as usize,
+                        None => 0,
+                    },
+                }),
+                "kerning" => {
+                    let (first, second) = (number("first")? as u32, number("second")? as u32);
+                    if let (Some(first), Some(second)) =
+                        (char::from_u32(first), char::from_u32(second))
+                    {
+                        self.kerning.insert((first, second), number("amount")?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let missing = |tag: &str| FontError::Parse {
+            path: path.to_owned(),
+            line: 0,
+            message: format!("there is no `{}` line", tag),
+        };
+        let line_height = self.line_height.ok_or_else(|| missing("common"))?;
+        let base = self.base.unwrap_or(line_height);
+        let [width, height] = self.scale.ok_or_else(|| missing("common"))?;
+        self.pages.sort_by_key(|(id, _)| *id);
+        let mut glyphs = HashMap::new();
+        for char in self.chars {
+            let page = self
+                .pages
+                .iter()
+                .position(|(id, _)| *id == char.page)
+                .ok_or_else(|| FontError::Parse {
+                    path: path.to_owned(),
+                    line: char.line,
+                    message: format!("there is no page {}", char.page),
+                })?;
+            // Some generators include an invalid id for the missing glyph.
+            let c = match char::from_u32(char.id) {
+                Some(c) => c,
+                None => continue,
+            };
+            let [x, y, w, h] = char.rect;
+            glyphs.insert(
+                c,
+                Glyph {
+                    page,
+                    uv: UvRect {
+                        min: [x / width, y / height],
+                        max: [(x + w) / width, (y + h) / height],
+                    },
+                    size: [w, h],
+                    offset: char.offset,
+                    advance: char.advance,
+                },
+            );
+        }
+        Ok(Font {
+            size: self.size.unwrap_or(line_height),
+            line_height,
+            base,
+            glyphs,
+            kerning: self.kerning,
+            pages: self.pages.into_iter().map(|(_, page)| page).collect(),
+            rasterizer: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontId(usize);
+
+/// The fonts `Text` components can use.
+#[derive(Debug, Default)]
+pub struct Fonts {
+    fonts: Vec<Font>,
+}
+
+impl Fonts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, font: Font) -> FontId {
+        self.fonts.push(font);
+        FontId(self.fonts.len() - 1)
+    }
+
+    pub fn get(&self, id: FontId) -> &Font {
+        &self.fonts[id.0]
+    }
+
+    pub fn get_mut(&mut self, id: FontId) -> &mut Font {
+        &mut self.fonts[id.0]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextSpace {
+    /// Placed by the entity's transform in world units, y pointing up.
+    #[default]
+    World,
+    /// Placed by the entity's transform in pixels from the top left corner of
+    /// the window, y pointing down, and drawn on top of everything else.
+    Screen,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSpan {
+    pub text: String,
+    pub color: [f32; 4],
+}
+
+/// Text drawn with one of the `Fonts`. The entity's position is the top left
+/// corner of the first line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text {
+    pub font: FontId,
+    pub spans: Vec<TextSpan>,
+    /// The font's size in world units, or pixels in screen space.
+    pub size: f32,
+    pub align: TextAlign,
+    /// Lines are wrapped between words to fit, and aligned within it.
+    pub wrap_width: Option<f32>,
+    /// Multiplies the font's line height.
+    pub line_spacing: f32,
+    pub kerning: bool,
+    pub space: TextSpace,
+    /// Lower layers are drawn first.
+    pub layer: i32,
+}
+
+impl Text {
+    /// White text, one world unit or pixel tall.
+    pub fn new(font: FontId, text: impl Into<String>) -> Self {
+        Self {
+            font,
+            spans: vec![TextSpan {
+                text: text.into(),
+                color: [1.0; 4],
+            }],
+            size: 1.0,
+            align: TextAlign::default(),
+            wrap_width: None,
+            line_spacing: 1.0,
+            kerning: true,
+            space: TextSpace::default(),
+            layer: 0,
+        }
+    }
+
+    /// Appends text in another color.
+    pub fn with_span(mut self, text: impl Into<String>, color: [f32; 4]) -> Self {
+        self.spans.push(TextSpan {
+            text: text.into(),
+            color,
+        });
+        self
+    }
+
+    /// Changes the color of every span added so far.
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        for span in &mut self.spans {
+            span.color = color;
+        }
+        self
+    }
+
+    pub fn with_size(mut self, size: f32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn with_wrap_width(mut self, width: f32) -> Self {
+        self.wrap_width = Some(width);
+        self
+    }
+
+    pub fn with_line_spacing(mut self, line_spacing: f32) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+
+    pub fn with_kerning(mut self, kerning: bool) -> Self {
+        self.kerning = kerning;
+        self
+    }
+
+    pub fn with_space(mut self, space: TextSpace) -> Self {
+        self.space = space;
+        self
+    }
+
+    pub fn with_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionedGlyph {
+    pub c: char,
+    pub page: usize,
+    pub uv: UvRect,
+    /// The top left corner, y pointing down from the top of the first line.
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub color: [f32; 4],
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextLayout {
+    pub glyphs: Vec<PositionedGlyph>,
+    /// The wrap width, or the widest line, by the height of the lines.
+    pub size: [f32; 2],
+}
+
+/// Lays out every visible `Text` into the scene's glyphs, rasterizing the
+/// characters TTF fonts are missing first.
+pub fn extract_text() -> FnSystem<impl FnMut(&World)> {
+    system(
+        "extract_text",
+        Access::new()
+            .read::<Text>()
+            .read::<GlobalTransform>()
+            .read::<Hidden>()
+            .write::<Fonts>()
+            .write::<Scene>(),
+        extract,
+    )
+}
+
+fn extract(world: &World) {
+    let mut scene = world.resource_mut::<Scene>();
+    let scene = &mut *scene;
+    scene.text.clear();
+    scene.screen_text.clear();
+    let mut fonts = match world.get_resource_mut::<Fonts>() {
+        Some(fonts) => fonts,
+        None => return,
+    };
+
+    // Each font rasterizes what it is missing at once, updating its atlas once
+    let mut characters = HashMap::<FontId, String>::new();
+    for (_, (text, ())) in world.query::<(&Text, Without<Hidden>)>().iter() {
+        if fonts.get(text.font).glyph_atlas().is_some() {
+            let characters = characters.entry(text.font).or_default();
+            for span in &text.spans {
+                characters.push_str(&span.text);
+            }
+        }
+    }
+    for (font, characters) in characters {
+        if let Err(e) = fonts.get_mut(font).add_glyphs(&characters) {
+            log::error!("Failed to rasterize glyphs: {}", e);
+        }
+    }
+
+    for (_, (text, global, ())) in world
+        .query::<(&Text, Option<&GlobalTransform>, Without<Hidden>)>()
+        .iter()
+    {
+        let font = fonts.get(text.font);
+        let layout = font.layout(text);
+        let textures = font
+            .pages()
+            .iter()
+            .map(|page| scene.texture(page))
+            .collect::<Vec<_>>();
+        let model = global.map_or_else(Matrix4::identity, |global| global.0);
+        // World space has y pointing up, so the layout is mirrored.
+        let (sprites, y) = match text.space {
+            TextSpace::World => (&mut scene.text, -1.0),
+            TextSpace::Screen => (&mut scene.screen_text, 1.0),
+        };
+        sprites.extend(layout.glyphs.iter().map(|glyph| {
+            let center = Vector3::new(
+                glyph.position[0] + glyph.size[0] / 2.0,
+                y * (glyph.position[1] + glyph.size[1] / 2.0),
+                0.0,
+            );
+            let instance = Instance {
+                model: model * Matrix4::from_translation(center),
+                ..Instance::default()
+            }
+            .with_scale([glyph.size[0], -y * glyph.size[1]])
+            .with_uv(glyph.uv)
+            .with_tint(glyph.color);
+            SceneSprite {
+                instance,
+                texture: textures[glyph.page],
+                layer: text.layer,
+                blend: SpriteBlend::Alpha,
+            }
+        }));
+    }
+    for font in &fonts.fonts {
+        if let Some(atlas) = font.glyph_atlas() {
+            scene.set_image(&font.pages[0], atlas.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// Every character is 10 pixels wide and advances 10, spaces are empty.
+    fn monospace() -> Font {
+        let glyph = |size: f32| Glyph {
+            page: 0,
+            uv: UvRect::FULL,
+            size: [size, 10.0],
+            offset: [0.0, 1.0],
+            advance: 10.0,
+        };
+        let mut glyphs = "abcd?"
+            .chars()
+            .map(|c| (c, glyph(10.0)))
+            .collect::<HashMap<_, _>>();
+        glyphs.insert(' ', glyph(0.0));
+        Font {
+            size: 12.0,
+            line_height: 12.0,
+            base: 10.0,
+            glyphs,
+            kerning: HashMap::from([(('a', 'b'), -3.0)]),
+            pages: vec![PathBuf::from("font.png")],
+            rasterizer: None,
+        }
+    }
+
+    fn text(text: &str) -> Text {
+        Text::new(FontId(0), text).with_size(12.0)
+    }
+
+    /// The characters of each line and the x of its first glyph.
+    fn lines(layout: &TextLayout) -> Vec<(String, f32)> {
+        let mut lines = Vec::<(f32, String, f32)>::new();
+        for glyph in &layout.glyphs {
+            match lines.last_mut() {
+                Some((y, line, _)) if *y == glyph.position[1] => line.push(glyph.c),
+                _ => lines.push((glyph.position[1], glyph.c.to_string(), glyph.position[0])),
+            }
+        }
+        lines.into_iter().map(|(_, line, x)| (line, x)).collect()
+    }
+
+    #[test]
+    fn wraps_between_words() {
+        let font = monospace();
+        let layout = font.layout(&text("ab cd abc").with_wrap_width(60.0));
+        assert_eq!(
+            lines(&layout),
+            [("abcd".to_string(), 0.0), ("abc".to_string(), 0.0)]
+        );
+        let second_line = &layout.glyphs[4];
+        assert_eq!(second_line.position, [0.0, 13.0]);
+        assert_eq!(layout.size, [60.0, 24.0]);
+    }
+
+    #[test]
+    fn breaks_words_longer_than_the_width() {
+        let font = monospace();
+        let layout = font.layout(&text("ab abcdabcd").with_wrap_width(40.0));
+        assert_eq!(
+            lines(&layout),
+            [
+                ("ab".to_string(), 0.0),
+                ("abcd".to_string(), 0.0),
+                ("abcd".to_string(), 0.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_whitespace_does_not_wrap() {
+        let font = monospace();
+        let layout = font.layout(&text("abcd    ").with_wrap_width(40.0));
+        assert_eq!(lines(&layout), [("abcd".to_string(), 0.0)]);
+        assert_eq!(layout.size, [40.0, 12.0]);
+
+        let layout = font.layout(&text("ab  \ncd"));
+        assert_eq!(layout.size, [20.0, 24.0]);
+    }
+
+    #[test]
+    fn aligns_within_the_wrap_width() {
+        let font = monospace();
+        let right = font.layout(
+            &text("cd\ncdcd ")
+                .with_wrap_width(100.0)
+                .with_align(TextAlign::Right),
+        );
+        assert_eq!(
+            lines(&right),
+            [("cd".to_string(), 80.0), ("cdcd".to_string(), 60.0)]
+        );
+        let center = font.layout(
+            &text("cd")
+                .with_wrap_width(100.0)
+                .with_align(TextAlign::Center),
+        );
+        assert_eq!(lines(&center), [("cd".to_string(), 40.0)]);
+
+        // Without a wrap width lines align to the widest
+        let center = font.layout(&text("cd\ncdcd").with_align(TextAlign::Center));
+        assert_eq!(
+            lines(&center),
+            [("cd".to_string(), 10.0), ("cdcd".to_string(), 0.0)]
+        );
+    }
+
+    #[test]
+    fn kerns_across_spans() {
+        let font = monospace();
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let layout = font.layout(&text("a").with_span("b", red));
+        assert_eq!(layout.glyphs[1].position[0], 7.0);
+        assert_eq!(layout.glyphs[0].color, [1.0; 4]);
+        assert_eq!(layout.glyphs[1].color, red);
+
+        let layout = font.layout(&text("a").with_span("b", red).with_kerning(false));
+        assert_eq!(layout.glyphs[1].position[0], 10.0);
+        assert_eq!(font.kerning('b', 'a'), 0.0);
+    }
+
+    #[test]
+    fn scales_to_the_text_size() {
+        let font = monospace();
+        let layout = font.layout(&text("a\u{263a}").with_size(24.0));
+        assert_eq!(layout.glyphs[1].c, '\u{263a}');
+        assert_eq!(layout.glyphs[1].position, [20.0, 2.0]);
+        assert_eq!(layout.glyphs[1].size, [20.0, 20.0]);
+        assert_eq!(layout.size, [40.0, 24.0]);
+    }
+
+    const BMFONT_TEXT: &str = r#"info face="Test Font" size=-16 bold=0
+common lineHeight=18 base=14 scaleW=64 scaleH=32 pages=2
+page id=3 file="second page.png"
+page id=1 file="first.png"
+chars count=2
+char id=65   x=0  y=0  width=8 height=12 xoffset=1 yoffset=2 xadvance=9 page=3
+char id=66   x=16 y=16 width=8 height=12 xoffset=0 yoffset=2 xadvance=9 page=1
+char id=-1   x=0  y=0  width=0 height=0  xoffset=0 yoffset=0 xadvance=0 page=1
+kernings count=1
+kerning first=65 second=66 amount=-2
+"#;
+
+    const BMFONT_XML: &str = r#"<?xml version="1.0"?>
+<font>
+  <info face="Test Font" size="-16"/>
+  <common lineHeight="18" base="14" scaleW="64" scaleH="32" pages="2"/>
+  <pages>
+    <page id="3" file="second page.png"/>
+    <page id="1" file="first.png"/>
+  </pages>
+  <chars count="2">
+    <char id="65" x="0" y="0" width="8" height="12" xoffset="1" yoffset="2" xadvance="9" page="3"/>
+    <char id="66" x="16" y="16" width="8" height="12" xoffset="0" yoffset="2" xadvance="9" page="1"/>
+  </chars>
+  <kernings count="1">
+    <kerning first="65" second="66" amount="-2"/>
+  </kernings>
+</font>
+"#;
+
+    fn load_fixture(name: &str, contents: &[u8]) -> Result<Font, FontError> {
+        let dir = TempDir::new("bmfont");
+        Font::load_bmfont(dir.write(name, contents))
+    }
+
+    fn assert_test_font(font: &Font) {
+        assert_eq!(font.size(), 16.0);
+        assert_eq!(font.line_height(), 18.0);
+        assert_eq!(font.base(), 14.0);
+        let pages = font.pages();
+        assert_eq!(pages.len(), 2);
+        assert!(pages[0].ends_with("first.png"));
+        assert!(pages[1].ends_with("second page.png"));
+
+        // Pages are looked up by id
+        let a = font.glyph('A').unwrap();
+        assert_eq!(a.page, 1);
+        assert_eq!(a.size, [8.0, 12.0]);
+        assert_eq!(a.offset, [1.0, 2.0]);
+        assert_eq!(a.advance, 9.0);
+        let b = font.glyph('B').unwrap();
+        assert_eq!(b.page, 0);
+        assert_eq!(
+            b.uv,
+            UvRect {
+                min: [0.25, 0.5],
+                max: [0.375, 0.875]
+            }
+        );
+        assert_eq!(font.kerning('A', 'B'), -2.0);
+    }
+
+    #[test]
+    fn loads_bmfont_text_files() {
+        let font = load_fixture("font.fnt", BMFONT_TEXT.as_bytes()).unwrap();
+        assert_test_font(&font);
+    }
+
+    #[test]
+    fn loads_bmfont_xml_files() {
+        let font = load_fixture("font.xml", BMFONT_XML.as_bytes()).unwrap();
+        assert_test_font(&font);
+    }
+
+    #[test]
+    fn rejects_invalid_bmfont_files() {
+        let missing_page = BMFONT_TEXT.replace("page=1\n", "page=2\n");
+        assert!(matches!(
+            load_fixture("page.fnt", missing_page.as_bytes()),
+            Err(FontError::Parse { line: 7, message, .. }) if message == "there is no page 2"
+        ));
+
+        let bad_number = BMFONT_TEXT.replace("x=16", "x=sixteen");
+        assert!(matches!(
+            load_fixture("number.fnt", bad_number.as_bytes()),
+            Err(FontError::Parse { line: 7, .. })
+        ));
+
+        let no_common = BMFONT_TEXT.replace("common", "uncommon");
+        assert!(matches!(
+            load_fixture("common.fnt", no_common.as_bytes()),
+            Err(FontError::Parse { message, .. }) if message.contains("`common`")
+        ));
+
+        assert!(matches!(
+            load_fixture("binary.fnt", b"BMF\x03"),
+            Err(FontError::Binary { .. })
+        ));
+    }
+
+    fn bitmap(size: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(size, size, Rgba([value, 0, 0, 255]))
+    }
+
+    #[test]
+    fn glyph_atlas_packs_incrementally() {
+        let mut atlas = GlyphAtlas::new();
+        atlas.pack('a', bitmap(20, 1)).unwrap();
+        let first = atlas.image.image.clone();
+        atlas.pack('b', bitmap(20, 2)).unwrap();
+
+        // The first glyph stays put, and the image is written in place
+        assert_eq!(atlas.bitmaps[0].2.x, GLYPH_PADDING);
+        assert_eq!(atlas.image.image.dimensions(), (128, 128));
+        let (_, _, b) = atlas.bitmaps[1];
+        assert_eq!(atlas.image.image.get_pixel(b.x, b.y)[0], 2);
+        assert_eq!(first.get_pixel(b.x, b.y)[0], 0);
+    }
+
+    #[test]
+    fn glyph_atlas_grows_when_full() {
+        let mut atlas = GlyphAtlas::new();
+        for i in 0..20 {
+            atlas.pack(char::from(b'a' + i), bitmap(30, i + 1)).unwrap();
+        }
+        let (width, height) = atlas.image.image.dimensions();
+        assert!(width > 128 || height > 128);
+        for (i, (_, _, rect)) in atlas.bitmaps.iter().enumerate() {
+            let value = atlas.image.image.get_pixel(rect.x, rect.y)[0];
+            assert_eq!(value, i as u8 + 1);
+            let (right, bottom) = (rect.x + rect.width - 1, rect.y + rect.height - 1);
+            assert_eq!(atlas.image.image.get_pixel(right, bottom)[0], i as u8 + 1);
+        }
+
+        assert!(matches!(
+            atlas.pack('z', bitmap(GLYPH_ATLAS_MAX_SIZE, 0)),
+            Err(AtlasError::TooLarge { .. })
+        ));
+        assert_eq!(atlas.bitmaps.len(), 20);
+    }
+}
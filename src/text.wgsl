@@ -0,0 +1,38 @@
+#include "common.wgsl"
+
+// Vertex shader
+
+[[group(1), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] tex_coords: vec2<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+    [[location(1)]] tint: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = instance_tex_coords(instance, model.tex_coords);
+    out.tint = instance.tint;
+    out.clip_position = camera.view_proj * instance_world_position(instance, model.position);
+    return out;
+}
+
+// Fragment shader
+
+[[group(0), binding(0)]]
+var t_font: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_font: sampler;
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return textureSample(t_font, s_font, in.tex_coords) * in.tint;
+}
@@ -0,0 +1,1197 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use cgmath::Rad;
+use roxmltree::Node;
+use serde::Deserialize;
+
+use crate::tilemap::{Tile, TileAnimationFrame, TileFlip, TileLayer, Tilemap, Tileset};
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+/// The flip flags, plus the hexagonal rotation flag.
+const GID_FLAGS: u32 = 0xf000_0000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TiledError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid TMX file {path}: {source}")]
+    Xml {
+        path: PathBuf,
+        source: roxmltree::Error,
+    },
+    #[error("invalid Tiled JSON file {path}: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("failed to read the size of {path}: {source}")]
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("invalid Tiled map {path}: {message}")]
+    Invalid { path: PathBuf, message: String },
+    #[error("{path} uses {feature}, which is not supported")]
+    Unsupported { path: PathBuf, feature: String },
+}
+
+fn invalid(path: &Path, message: impl Into<String>) -> TiledError {
+    TiledError::Invalid {
+        path: path.to_owned(),
+        message: message.into(),
+    }
+}
+
+fn unsupported(path: &Path, feature: impl Into<String>) -> TiledError {
+    TiledError::Unsupported {
+        path: path.to_owned(),
+        feature: feature.into(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    /// Strings, and every other property type.
+    String(String),
+}
+
+pub type Properties = HashMap<String, PropertyValue>;
+
+/// A map loaded from Tiled. Image layers are skipped and group layers are
+/// flattened into their children.
+#[derive(Debug)]
+pub struct TiledMap {
+    pub tilemap: Tilemap,
+    pub object_layers: Vec<ObjectLayer>,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f32,
+    /// In world units, like the offsets of tile layers.
+    pub offset: [f32; 2],
+    pub objects: Vec<TiledObject>,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectShape {
+    Rectangle,
+    Ellipse,
+    Point,
+    /// Points relative to the object's position.
+    Polygon(Vec<[f32; 2]>),
+    Polyline(Vec<[f32; 2]>),
+    Text(String),
+}
+
+/// An object from an object layer, measured in world units where a map cell
+/// is one unit and y points up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TiledObject {
+    pub id: u32,
+    pub name: String,
+    pub class: String,
+    /// Relative to the layer's offset. This is the top left corner of shapes,
+    /// but the bottom left corner of tile objects.
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    /// Counter-clockwise.
+    pub rotation: Rad<f32>,
+    pub tile: Option<Tile>,
+    pub visible: bool,
+    pub shape: ObjectShape,
+    pub properties: Properties,
+}
+
+/// Loads a `.tmx` or `.tmj` map along with its tilesets. Only orthogonal,
+/// finite maps are supported.
+pub fn load(path: impl AsRef<Path>) -> Result<TiledMap, TiledError> {
+    let path = path.as_ref();
+    let text = read(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let (map, tilesets) = match extension(path).as_str() {
+        "tmx" | "xml" => xml_map(path, dir, &text)?,
+        "tmj" | "json" => json_map(path, dir, &text)?,
+        other => return Err(unsupported(path, format!("the `.{}` extension", other))),
+    };
+    MapBuilder::build(path, map, tilesets)
+}
+
+fn read(path: &Path) -> Result<String, TiledError> {
+    std::fs::read_to_string(path).map_err(|source| TiledError::Read {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn orthogonal() -> String {
+    "orthogonal".to_owned()
+}
+
+/// The parts of a map shared by both formats, laid out like the JSON format.
+#[derive(Deserialize)]
+struct RawMap {
+    #[serde(default = "orthogonal")]
+    orientation: String,
+    #[serde(default)]
+    infinite: bool,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    layers: Vec<RawLayer>,
+    #[serde(default)]
+    properties: Vec<RawProperty>,
+}
+
+#[derive(Deserialize)]
+struct RawTilesetRef {
+    firstgid: u32,
+    source: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawTileset {
+    #[serde(default)]
+    name: String,
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: u32,
+    #[serde(default)]
+    imageheight: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    tiles: Vec<RawTile>,
+}
+
+#[derive(Deserialize)]
+struct RawTile {
+    id: u32,
+    #[serde(default)]
+    animation: Vec<RawFrame>,
+}
+
+#[derive(Deserialize)]
+struct RawFrame {
+    tileid: u32,
+    /// In milliseconds.
+    duration: u32,
+}
+
+/// A tileset and the directory its image path is relative to.
+struct TilesetEntry {
+    first_gid: u32,
+    tileset: RawTileset,
+    dir: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct RawLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default = "one")]
+    opacity: f32,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    data: Option<RawData>,
+    #[serde(default)]
+    encoding: String,
+    #[serde(default)]
+    compression: String,
+    #[serde(default)]
+    objects: Vec<RawObject>,
+    #[serde(default)]
+    layers: Vec<RawLayer>,
+    #[serde(default)]
+    properties: Vec<RawProperty>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawData {
+    Gids(Vec<u32>),
+    Encoded(String),
+}
+
+#[derive(Deserialize)]
+struct RawObject {
+    #[serde(default)]
+    id: u32,
+    #[serde(default)]
+    name: String,
+    /// The class before Tiled 1.9.
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    #[serde(default)]
+    x: f32,
+    #[serde(default)]
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    /// In degrees, clockwise.
+    #[serde(default)]
+    rotation: f32,
+    gid: Option<u32>,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    point: bool,
+    polygon: Option<Vec<RawPoint>>,
+    polyline: Option<Vec<RawPoint>>,
+    text: Option<RawText>,
+    #[serde(default)]
+    properties: Vec<RawProperty>,
+}
+
+#[derive(Deserialize)]
+struct RawPoint {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Deserialize)]
+struct RawText {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct RawProperty {
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+fn json<T: serde::de::DeserializeOwned>(
+    path: &Path,
+    value: serde_json::Value,
+) -> Result<T, TiledError> {
+    serde_json::from_value(value).map_err(|source| TiledError::Json {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+fn json_map(
+    path: &Path,
+    dir: &Path,
+    text: &str,
+) -> Result<(RawMap, Vec<TilesetEntry>), TiledError> {
+    let value: serde_json::Value =
+        serde_json::from_str(text).map_err(|source| TiledError::Json {
+            path: path.to_owned(),
+            source,
+        })?;
+    let tilesets = value
+        .get("tilesets")
+        .and_then(|tilesets| tilesets.as_array())
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|tileset| {
+            let reference: RawTilesetRef = json(path, tileset.clone())?;
+            match reference.source {
+                Some(source) => external_tileset(reference.firstgid, &dir.join(source)),
+                None => Ok(TilesetEntry {
+                    first_gid: reference.firstgid,
+                    tileset: json(path, tileset)?,
+                    dir: dir.to_owned(),
+                }),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((json(path, value)?, tilesets))
+}
+
+/// Loads a `.tsx` or `.tsj` tileset.
+fn external_tileset(first_gid: u32, path: &Path) -> Result<TilesetEntry, TiledError> {
+    let text = read(path)?;
+    let tileset = match extension(path).as_str() {
+        "tsx" | "xml" => {
+            let document = xml_document(path, &text)?;
+            xml_tileset(path, document.root_element())?
+        }
+        _ => serde_json::from_str(&text).map_err(|source| TiledError::Json {
+            path: path.to_owned(),
+            source,
+        })?,
+    };
+    Ok(TilesetEntry {
+        first_gid,
+        tileset,
+        dir: path.parent().unwrap_or_else(|| Path::new("")).to_owned(),
+    })
+}
+
+fn xml_document<'a>(path: &Path, text: &'a str) -> Result<roxmltree::Document<'a>, TiledError> {
+    roxmltree::Document::parse(text).map_err(|source| TiledError::Xml {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+fn attribute<T: FromStr>(path: &Path, node: Node, name: &str) -> Result<Option<T>, TiledError> {
+    node.attribute(name)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                invalid(
+                    path,
+                    format!(
+                        "<{}> has an invalid {} `{}`",
+                        node.tag_name().name(),
+                        name,
+                        value
+                    ),
+                )
+            })
+        })
+        .transpose()
+}
+
+fn required<T: FromStr>(path: &Path, node: Node, name: &str) -> Result<T, TiledError> {
+    attribute(path, node, name)?.ok_or_else(|| {
+        invalid(
+            path,
+            format!("<{}> is missing {}", node.tag_name().name(), name),
+        )
+    })
+}
+
+/// TMX writes booleans as 0 and 1.
+fn flag(path: &Path, node: Node, name: &str, default: bool) -> Result<bool, TiledError> {
+    Ok(attribute::<u8>(path, node, name)?.map_or(default, |value| value != 0))
+}
+
+fn string(node: Node, name: &str) -> String {
+    node.attribute(name).unwrap_or_default().to_owned()
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn xml_map(path: &Path, dir: &Path, text: &str) -> Result<(RawMap, Vec<TilesetEntry>), TiledError> {
+    let document = xml_document(path, text)?;
+    let root = document.root_element();
+    if !root.has_tag_name("map") {
+        return Err(invalid(path, "the root element is not <map>"));
+    }
+    let mut tilesets = Vec::new();
+    for node in root.children().filter(|node| node.has_tag_name("tileset")) {
+        let first_gid = required(path, node, "firstgid")?;
+        tilesets.push(match node.attribute("source") {
+            Some(source) => external_tileset(first_gid, &dir.join(source))?,
+            None => TilesetEntry {
+                first_gid,
+                tileset: xml_tileset(path, node)?,
+                dir: dir.to_owned(),
+            },
+        });
+    }
+    let map = RawMap {
+        orientation: node_or(root, "orientation", "orthogonal"),
+        infinite: flag(path, root, "infinite", false)?,
+        tilewidth: required(path, root, "tilewidth")?,
+        tileheight: required(path, root, "tileheight")?,
+        layers: xml_layers(path, root)?,
+        properties: xml_properties(root),
+    };
+    Ok((map, tilesets))
+}
+
+fn node_or(node: Node, name: &str, default: &str) -> String {
+    node.attribute(name).unwrap_or(default).to_owned()
+}
+
+fn xml_tileset(path: &Path, node: Node) -> Result<RawTileset, TiledError> {
+    let image = child(node, "image");
+    let mut tiles = Vec::new();
+    for tile in node.children().filter(|child| child.has_tag_name("tile")) {
+        let mut animation = Vec::new();
+        if let Some(frames) = child(tile, "animation") {
+            for frame in frames
+                .children()
+                .filter(|child| child.has_tag_name("frame"))
+            {
+                animation.push(RawFrame {
+                    tileid: required(path, frame, "tileid")?,
+                    duration: required(path, frame, "duration")?,
+                });
+            }
+        }
+        tiles.push(RawTile {
+            id: required(path, tile, "id")?,
+            animation,
+        });
+    }
+    Ok(RawTileset {
+        name: string(node, "name"),
+        image: image
+            .and_then(|image| image.attribute("source"))
+            .map(str::to_owned),
+        imagewidth: image
+            .map_or(Ok(None), |image| attribute(path, image, "width"))?
+            .unwrap_or_default(),
+        imageheight: image
+            .map_or(Ok(None), |image| attribute(path, image, "height"))?
+            .unwrap_or_default(),
+        tilewidth: required(path, node, "tilewidth")?,
+        tileheight: required(path, node, "tileheight")?,
+        margin: attribute(path, node, "margin")?.unwrap_or_default(),
+        spacing: attribute(path, node, "spacing")?.unwrap_or_default(),
+        tiles,
+    })
+}
+
+fn xml_layers(path: &Path, parent: Node) -> Result<Vec<RawLayer>, TiledError> {
+    let mut layers = Vec::new();
+    for node in parent.children().filter(|node| node.is_element()) {
+        let kind = match node.tag_name().name() {
+            "layer" => "tilelayer",
+            "objectgroup" => "objectgroup",
+            "group" => "group",
+            "imagelayer" => "imagelayer",
+            _ => continue,
+        };
+        let mut layer = RawLayer {
+            kind: kind.to_owned(),
+            name: string(node, "name"),
+            visible: flag(path, node, "visible", true)?,
+            opacity: attribute(path, node, "opacity")?.unwrap_or(1.0),
+            offsetx: attribute(path, node, "offsetx")?.unwrap_or_default(),
+            offsety: attribute(path, node, "offsety")?.unwrap_or_default(),
+            width: attribute(path, node, "width")?.unwrap_or_default(),
+            height: attribute(path, node, "height")?.unwrap_or_default(),
+            data: None,
+            encoding: String::new(),
+            compression: String::new(),
+            objects: Vec::new(),
+            layers: Vec::new(),
+            properties: xml_properties(node),
+        };
+        match kind {
+            "tilelayer" => {
+                let data = child(node, "data")
+                    .ok_or_else(|| invalid(path, format!("layer `{}` has no data", layer.name)))?;
+                if child(data, "chunk").is_some() {
+                    return Err(unsupported(path, "infinite maps"));
+                }
+                layer.encoding = string(data, "encoding");
+                layer.compression = string(data, "compression");
+                let text = data.text().unwrap_or_default();
+                layer.data = Some(match layer.encoding.as_str() {
+                    "csv" => RawData::Gids(
+                        text.split(',')
+                            .map(|gid| {
+                                gid.trim().parse().map_err(|_| {
+                                    invalid(path, format!("invalid tile `{}`", gid.trim()))
+                                })
+                            })
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    "" => RawData::Gids(
+                        data.children()
+                            .filter(|child| child.has_tag_name("tile"))
+                            .map(|tile| Ok(attribute(path, tile, "gid")?.unwrap_or_default()))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    _ => RawData::Encoded(text.to_owned()),
+                });
+            }
+            "objectgroup" => {
+                layer.objects = node
+                    .children()
+                    .filter(|child| child.has_tag_name("object"))
+                    .map(|object| xml_object(path, object))
+                    .collect::<Result<_, _>>()?;
+            }
+            "group" => layer.layers = xml_layers(path, node)?,
+            _ => {}
+        }
+        layers.push(layer);
+    }
+    Ok(layers)
+}
+
+fn xml_object(path: &Path, node: Node) -> Result<RawObject, TiledError> {
+    let points = |name: &str| {
+        child(node, name)
+            .map(|shape| {
+                string(shape, "points")
+                    .split_whitespace()
+                    .map(|point| {
+                        let (x, y) = point.split_once(',').unwrap_or((point, ""));
+                        match (x.parse(), y.parse()) {
+                            (Ok(x), Ok(y)) => Ok(RawPoint { x, y }),
+                            _ => Err(invalid(path, format!("invalid point `{}`", point))),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()
+    };
+    Ok(RawObject {
+        id: attribute(path, node, "id")?.unwrap_or_default(),
+        name: string(node, "name"),
+        kind: string(node, "type"),
+        class: string(node, "class"),
+        x: attribute(path, node, "x")?.unwrap_or_default(),
+        y: attribute(path, node, "y")?.unwrap_or_default(),
+        width: attribute(path, node, "width")?.unwrap_or_default(),
+        height: attribute(path, node, "height")?.unwrap_or_default(),
+        rotation: attribute(path, node, "rotation")?.unwrap_or_default(),
+        gid: attribute(path, node, "gid")?,
+        visible: flag(path, node, "visible", true)?,
+        ellipse: child(node, "ellipse").is_some(),
+        point: child(node, "point").is_some(),
+        polygon: points("polygon")?,
+        polyline: points("polyline")?,
+        text: child(node, "text").map(|text| RawText {
+            text: text.text().unwrap_or_default().to_owned(),
+        }),
+        properties: xml_properties(node),
+    })
+}
+
+fn xml_properties(node: Node) -> Vec<RawProperty> {
+    child(node, "properties")
+        .into_iter()
+        .flat_map(|properties| properties.children())
+        .filter(|property| property.has_tag_name("property"))
+        .map(|property| RawProperty {
+            name: string(property, "name"),
+            kind: string(property, "type"),
+            // Multi-line strings are stored as text instead of an attribute.
+            value: serde_json::Value::String(
+                property
+                    .attribute("value")
+                    .or_else(|| property.text())
+                    .unwrap_or_default()
+                    .to_owned(),
+            ),
+        })
+        .collect()
+}
+
+fn properties(raw: Vec<RawProperty>) -> Properties {
+    raw.into_iter()
+        .map(|property| {
+            let text = match property.value {
+                serde_json::Value::String(text) => text,
+                value => value.to_string(),
+            };
+            let value = match property.kind.as_str() {
+                "bool" => PropertyValue::Bool(text == "true"),
+                "int" | "object" => text
+                    .parse()
+                    .map_or(PropertyValue::String(text), PropertyValue::Int),
+                "float" => text
+                    .parse()
+                    .map_or(PropertyValue::String(text), PropertyValue::Float),
+                _ => PropertyValue::String(text),
+            };
+            (property.name, value)
+        })
+        .collect()
+}
+
+fn decode(path: &Path, layer: &mut RawLayer) -> Result<Vec<u32>, TiledError> {
+    let text = match layer.data.take() {
+        Some(RawData::Gids(gids)) => return Ok(gids),
+        Some(RawData::Encoded(text)) => text,
+        None => return Err(invalid(path, format!("layer `{}` has no data", layer.name))),
+    };
+    if layer.encoding != "base64" {
+        return Err(unsupported(
+            path,
+            format!("the `{}` encoding", layer.encoding),
+        ));
+    }
+    let text = text.split_whitespace().collect::<String>();
+    let bytes = base64::decode(text)
+        .map_err(|error| invalid(path, format!("layer `{}`: {}", layer.name, error)))?;
+    let inflate = |mut reader: Box<dyn Read + '_>| {
+        let mut inflated = Vec::new();
+        reader
+            .read_to_end(&mut inflated)
+            .map_err(|error| invalid(path, format!("layer `{}`: {}", layer.name, error)))?;
+        Ok(inflated)
+    };
+    let bytes = match layer.compression.as_str() {
+        "" => bytes,
+        "zlib" => inflate(Box::new(flate2::read::ZlibDecoder::new(&bytes[..])))?,
+        "gzip" => inflate(Box::new(flate2::read::GzDecoder::new(&bytes[..])))?,
+        other => return Err(unsupported(path, format!("{} compression", other))),
+    };
+    if bytes.len() % 4 != 0 {
+        return Err(invalid(
+            path,
+            format!("layer `{}` has truncated data", layer.name),
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+        .collect())
+}
+
+fn tileset(path: &Path, entry: TilesetEntry) -> Result<Tileset, TiledError> {
+    let raw = entry.tileset;
+    let image = raw
+        .image
+        .ok_or_else(|| unsupported(path, format!("the image collection `{}`", raw.name)))?;
+    let texture = entry.dir.join(image);
+    let image_size = if raw.imagewidth > 0 && raw.imageheight > 0 {
+        [raw.imagewidth, raw.imageheight]
+    } else {
+        let (width, height) =
+            image::image_dimensions(&texture).map_err(|source| TiledError::Image {
+                path: texture.clone(),
+                source,
+            })?;
+        [width, height]
+    };
+    let mut tileset = Tileset::new(texture, image_size, [raw.tilewidth, raw.tileheight])
+        .with_name(raw.name)
+        .with_margin(raw.margin)
+        .with_spacing(raw.spacing);
+    for tile in raw
+        .tiles
+        .into_iter()
+        .filter(|tile| !tile.animation.is_empty())
+    {
+        if tile.animation.iter().any(|frame| frame.duration == 0) {
+            return Err(invalid(
+                path,
+                format!("tile {} has an animation frame without a duration", tile.id),
+            ));
+        }
+        let frames = tile
+            .animation
+            .iter()
+            .map(|frame| TileAnimationFrame {
+                tile: frame.tileid,
+                duration: frame.duration as f32 / 1000.0,
+            })
+            .collect();
+        tileset = tileset.with_animation(tile.id, frames);
+    }
+    Ok(tileset)
+}
+
+/// What a layer inherits from the groups it is in.
+#[derive(Clone, Copy)]
+struct Inherited {
+    offset: [f32; 2],
+    opacity: f32,
+    visible: bool,
+}
+
+struct MapBuilder<'a> {
+    path: &'a Path,
+    tile_size: [f32; 2],
+    first_gids: Vec<u32>,
+    tilemap: Tilemap,
+    object_layers: Vec<ObjectLayer>,
+}
+
+impl<'a> MapBuilder<'a> {
+    fn build(
+        path: &'a Path,
+        map: RawMap,
+        mut tilesets: Vec<TilesetEntry>,
+    ) -> Result<TiledMap, TiledError> {
+        if map.orientation != "orthogonal" {
+            return Err(unsupported(path, format!("{} maps", map.orientation)));
+        }
+        if map.infinite {
+            return Err(unsupported(path, "infinite maps"));
+        }
+        tilesets.sort_by_key(|entry| entry.first_gid);
+        let mut builder = Self {
+            path,
+            tile_size: [map.tilewidth as f32, map.tileheight as f32],
+            first_gids: tilesets.iter().map(|entry| entry.first_gid).collect(),
+            tilemap: Tilemap::new([map.tilewidth, map.tileheight]),
+            object_layers: Vec::new(),
+        };
+        for entry in tilesets {
+            builder.tilemap.add_tileset(tileset(path, entry)?);
+        }
+        builder.add_layers(
+            map.layers,
+            Inherited {
+                offset: [0.0, 0.0],
+                opacity: 1.0,
+                visible: true,
+            },
+        )?;
+        Ok(TiledMap {
+            tilemap: builder.tilemap,
+            object_layers: builder.object_layers,
+            properties: properties(map.properties),
+        })
+    }
+
+    /// Converts pixels to world units.
+    fn world(&self, x: f32, y: f32) -> [f32; 2] {
+        [x / self.tile_size[0], -y / self.tile_size[1]]
+    }
+
+    fn tile(&self, gid: u32) -> Result<Option<Tile>, TiledError> {
+        let id = gid & !GID_FLAGS;
+        if id == 0 {
+            return Ok(None);
+        }
+        let tileset = self
+            .first_gids
+            .iter()
+            .rposition(|&first_gid| first_gid <= id)
+            .ok_or_else(|| invalid(self.path, format!("tile {} is not in a tileset", id)))?;
+        let flip = TileFlip {
+            horizontal: gid & FLIPPED_HORIZONTALLY != 0,
+            vertical: gid & FLIPPED_VERTICALLY != 0,
+            diagonal: gid & FLIPPED_DIAGONALLY != 0,
+        };
+        Ok(Some(
+            Tile::new(tileset, id - self.first_gids[tileset]).with_flip(flip),
+        ))
+    }
+
+    fn add_layers(&mut self, layers: Vec<RawLayer>, parent: Inherited) -> Result<(), TiledError> {
+        for mut layer in layers {
+            let offset = self.world(layer.offsetx, layer.offsety);
+            let inherited = Inherited {
+                offset: [parent.offset[0] + offset[0], parent.offset[1] + offset[1]],
+                opacity: parent.opacity * layer.opacity,
+                visible: parent.visible && layer.visible,
+            };
+            match layer.kind.as_str() {
+                "tilelayer" => self.add_tile_layer(&mut layer, inherited)?,
+                "objectgroup" => {
+                    let objects = std::mem::take(&mut layer.objects)
+                        .into_iter()
+                        .map(|object| self.object(object))
+                        .collect::<Result<_, _>>()?;
+                    self.object_layers.push(ObjectLayer {
+                        name: layer.name,
+                        visible: inherited.visible,
+                        opacity: inherited.opacity,
+                        offset: inherited.offset,
+                        objects,
+                        properties: properties(layer.properties),
+                    });
+                }
+                "group" => self.add_layers(layer.layers, inherited)?,
+                "imagelayer" => {}
+                other => {
+                    return Err(invalid(
+                        self.path,
+                        format!("unknown layer type `{}`", other),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_tile_layer(
+        &mut self,
+        layer: &mut RawLayer,
+        inherited: Inherited,
+    ) -> Result<(), TiledError> {
+        let cells = layer.width.checked_mul(layer.height).ok_or_else(|| {
+            invalid(
+                self.path,
+                format!(
+                    "layer `{}` is too large at {}x{}",
+                    layer.name, layer.width, layer.height
+                ),
+            )
+        })?;
+        let gids = decode(self.path, layer)?;
+        if gids.len() != cells as usize {
+            return Err(invalid(
+                self.path,
+                format!(
+                    "layer `{}` has {} tiles instead of {}x{}",
+                    layer.name,
+                    gids.len(),
+                    layer.width,
+                    layer.height
+                ),
+            ));
+        }
+        let mut tiles = TileLayer::new(layer.name.clone(), layer.width, layer.height)
+            .with_offset(inherited.offset)
+            .with_opacity(inherited.opacity);
+        tiles.visible = inherited.visible;
+        for (index, &gid) in gids.iter().enumerate() {
+            let tile = self.tile(gid)?;
+            if tile.is_some() {
+                let index = index as u32;
+                tiles.set_tile(index % layer.width, index / layer.width, tile);
+            }
+        }
+        self.tilemap.add_layer(tiles);
+        Ok(())
+    }
+
+    fn object(&self, raw: RawObject) -> Result<TiledObject, TiledError> {
+        let points = |points: Vec<RawPoint>| {
+            points
+                .into_iter()
+                .map(|point| self.world(point.x, point.y))
+                .collect()
+        };
+        let shape = if let Some(polygon) = raw.polygon {
+            ObjectShape::Polygon(points(polygon))
+        } else if let Some(polyline) = raw.polyline {
+            ObjectShape::Polyline(points(polyline))
+        } else if let Some(text) = raw.text {
+            ObjectShape::Text(text.text)
+        } else if raw.ellipse {
+            ObjectShape::Ellipse
+        } else if raw.point {
+            ObjectShape::Point
+        } else {
+            ObjectShape::Rectangle
+        };
+        Ok(TiledObject {
+            id: raw.id,
+            name: raw.name,
+            class: if raw.class.is_empty() {
+                raw.kind
+            } else {
+                raw.class
+            },
+            position: self.world(raw.x, raw.y),
+            size: [
+                raw.width / self.tile_size[0],
+                raw.height / self.tile_size[1],
+            ],
+            rotation: Rad(-raw.rotation.to_radians()),
+            tile: raw.gid.map(|gid| self.tile(gid)).transpose()?.flatten(),
+            visible: raw.visible,
+            shape,
+            properties: properties(raw.properties),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+    use std::io::Write;
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    const FLIPPED: u32 = FLIPPED_HORIZONTALLY | 1;
+    const GIDS: [u32; 6] = [1, 2, 0, 5, 6, FLIPPED];
+
+    fn encode(compression: &str) -> String {
+        let bytes = GIDS
+            .iter()
+            .flat_map(|gid| gid.to_le_bytes())
+            .collect::<Vec<_>>();
+        let bytes = match compression {
+            "zlib" => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&bytes).unwrap();
+                encoder.finish().unwrap()
+            }
+            "gzip" => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&bytes).unwrap();
+                encoder.finish().unwrap()
+            }
+            _ => bytes,
+        };
+        base64::encode(bytes)
+    }
+
+    fn tmx() -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="3" height="2" tilewidth="16" tileheight="16" infinite="0">
+ <properties>
+  <property name="title" value="test"/>
+  <property name="level" type="int" value="3"/>
+ </properties>
+ <tileset firstgid="5" source="props.tsx"/>
+ <tileset firstgid="1" name="ground" tilewidth="16" tileheight="16">
+  <image source="ground.png" width="32" height="32"/>
+  <tile id="0">
+   <animation>
+    <frame tileid="0" duration="100"/>
+    <frame tileid="3" duration="100"/>
+   </animation>
+  </tile>
+ </tileset>
+ <layer name="csv" width="3" height="2">
+  <data encoding="csv">
+1,2,0,
+5,6,{flipped}
+</data>
+ </layer>
+ <layer name="zlib" width="3" height="2">
+  <data encoding="base64" compression="zlib">
+   {zlib}
+  </data>
+ </layer>
+ <group name="group" offsetx="16" offsety="8" opacity="0.5" visible="0">
+  <layer name="gzip" width="3" height="2" offsetx="16" opacity="0.5">
+   <data encoding="base64" compression="gzip">{gzip}</data>
+  </layer>
+  <objectgroup name="objects">
+   <object id="1" name="spawn" x="16" y="32" width="32" height="16" rotation="90"/>
+   <object id="2" gid="{flipped}" x="0" y="16" width="16" height="16"/>
+   <object id="3" x="0" y="0"><polygon points="0,0 16,0 16,16"/></object>
+   <object id="4" x="0" y="0"><point/></object>
+  </objectgroup>
+ </group>
+ <imagelayer name="background"/>
+</map>"#,
+            flipped = FLIPPED,
+            zlib = encode("zlib"),
+            gzip = encode("gzip"),
+        )
+    }
+
+    const TSX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset name="props" tilewidth="16" tileheight="32">
+ <image source="props.png" width="32" height="32"/>
+</tileset>"#;
+
+    fn tmj() -> String {
+        format!(
+            r#"{{
+    "orientation": "orthogonal",
+    "tilewidth": 16,
+    "tileheight": 16,
+    "tilesets": [
+        {{ "firstgid": 1, "name": "ground", "image": "ground.png", "imagewidth": 32,
+           "imageheight": 32, "tilewidth": 16, "tileheight": 16 }},
+        {{ "firstgid": 5, "source": "props.tsx" }}
+    ],
+    "layers": [
+        {{ "type": "tilelayer", "name": "array", "width": 3, "height": 2,
+           "data": [{gids}] }},
+        {{ "type": "tilelayer", "name": "base64", "width": 3, "height": 2,
+           "encoding": "base64", "data": "{base64}" }},
+        {{ "type": "group", "offsetx": -16, "opacity": 0.5, "layers": [
+            {{ "type": "tilelayer", "name": "nested", "width": 3, "height": 2,
+               "offsety": 16, "visible": false, "encoding": "base64",
+               "compression": "zlib", "data": "{zlib}" }},
+            {{ "type": "objectgroup", "name": "objects", "objects": [
+                {{ "id": 1, "name": "spawn", "x": 16, "y": 32, "rotation": -90 }}
+            ] }}
+        ] }}
+    ],
+    "properties": [{{ "name": "gravity", "type": "float", "value": 9.5 }}]
+}}"#,
+            gids = GIDS.map(|gid| gid.to_string()).join(", "),
+            base64 = encode(""),
+            zlib = encode("zlib"),
+        )
+    }
+
+    fn load_fixture(name: &str, map: &str) -> Result<TiledMap, TiledError> {
+        let dir = TempDir::new("tiled");
+        dir.write("props.tsx", TSX);
+        load(dir.write(name, map))
+    }
+
+    fn assert_tiles(layer: &TileLayer) {
+        assert_eq!((layer.width(), layer.height()), (3, 2));
+        assert_eq!(layer.tile(0, 0), Some(Tile::new(0, 0)));
+        assert_eq!(layer.tile(1, 0), Some(Tile::new(0, 1)));
+        assert_eq!(layer.tile(2, 0), None);
+        assert_eq!(layer.tile(0, 1), Some(Tile::new(1, 0)));
+        assert_eq!(layer.tile(1, 1), Some(Tile::new(1, 1)));
+        let flip = TileFlip {
+            horizontal: true,
+            ..TileFlip::default()
+        };
+        assert_eq!(layer.tile(2, 1), Some(Tile::new(0, 0).with_flip(flip)));
+    }
+
+    #[test]
+    fn loads_tmx_maps() {
+        let map = load_fixture("map.tmx", &tmx()).unwrap();
+        let tilemap = &map.tilemap;
+        assert_eq!(tilemap.tile_size(), [16, 16]);
+
+        // Tilesets are sorted by firstgid
+        let tilesets = tilemap.tilesets();
+        assert_eq!(tilesets[0].name, "ground");
+        assert_eq!(tilesets[0].tile_count, 4);
+        assert_eq!(tilesets[0].animations[&0][1].tile, 3);
+        assert_eq!(tilesets[0].animations[&0][1].duration, 0.1);
+        assert!(tilesets[0].texture.ends_with("ground.png"));
+        assert_eq!(tilesets[1].name, "props");
+        assert_eq!(tilesets[1].tile_size, [16, 32]);
+
+        let names = tilemap.layers().iter().map(|layer| layer.name.as_str());
+        assert_eq!(names.collect::<Vec<_>>(), ["csv", "zlib", "gzip"]);
+        for layer in tilemap.layers() {
+            assert_tiles(layer);
+        }
+
+        assert_eq!(
+            map.properties["title"],
+            PropertyValue::String("test".to_string())
+        );
+        assert_eq!(map.properties["level"], PropertyValue::Int(3));
+    }
+
+    #[test]
+    fn groups_pass_offset_opacity_and_visibility_down() {
+        let map = load_fixture("group.tmx", &tmx()).unwrap();
+        let gzip = &map.tilemap.layers()[2];
+        assert_eq!(gzip.offset(), [2.0, -0.5]);
+        assert_eq!(gzip.opacity(), 0.25);
+        assert!(!gzip.visible);
+        let csv = &map.tilemap.layers()[0];
+        assert_eq!(
+            (csv.offset(), csv.opacity(), csv.visible),
+            ([0.0, 0.0], 1.0, true)
+        );
+
+        let objects = &map.object_layers[0];
+        assert_eq!(objects.offset, [1.0, -0.5]);
+        assert_eq!(objects.opacity, 0.5);
+        assert!(!objects.visible);
+    }
+
+    #[test]
+    fn flips_objects_to_y_up() {
+        let map = load_fixture("objects.tmx", &tmx()).unwrap();
+        let objects = &map.object_layers[0].objects;
+
+        let spawn = &objects[0];
+        assert_eq!(spawn.name, "spawn");
+        assert_eq!(spawn.position, [1.0, -2.0]);
+        assert_eq!(spawn.size, [2.0, 1.0]);
+        assert_eq!(spawn.rotation, Rad(-FRAC_PI_2));
+        assert_eq!(spawn.shape, ObjectShape::Rectangle);
+
+        let flip = TileFlip {
+            horizontal: true,
+            ..TileFlip::default()
+        };
+        assert_eq!(objects[1].tile, Some(Tile::new(0, 0).with_flip(flip)));
+        assert_eq!(objects[1].position, [0.0, -1.0]);
+        assert_eq!(
+            objects[2].shape,
+            ObjectShape::Polygon(vec![[0.0, 0.0], [1.0, 0.0], [1.0, -1.0]])
+        );
+        assert_eq!(objects[3].shape, ObjectShape::Point);
+    }
+
+    #[test]
+    fn loads_tmj_maps() {
+        let map = load_fixture("map.tmj", &tmj()).unwrap();
+        let tilemap = &map.tilemap;
+        assert_eq!(tilemap.tilesets()[0].name, "ground");
+        assert_eq!(tilemap.tilesets()[1].name, "props");
+        for layer in tilemap.layers() {
+            assert_tiles(layer);
+        }
+
+        let nested = &tilemap.layers()[2];
+        assert_eq!(nested.offset(), [-1.0, -1.0]);
+        assert_eq!(nested.opacity(), 0.5);
+        assert!(!nested.visible);
+
+        let spawn = &map.object_layers[0].objects[0];
+        assert_eq!(spawn.position, [1.0, -2.0]);
+        assert_eq!(spawn.rotation, Rad(FRAC_PI_2));
+        assert_eq!(map.properties["gravity"], PropertyValue::Float(9.5));
+    }
+
+    #[test]
+    fn rejects_invalid_maps() {
+        let map = tmx().replace("5,6,", "5,6,0,");
+        assert!(matches!(
+            load_fixture("long.tmx", &map),
+            Err(TiledError::Invalid { message, .. }) if message.contains("7 tiles")
+        ));
+
+        let map = tmx().replace("1,2,0,", "1,2,x,");
+        assert!(matches!(
+            load_fixture("gid.tmx", &map),
+            Err(TiledError::Invalid { message, .. }) if message.contains("`x`")
+        ));
+
+        let map = tmj().replace(
+            r#""name": "array", "width": 3, "height": 2"#,
+            r#""name": "array", "width": 65536, "height": 65536"#,
+        );
+        assert!(matches!(
+            load_fixture("huge.tmj", &map),
+            Err(TiledError::Invalid { message, .. }) if message.contains("too large")
+        ));
+
+        let map = tmx().replace(r#"infinite="0""#, r#"infinite="1""#);
+        assert!(matches!(
+            load_fixture("infinite.tmx", &map),
+            Err(TiledError::Unsupported { .. })
+        ));
+    }
+}
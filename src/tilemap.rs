@@ -0,0 +1,991 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use cgmath::Matrix4;
+
+use crate::atlas::UvRect;
+use crate::buffers::{self, Storage, Uniform};
+use crate::camera::Camera;
+use crate::ecs::{system, Access, FnSystem, Without, World};
+use crate::renderer::Context;
+use crate::scene::{Hidden, Scene};
+use crate::sprite::TextureId;
+use crate::texture::Texture;
+use crate::time::FrameTime;
+use crate::transform::GlobalTransform;
+
+/// Width and height of a chunk in tiles.
+pub const CHUNK_SIZE: u32 = 16;
+
+static NEXT_TILEMAP_ID: AtomicU64 = AtomicU64::new(0);
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TilemapId(u64);
+
+/// Tiled's flip flags. The diagonal flip swaps x and y and is applied first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TileFlip {
+    pub horizontal: bool,
+    pub vertical: bool,
+    pub diagonal: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    /// Index into the map's tilesets.
+    pub tileset: usize,
+    pub id: u32,
+    pub flip: TileFlip,
+}
+
+impl Tile {
+    pub fn new(tileset: usize, id: u32) -> Self {
+        Self {
+            tileset,
+            id,
+            flip: TileFlip::default(),
+        }
+    }
+
+    pub fn with_flip(mut self, flip: TileFlip) -> Self {
+        self.flip = flip;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileAnimationFrame {
+    pub tile: u32,
+    /// In seconds.
+    pub duration: f32,
+}
+
+/// A texture split into equally sized tiles, numbered row by row from the top
+/// left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tileset {
+    pub name: String,
+    pub texture: PathBuf,
+    pub image_size: [u32; 2],
+    pub tile_size: [u32; 2],
+    pub margin: u32,
+    pub spacing: u32,
+    pub columns: u32,
+    pub tile_count: u32,
+    /// Frames of the animated tiles, by tile id.
+    pub animations: HashMap<u32, Vec<TileAnimationFrame>>,
+}
+
+impl Tileset {
+    pub fn new(texture: impl Into<PathBuf>, image_size: [u32; 2], tile_size: [u32; 2]) -> Self {
+        let mut tileset = Self {
+            name: String::new(),
+            texture: texture.into(),
+            image_size,
+            tile_size,
+            margin: 0,
+            spacing: 0,
+            columns: 0,
+            tile_count: 0,
+            animations: HashMap::new(),
+        };
+        tileset.fit_tiles();
+        tileset
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Pixels around the tiles.
+    pub fn with_margin(mut self, margin: u32) -> Self {
+        self.margin = margin;
+        self.fit_tiles();
+        self
+    }
+
+    /// Pixels between the tiles.
+    pub fn with_spacing(mut self, spacing: u32) -> Self {
+        self.spacing = spacing;
+        self.fit_tiles();
+        self
+    }
+
+    /// Panics if a frame's duration is not positive.
+    pub fn with_animation(mut self, tile: u32, frames: Vec<TileAnimationFrame>) -> Self {
+        assert!(
+            frames.iter().all(|frame| frame.duration > 0.0),
+            "frame durations must be positive"
+        );
+        self.animations.insert(tile, frames);
+        self
+    }
+
+    fn fit_tiles(&mut self) {
+        let fit = |size: u32, tile: u32| {
+            (size.saturating_sub(2 * self.margin) + self.spacing) / (tile + self.spacing).max(1)
+        };
+        self.columns = fit(self.image_size[0], self.tile_size[0]);
+        self.tile_count = self.columns * fit(self.image_size[1], self.tile_size[1]);
+    }
+
+    pub fn tile_uv(&self, id: u32) -> UvRect {
+        let columns = self.columns.max(1);
+        let (column, row) = (id % columns, id / columns);
+        let x = self.margin + column * (self.tile_size[0] + self.spacing);
+        let y = self.margin + row * (self.tile_size[1] + self.spacing);
+        let (width, height) = (self.image_size[0] as f32, self.image_size[1] as f32);
+        UvRect {
+            min: [x as f32 / width, y as f32 / height],
+            max: [
+                (x + self.tile_size[0]) as f32 / width,
+                (y + self.tile_size[1]) as f32 / height,
+            ],
+        }
+    }
+
+    /// The UV rectangle of every tile `time` seconds into the animations,
+    /// animated tiles showing their current frame.
+    pub fn uvs_at(&self, time: f32) -> Vec<UvRect> {
+        let mut uvs = (0..self.tile_count)
+            .map(|id| self.tile_uv(id))
+            .collect::<Vec<_>>();
+        for (tile, shown) in self.animation_frames_at(time) {
+            if let Some(uv) = uvs.get_mut(tile as usize) {
+                *uv = self.tile_uv(shown);
+            }
+        }
+        uvs
+    }
+
+    /// The tile each animated tile shows `time` seconds into its animation,
+    /// sorted by animated tile.
+    fn animation_frames_at(&self, time: f32) -> Vec<(u32, u32)> {
+        let mut shown = self
+            .animations
+            .iter()
+            .filter_map(|(&tile, frames)| {
+                let total = frames.iter().map(|frame| frame.duration).sum::<f32>();
+                if total <= 0.0 {
+                    return None;
+                }
+                let mut time = time % total;
+                for frame in frames {
+                    if time < frame.duration {
+                        return Some((tile, frame.tile));
+                    }
+                    time -= frame.duration;
+                }
+                frames.last().map(|frame| (tile, frame.tile))
+            })
+            .collect::<Vec<_>>();
+        shown.sort_unstable();
+        shown
+    }
+}
+
+/// A grid of tiles. Changing tiles marks their chunk for rebuilding.
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub name: String,
+    width: u32,
+    height: u32,
+    tiles: Vec<Option<Tile>>,
+    /// In world units.
+    offset: [f32; 2],
+    opacity: f32,
+    pub visible: bool,
+    revisions: Vec<u64>,
+}
+
+impl TileLayer {
+    /// Panics if the layer has more than `u32::MAX` cells.
+    pub fn new(name: impl Into<String>, width: u32, height: u32) -> Self {
+        let cells = width
+            .checked_mul(height)
+            .unwrap_or_else(|| panic!("a {}x{} layer has too many tiles", width, height));
+        let chunks = width.div_ceil(CHUNK_SIZE) * height.div_ceil(CHUNK_SIZE);
+        Self {
+            name: name.into(),
+            width,
+            height,
+            tiles: vec![None; cells as usize],
+            offset: [0.0; 2],
+            opacity: 1.0,
+            visible: true,
+            revisions: (0..chunks).map(|_| next_revision()).collect(),
+        }
+    }
+
+    pub fn with_offset(mut self, offset: [f32; 2]) -> Self {
+        self.set_offset(offset);
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.set_opacity(opacity);
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn offset(&self) -> [f32; 2] {
+        self.offset
+    }
+
+    pub fn set_offset(&mut self, offset: [f32; 2]) {
+        self.offset = offset;
+        self.touch_all();
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+        self.touch_all();
+    }
+
+    /// `None` for empty cells and cells outside the layer.
+    pub fn tile(&self, x: u32, y: u32) -> Option<Tile> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.tiles[(y * self.width + x) as usize]
+    }
+
+    /// Panics if the cell is outside the layer.
+    pub fn set_tile(&mut self, x: u32, y: u32, tile: Option<Tile>) {
+        assert!(
+            x < self.width && y < self.height,
+            "tile ({}, {}) is outside the {}x{} layer",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        let cell = &mut self.tiles[(y * self.width + x) as usize];
+        if *cell != tile {
+            *cell = tile;
+            let chunk = self.chunk_index(x / CHUNK_SIZE, y / CHUNK_SIZE);
+            self.revisions[chunk] = next_revision();
+        }
+    }
+
+    fn chunk_columns(&self) -> u32 {
+        self.width.div_ceil(CHUNK_SIZE)
+    }
+
+    fn chunk_index(&self, x: u32, y: u32) -> usize {
+        (y * self.chunk_columns() + x) as usize
+    }
+
+    fn touch_all(&mut self) {
+        for revision in &mut self.revisions {
+            *revision = next_revision();
+        }
+    }
+}
+
+/// Layers of tiles from a set of tilesets, drawn in layer order. One map cell
+/// is one world unit, with the top left cell's top left corner at the origin
+/// and rows going down. Tiles larger than a cell extend up and right from the
+/// bottom left corner of theirs.
+///
+/// Chunks of `CHUNK_SIZE` by `CHUNK_SIZE` tiles are meshed once and kept in
+/// static vertex buffers until one of their tiles changes. Animated tiles only
+/// update a table of tile UVs.
+#[derive(Debug)]
+pub struct Tilemap {
+    id: TilemapId,
+    /// Size of a cell in pixels, which tileset tile sizes are relative to.
+    tile_size: [u32; 2],
+    tilesets: Vec<Tileset>,
+    layers: Vec<TileLayer>,
+    /// Seconds the tile animations have played for.
+    pub time: f32,
+    meshes: HashMap<ChunkKey, (u64, Rc<ChunkMesh>)>,
+    uvs: Vec<TilesetUvs>,
+}
+
+/// The tile UVs of a tileset, and the animation frames they show.
+#[derive(Debug)]
+struct TilesetUvs {
+    frames: Vec<(u32, u32)>,
+    revision: u64,
+    uvs: Rc<Vec<UvRect>>,
+}
+
+impl Tilemap {
+    pub fn new(tile_size: [u32; 2]) -> Self {
+        Self {
+            id: TilemapId(NEXT_TILEMAP_ID.fetch_add(1, Ordering::Relaxed)),
+            tile_size,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            time: 0.0,
+            meshes: HashMap::new(),
+            uvs: Vec::new(),
+        }
+    }
+
+    pub fn with_tileset(mut self, tileset: Tileset) -> Self {
+        self.add_tileset(tileset);
+        self
+    }
+
+    pub fn with_layer(mut self, layer: TileLayer) -> Self {
+        self.add_layer(layer);
+        self
+    }
+
+    pub fn id(&self) -> TilemapId {
+        self.id
+    }
+
+    pub fn tile_size(&self) -> [u32; 2] {
+        self.tile_size
+    }
+
+    pub fn add_tileset(&mut self, tileset: Tileset) -> usize {
+        self.tilesets.push(tileset);
+        self.tilesets.len() - 1
+    }
+
+    pub fn tilesets(&self) -> &[Tileset] {
+        &self.tilesets
+    }
+
+    pub fn add_layer(&mut self, layer: TileLayer) -> usize {
+        self.layers.push(layer);
+        self.layers.len() - 1
+    }
+
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+
+    pub fn layer(&self, index: usize) -> Option<&TileLayer> {
+        self.layers.get(index)
+    }
+
+    pub fn layer_mut(&mut self, index: usize) -> Option<&mut TileLayer> {
+        self.layers.get_mut(index)
+    }
+
+    pub fn layer_by_name(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    /// The visible chunks with at least one tile, in draw order, with their
+    /// meshes. Meshes are rebuilt only for chunks that changed since the last
+    /// call.
+    pub fn chunks(&mut self) -> Vec<SceneChunk> {
+        let mut chunks = Vec::new();
+        let mut meshes = HashMap::with_capacity(self.meshes.len());
+        for (index, layer) in self.layers.iter().enumerate() {
+            if !layer.visible {
+                continue;
+            }
+            let columns = layer.chunk_columns();
+            for (chunk, &revision) in layer.revisions.iter().enumerate() {
+                let key = ChunkKey {
+                    layer: index,
+                    x: chunk as u32 % columns,
+                    y: chunk as u32 / columns,
+                };
+                let mesh = match self.meshes.remove(&key) {
+                    Some((cached, mesh)) if cached == revision => mesh,
+                    _ => Rc::new(self.build_chunk(layer, key)),
+                };
+                if !mesh.parts.is_empty() {
+                    chunks.push(SceneChunk {
+                        key,
+                        revision,
+                        mesh: mesh.clone(),
+                    });
+                }
+                meshes.insert(key, (revision, mesh));
+            }
+        }
+        self.meshes = meshes;
+        chunks
+    }
+
+    /// The UVs of every tileset's tiles at `time`, with a revision that only
+    /// changes when an animated tile moves to another frame.
+    pub fn tile_uvs(&mut self) -> Vec<(u64, Rc<Vec<UvRect>>)> {
+        for (i, tileset) in self.tilesets.iter().enumerate() {
+            let frames = tileset.animation_frames_at(self.time);
+            if self
+                .uvs
+                .get(i)
+                .is_some_and(|cached| cached.frames == frames)
+            {
+                continue;
+            }
+            let uvs = TilesetUvs {
+                frames,
+                revision: next_revision(),
+                uvs: Rc::new(tileset.uvs_at(self.time)),
+            };
+            if i < self.uvs.len() {
+                self.uvs[i] = uvs;
+            } else {
+                self.uvs.push(uvs);
+            }
+        }
+        self.uvs
+            .iter()
+            .map(|cached| (cached.revision, cached.uvs.clone()))
+            .collect()
+    }
+
+    fn build_chunk(&self, layer: &TileLayer, key: ChunkKey) -> ChunkMesh {
+        let mut parts: Vec<ChunkPart> = Vec::new();
+        let (x0, y0) = (key.x * CHUNK_SIZE, key.y * CHUNK_SIZE);
+        for y in y0..(y0 + CHUNK_SIZE).min(layer.height) {
+            for x in x0..(x0 + CHUNK_SIZE).min(layer.width) {
+                let tile = match layer.tile(x, y) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+                let tileset = match self.tilesets.get(tile.tileset) {
+                    Some(tileset) => tileset,
+                    None => continue,
+                };
+                let part = match parts.iter_mut().position(|p| p.tileset == tile.tileset) {
+                    Some(i) => &mut parts[i],
+                    None => {
+                        parts.push(ChunkPart {
+                            tileset: tile.tileset,
+                            vertices: Vec::new(),
+                        });
+                        parts.last_mut().unwrap()
+                    }
+                };
+
+                let left = x as f32 + layer.offset[0];
+                let bottom = -(y as f32 + 1.0) + layer.offset[1];
+                let right = left + tileset.tile_size[0] as f32 / self.tile_size[0] as f32;
+                let top = bottom + tileset.tile_size[1] as f32 / self.tile_size[1] as f32;
+                let corners = [
+                    ([left, top], [0.0, 0.0]),
+                    ([left, bottom], [0.0, 1.0]),
+                    ([right, bottom], [1.0, 1.0]),
+                    ([right, top], [1.0, 0.0]),
+                ];
+                for ([x, y], corner) in corners {
+                    part.vertices.push(TileVertex {
+                        position: [x, y, 0.0],
+                        corner: flip_corner(corner, tile.flip),
+                        tile: tile.id,
+                        opacity: layer.opacity,
+                    });
+                }
+            }
+        }
+        ChunkMesh { parts }
+    }
+}
+
+fn flip_corner([mut u, mut v]: [f32; 2], flip: TileFlip) -> [f32; 2] {
+    if flip.diagonal {
+        std::mem::swap(&mut u, &mut v);
+    }
+    if flip.horizontal {
+        u = 1.0 - u;
+    }
+    if flip.vertical {
+        v = 1.0 - v;
+    }
+    [u, v]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    pub layer: usize,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TileVertex {
+    pub position: [f32; 3],
+    /// Which corner of the tile's UV rectangle this vertex samples.
+    pub corner: [f32; 2],
+    pub tile: u32,
+    pub opacity: f32,
+}
+
+impl TileVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Uint32,
+        3 => Float32,
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TileVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// The quads of one chunk, four vertices each, split by tileset.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkMesh {
+    pub parts: Vec<ChunkPart>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkPart {
+    pub tileset: usize,
+    pub vertices: Vec<TileVertex>,
+}
+
+/// A chunk for the renderer to draw. The renderer uploads `mesh` again only
+/// when `revision` changed.
+#[derive(Debug, Clone)]
+pub struct SceneChunk {
+    pub key: ChunkKey,
+    pub revision: u64,
+    pub mesh: Rc<ChunkMesh>,
+}
+
+/// A tileset for the renderer to draw with. The renderer uploads `uvs` again
+/// only when `revision` changed.
+#[derive(Debug, Clone)]
+pub struct SceneTileset {
+    pub texture: TextureId,
+    pub revision: u64,
+    pub uvs: Rc<Vec<UvRect>>,
+}
+
+/// A tilemap for the renderer to draw this frame.
+#[derive(Debug, Clone)]
+pub struct SceneTilemap {
+    pub id: TilemapId,
+    pub model: Matrix4<f32>,
+    pub tilesets: Vec<SceneTileset>,
+    pub chunks: Vec<SceneChunk>,
+}
+
+/// Advances the tile animations of every `Tilemap` and copies the visible ones
+/// into the `Scene`, placed by their `GlobalTransform`.
+pub fn extract_tilemaps() -> FnSystem<impl FnMut(&World)> {
+    system(
+        "extract_tilemaps",
+        Access::new()
+            .write::<Tilemap>()
+            .read::<GlobalTransform>()
+            .read::<Hidden>()
+            .read::<FrameTime>()
+            .write::<Scene>(),
+        extract,
+    )
+}
+
+fn extract(world: &World) {
+    let dt = world.resource::<FrameTime>().dt;
+    let mut scene = world.resource_mut::<Scene>();
+    scene.tilemaps.clear();
+    for (_, (tilemap, global, ())) in world
+        .query::<(&mut Tilemap, Option<&GlobalTransform>, Without<Hidden>)>()
+        .iter()
+    {
+        tilemap.time += dt;
+        let tilesets = tilemap
+            .tile_uvs()
+            .into_iter()
+            .zip(&tilemap.tilesets)
+            .map(|((revision, uvs), tileset)| SceneTileset {
+                texture: scene.texture(&tileset.texture),
+                revision,
+                uvs,
+            })
+            .collect();
+        let scene_tilemap = SceneTilemap {
+            id: tilemap.id,
+            model: global.map_or_else(|| GlobalTransform::default().0, |global| global.0),
+            tilesets,
+            chunks: tilemap.chunks(),
+        };
+        scene.tilemaps.push(scene_tilemap);
+    }
+}
+
+/// The GPU side of the tilemaps: a vertex buffer per chunk and tileset, kept
+/// while the chunk's revision stays the same, and a storage buffer of tile UVs
+/// per tileset, rewritten when an animated tile changes frame.
+pub struct TilemapRenderer {
+    indices: wgpu::Buffer,
+    maps: Vec<GpuTilemap>,
+    /// Bind group layouts to build the tilemap pipeline with.
+    uvs_layout: Storage<UvRect>,
+    model_layout: Uniform<Matrix4<f32>>,
+}
+
+struct GpuTilemap {
+    id: TilemapId,
+    model: Uniform<Matrix4<f32>>,
+    tilesets: Vec<GpuTileset>,
+    chunks: Vec<GpuChunk>,
+}
+
+struct GpuTileset {
+    texture: TextureId,
+    revision: u64,
+    uvs: Storage<UvRect>,
+}
+
+struct GpuChunk {
+    key: ChunkKey,
+    revision: u64,
+    parts: Vec<GpuChunkPart>,
+}
+
+struct GpuChunkPart {
+    tileset: usize,
+    vertices: wgpu::Buffer,
+    num_indices: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UvUpload {
+    Keep,
+    Update,
+    /// The buffer is missing or too small, so a new one is created with the
+    /// UVs and their count.
+    Create,
+}
+
+/// What to do with a tileset's UV buffer, given the capacity and revision of
+/// the one uploaded before, if any.
+fn uv_upload(uploaded: Option<(usize, u64)>, tileset: &SceneTileset) -> UvUpload {
+    match uploaded {
+        Some((capacity, _)) if capacity < tileset.uvs.len() => UvUpload::Create,
+        Some((_, revision)) if revision == tileset.revision => UvUpload::Keep,
+        Some(_) => UvUpload::Update,
+        None => UvUpload::Create,
+    }
+}
+
+impl TilemapRenderer {
+    pub fn new(context: &Context) -> Self {
+        let indices = (0..CHUNK_SIZE * CHUNK_SIZE)
+            .flat_map(|tile| {
+                let first = (tile * 4) as u16;
+                crate::quad::INDICES.iter().map(move |&i| first + i)
+            })
+            .collect::<Vec<_>>();
+        Self {
+            indices: buffers::index(context.device(), &indices),
+            maps: Vec::new(),
+            uvs_layout: Storage::new(context, []),
+            model_layout: Uniform::new(context, GlobalTransform::default().0),
+        }
+    }
+
+    pub fn uvs_layout(&self) -> &Storage<UvRect> {
+        &self.uvs_layout
+    }
+
+    pub fn model_layout(&self) -> &Uniform<Matrix4<f32>> {
+        &self.model_layout
+    }
+
+    /// Uploads the tile UVs and transforms, and the chunks that are new or
+    /// changed. Buffers of tilemaps and chunks no longer drawn are dropped.
+    pub fn prepare(&mut self, context: &Context, tilemaps: &[SceneTilemap]) {
+        let mut previous = std::mem::take(&mut self.maps);
+        for tilemap in tilemaps {
+            let mut map = match previous.iter().position(|map| map.id == tilemap.id) {
+                Some(i) => {
+                    let map = previous.swap_remove(i);
+                    map.model.update(context, tilemap.model);
+                    map
+                }
+                None => GpuTilemap {
+                    id: tilemap.id,
+                    model: Uniform::new(context, tilemap.model),
+                    tilesets: Vec::new(),
+                    chunks: Vec::new(),
+                },
+            };
+
+            map.tilesets.truncate(tilemap.tilesets.len());
+            for (i, tileset) in tilemap.tilesets.iter().enumerate() {
+                let uploaded = map
+                    .tilesets
+                    .get(i)
+                    .map(|gpu| (gpu.uvs.capacity(), gpu.revision));
+                match (uv_upload(uploaded, tileset), map.tilesets.get_mut(i)) {
+                    (UvUpload::Keep, Some(gpu)) => gpu.texture = tileset.texture,
+                    (UvUpload::Update, Some(gpu)) => {
+                        gpu.texture = tileset.texture;
+                        gpu.revision = tileset.revision;
+                        gpu.uvs.update(context, tileset.uvs.as_slice());
+                    }
+                    _ => {
+                        let gpu = GpuTileset {
+                            texture: tileset.texture,
+                            revision: tileset.revision,
+                            uvs: Storage::new(context, tileset.uvs.as_slice()),
+                        };
+                        if i < map.tilesets.len() {
+                            map.tilesets[i] = gpu;
+                        } else {
+                            map.tilesets.push(gpu);
+                        }
+                    }
+                }
+            }
+
+            let mut chunks = std::mem::take(&mut map.chunks)
+                .into_iter()
+                .map(|chunk| (chunk.key, chunk))
+                .collect::<HashMap<_, _>>();
+            map.chunks = tilemap
+                .chunks
+                .iter()
+                .map(|chunk| match chunks.remove(&chunk.key) {
+                    Some(gpu) if gpu.revision == chunk.revision => gpu,
+                    _ => GpuChunk {
+                        key: chunk.key,
+                        revision: chunk.revision,
+                        parts: chunk
+                            .mesh
+                            .parts
+                            .iter()
+                            .map(|part| GpuChunkPart {
+                                tileset: part.tileset,
+                                vertices: buffers::vertex(context.device(), &part.vertices),
+                                num_indices: (part.vertices.len() / 4 * 6) as u32,
+                            })
+                            .collect(),
+                    },
+                })
+                .collect();
+            self.maps.push(map);
+        }
+    }
+
+    /// Draws the prepared tilemaps with `pipeline`, binding each tileset's
+    /// texture from `texture`.
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+        camera: &'a Uniform<Camera>,
+        texture: impl Fn(TextureId) -> &'a Texture,
+    ) {
+        if self.maps.is_empty() {
+            return;
+        }
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(1, camera.bind_group(), &[]);
+        pass.set_index_buffer(self.indices.slice(..), wgpu::IndexFormat::Uint16);
+        for map in &self.maps {
+            pass.set_bind_group(3, map.model.bind_group(), &[]);
+            for part in map.chunks.iter().flat_map(|chunk| &chunk.parts) {
+                let tileset = match map.tilesets.get(part.tileset) {
+                    Some(tileset) => tileset,
+                    None => continue,
+                };
+                pass.set_bind_group(0, texture(tileset.texture).bind_group(), &[]);
+                pass.set_bind_group(2, tileset.uvs.bind_group(), &[]);
+                pass.set_vertex_buffer(0, part.vertices.slice(..));
+                pass.draw_indexed(0..part.num_indices, 0, 0..1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tilemap() -> Tilemap {
+        let mut layer = TileLayer::new("ground", 40, 20);
+        layer.set_tile(0, 0, Some(Tile::new(0, 1)));
+        layer.set_tile(39, 19, Some(Tile::new(0, 2)));
+        Tilemap::new([16, 16])
+            .with_tileset(Tileset::new("tiles.png", [64, 16], [16, 16]))
+            .with_layer(layer)
+    }
+
+    fn chunk(chunks: &[SceneChunk], x: u32, y: u32) -> &SceneChunk {
+        chunks
+            .iter()
+            .find(|chunk| chunk.key.x == x && chunk.key.y == y)
+            .unwrap()
+    }
+
+    #[test]
+    fn chunks_rebuild_only_on_revision_changes() {
+        let mut tilemap = tilemap();
+        let first = tilemap.chunks();
+        // Empty chunks are not drawn
+        assert_eq!(first.len(), 2);
+
+        let second = tilemap.chunks();
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.revision, b.revision);
+            assert!(Rc::ptr_eq(&a.mesh, &b.mesh));
+        }
+
+        // Setting a tile to what it already is changes nothing
+        let layer = tilemap.layer_mut(0).unwrap();
+        layer.set_tile(0, 0, Some(Tile::new(0, 1)));
+        layer.set_tile(1, 0, Some(Tile::new(0, 3)));
+        let third = tilemap.chunks();
+        assert_ne!(chunk(&third, 0, 0).revision, chunk(&second, 0, 0).revision);
+        assert_eq!(chunk(&third, 0, 0).mesh.parts[0].vertices.len(), 8);
+        assert!(Rc::ptr_eq(
+            &chunk(&third, 2, 1).mesh,
+            &chunk(&second, 2, 1).mesh
+        ));
+
+        tilemap.layer_mut(0).unwrap().set_offset([1.0, 0.0]);
+        let fourth = tilemap.chunks();
+        for (a, b) in third.iter().zip(&fourth) {
+            assert_ne!(a.revision, b.revision);
+        }
+
+        tilemap.layer_mut(0).unwrap().visible = false;
+        assert!(tilemap.chunks().is_empty());
+    }
+
+    #[test]
+    fn chunk_meshes_place_and_flip_tiles() {
+        let mut layer = TileLayer::new("ground", 2, 2).with_opacity(0.5);
+        let flip = TileFlip {
+            horizontal: true,
+            vertical: false,
+            diagonal: true,
+        };
+        layer.set_tile(1, 1, Some(Tile::new(0, 2).with_flip(flip)));
+        let mut tilemap = Tilemap::new([16, 16])
+            .with_tileset(Tileset::new("tiles.png", [64, 32], [16, 32]))
+            .with_layer(layer);
+        let chunks = tilemap.chunks();
+        let vertices = &chunks[0].mesh.parts[0].vertices;
+
+        let positions = vertices.iter().map(|vertex| vertex.position);
+        assert_eq!(
+            positions.collect::<Vec<_>>(),
+            [
+                [1.0, 0.0, 0.0],
+                [1.0, -2.0, 0.0],
+                [2.0, -2.0, 0.0],
+                [2.0, 0.0, 0.0],
+            ]
+        );
+        let corners = vertices.iter().map(|vertex| vertex.corner);
+        assert_eq!(
+            corners.collect::<Vec<_>>(),
+            [[1.0, 0.0], [0.0, 0.0], [0.0, 1.0], [1.0, 1.0]]
+        );
+        assert!(vertices
+            .iter()
+            .all(|vertex| vertex.tile == 2 && vertex.opacity == 0.5));
+    }
+
+    #[test]
+    fn tile_uvs_change_only_with_animation_frames() {
+        let frames = vec![
+            TileAnimationFrame {
+                tile: 1,
+                duration: 0.5,
+            },
+            TileAnimationFrame {
+                tile: 2,
+                duration: 0.5,
+            },
+        ];
+        let tileset = Tileset::new("tiles.png", [64, 16], [16, 16]).with_animation(0, frames);
+        let mut tilemap = Tilemap::new([16, 16]).with_tileset(tileset);
+
+        let (revision, uvs) = tilemap.tile_uvs().remove(0);
+        assert_eq!(uvs.len(), 4);
+        assert_eq!(uvs[0], tilemap.tilesets()[0].tile_uv(1));
+
+        tilemap.time = 0.25;
+        let (same, unchanged) = tilemap.tile_uvs().remove(0);
+        assert_eq!(same, revision);
+        assert!(Rc::ptr_eq(&uvs, &unchanged));
+
+        tilemap.time = 0.75;
+        let (changed, uvs) = tilemap.tile_uvs().remove(0);
+        assert_ne!(changed, revision);
+        assert_eq!(uvs[0], tilemap.tilesets()[0].tile_uv(2));
+
+        tilemap.time = 1.25;
+        let (looped, uvs) = tilemap.tile_uvs().remove(0);
+        assert_ne!(looped, changed);
+        assert_eq!(*uvs, tilemap.tilesets()[0].uvs_at(0.0));
+    }
+
+    #[test]
+    fn uvs_are_uploaded_when_new_or_changed() {
+        let mut tilemap =
+            Tilemap::new([16, 16]).with_tileset(Tileset::new("tiles.png", [64, 16], [16, 16]));
+        let (revision, uvs) = tilemap.tile_uvs().remove(0);
+        let tileset = SceneTileset {
+            texture: TextureId::DEFAULT,
+            revision,
+            uvs,
+        };
+
+        assert_eq!(uv_upload(None, &tileset), UvUpload::Create);
+        assert_eq!(uv_upload(Some((2, revision)), &tileset), UvUpload::Create);
+        assert_eq!(uv_upload(Some((4, revision)), &tileset), UvUpload::Keep);
+        assert_eq!(
+            uv_upload(Some((8, revision - 1)), &tileset),
+            UvUpload::Update
+        );
+        assert_eq!(tileset.uvs.len(), 4);
+    }
+
+    #[test]
+    fn tilesets_fit_tiles_between_margin_and_spacing() {
+        let tileset = Tileset::new("tiles.png", [36, 20], [8, 8])
+            .with_margin(1)
+            .with_spacing(2);
+        assert_eq!(tileset.columns, 3);
+        assert_eq!(tileset.tile_count, 6);
+        assert_eq!(
+            tileset.tile_uv(4),
+            UvRect {
+                min: [11.0 / 36.0, 11.0 / 20.0],
+                max: [19.0 / 36.0, 19.0 / 20.0],
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "too many tiles")]
+    fn rejects_layers_with_too_many_cells() {
+        TileLayer::new("huge", u32::MAX, 2);
+    }
+}
@@ -0,0 +1,61 @@
+#include "common.wgsl"
+
+// Vertex shader
+
+struct TileUvs {
+    // min in xy, max in zw
+    data: array<vec4<f32>>;
+};
+struct TileCount {
+    data: u32;
+};
+struct TilemapUniform {
+    model: mat4x4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+[[group(2), binding(0)]]
+var<storage, read> tile_uvs: TileUvs;
+[[group(2), binding(1)]]
+var<uniform> tile_count: TileCount;
+
+[[group(3), binding(0)]]
+var<uniform> tilemap: TilemapUniform;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] corner: vec2<f32>;
+    [[location(2)]] tile: u32;
+    [[location(3)]] opacity: f32;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+    [[location(1)]] opacity: f32;
+};
+
+[[stage(vertex)]]
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let uv = tile_uvs.data[min(in.tile, max(tile_count.data, 1u) - 1u)];
+    var out: VertexOutput;
+    out.tex_coords = mix(uv.xy, uv.zw, in.corner);
+    out.opacity = in.opacity;
+    out.clip_position = camera.view_proj * tilemap.model * vec4<f32>(in.position, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+[[group(0), binding(0)]]
+var t_tileset: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_tileset: sampler;
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(t_tileset, s_tileset, in.tex_coords);
+    return vec4<f32>(color.rgb, color.a * in.opacity);
+}
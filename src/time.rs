@@ -0,0 +1,220 @@
+use std::time::{Duration, Instant};
+
+/// `thread::sleep` usually overshoots by up to a millisecond, so the limiter
+/// spins for the last part of the wait.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+pub const DEFAULT_UPDATES_PER_SECOND: u32 = 60;
+pub const DEFAULT_MAX_FRAME_SKIP: u32 = 5;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameTime {
+    /// Wall-clock time since the previous frame, in seconds.
+    pub dt: f32,
+    /// Length of one fixed update step, in seconds.
+    pub fixed_dt: f32,
+    /// Number of fixed updates to run this frame.
+    pub steps: u32,
+    /// How far the simulation is between the last two fixed updates, in `0..1`.
+    pub alpha: f32,
+}
+
+pub struct FrameClock {
+    last_frame: Instant,
+    accumulator: Duration,
+    fixed_step: Duration,
+    max_frame_skip: u32,
+}
+
+impl FrameClock {
+    pub fn new(updates_per_second: u32) -> Self {
+        Self {
+            last_frame: Instant::now(),
+            accumulator: Duration::ZERO,
+            fixed_step: Duration::from_secs(1) / updates_per_second.max(1),
+            max_frame_skip: DEFAULT_MAX_FRAME_SKIP,
+        }
+    }
+
+    pub fn with_max_frame_skip(mut self, max_frame_skip: u32) -> Self {
+        self.max_frame_skip = max_frame_skip.max(1);
+        self
+    }
+
+    pub fn fixed_step(&self) -> Duration {
+        self.fixed_step
+    }
+
+    pub fn reset(&mut self) {
+        self.last_frame = Instant::now();
+        self.accumulator = Duration::ZERO;
+    }
+
+    pub fn tick(&mut self) -> FrameTime {
+        self.tick_at(Instant::now())
+    }
+
+    pub fn tick_at(&mut self, now: Instant) -> FrameTime {
+        let dt = now.saturating_duration_since(self.last_frame);
+        self.last_frame = now;
+        self.accumulator += dt;
+
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_step && steps < self.max_frame_skip {
+            self.accumulator -= self.fixed_step;
+            steps += 1;
+        }
+
+        // After a long stall, drop the backlog instead of trying to catch up
+        // over the next frames and falling further behind.
+        if self.accumulator >= self.fixed_step {
+            let remainder = Duration::from_nanos(
+                (self.accumulator.as_nanos() % self.fixed_step.as_nanos()) as u64,
+            );
+            log::debug!(
+                "Frame clock dropped {:?} of simulation time",
+                self.accumulator - remainder
+            );
+            self.accumulator = remainder;
+        }
+
+        FrameTime {
+            dt: dt.as_secs_f32(),
+            fixed_dt: self.fixed_step.as_secs_f32(),
+            steps,
+            alpha: self.accumulator.as_secs_f32() / self.fixed_step.as_secs_f32(),
+        }
+    }
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_UPDATES_PER_SECOND)
+    }
+}
+
+/// Caps the frame rate on the CPU side, independent of the present mode.
+pub struct FrameLimiter {
+    max_fps: Option<u32>,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(max_fps: Option<u32>) -> Self {
+        Self {
+            max_fps: max_fps.filter(|&fps| fps > 0),
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn max_fps(&self) -> Option<u32> {
+        self.max_fps
+    }
+
+    /// `None` or `Some(0)` removes the cap.
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        self.max_fps = max_fps.filter(|&fps| fps > 0);
+        self.next_frame = Instant::now();
+    }
+
+    /// Blocks until the next frame is due.
+    pub fn wait(&mut self) {
+        let frame_time = match self.max_fps {
+            Some(fps) => Duration::from_secs(1) / fps,
+            None => return,
+        };
+
+        let now = Instant::now();
+        if self.next_frame <= now {
+            // Running behind, so start over instead of rendering a burst of
+            // frames to catch up.
+            self.next_frame = now + frame_time;
+            return;
+        }
+
+        let remaining = self.next_frame - now;
+        if remaining > SPIN_THRESHOLD {
+            std::thread::sleep(remaining - SPIN_THRESHOLD);
+        }
+        while Instant::now() < self.next_frame {
+            std::hint::spin_loop();
+        }
+        self.next_frame += frame_time;
+    }
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock_at(start: Instant, updates_per_second: u32) -> FrameClock {
+        FrameClock {
+            last_frame: start,
+            ..FrameClock::new(updates_per_second)
+        }
+    }
+
+    #[test]
+    fn accumulates_fixed_steps() {
+        let start = Instant::now();
+        let mut clock = clock_at(start, 10);
+
+        let time = clock.tick_at(start + Duration::from_millis(50));
+        assert_eq!(time.steps, 0);
+        assert!((time.dt - 0.05).abs() < 1e-6);
+        assert!((time.fixed_dt - 0.1).abs() < 1e-6);
+
+        let time = clock.tick_at(start + Duration::from_millis(120));
+        assert_eq!(time.steps, 1);
+
+        let time = clock.tick_at(start + Duration::from_millis(330));
+        assert_eq!(time.steps, 2);
+        assert!((time.alpha - 0.3).abs() < 1e-4);
+    }
+
+    #[test]
+    fn alpha_is_the_fraction_of_a_step_left_over() {
+        let start = Instant::now();
+        let mut clock = clock_at(start, 10);
+
+        let time = clock.tick_at(start + Duration::from_millis(25));
+        assert!((time.alpha - 0.25).abs() < 1e-4);
+        let time = clock.tick_at(start + Duration::from_millis(100));
+        assert_eq!(time.steps, 1);
+        assert!(time.alpha.abs() < 1e-4);
+        let time = clock.tick_at(start + Duration::from_millis(175));
+        assert_eq!(time.steps, 0);
+        assert!((time.alpha - 0.75).abs() < 1e-4);
+    }
+
+    #[test]
+    fn long_stalls_are_clamped_to_max_frame_skip() {
+        let start = Instant::now();
+        let mut clock = clock_at(start, 10).with_max_frame_skip(3);
+
+        let time = clock.tick_at(start + Duration::from_millis(1040));
+        assert_eq!(time.steps, 3);
+        // The backlog is dropped, keeping only the partial step
+        assert!((time.alpha - 0.4).abs() < 1e-4);
+
+        let time = clock.tick_at(start + Duration::from_millis(1100));
+        assert_eq!(time.steps, 1);
+        assert!(time.alpha.abs() < 1e-4);
+    }
+
+    #[test]
+    fn time_going_backwards_counts_as_zero() {
+        let start = Instant::now() + Duration::from_secs(1);
+        let mut clock = clock_at(start, 10);
+
+        let time = clock.tick_at(start - Duration::from_millis(500));
+        assert_eq!(time.steps, 0);
+        assert_eq!(time.dt, 0.0);
+    }
+}
@@ -0,0 +1,324 @@
+use cgmath::{Matrix4, One, Point3, Quaternion, Vector3};
+
+use crate::ecs::{system, Access, Entity, FnSystem, Without, World};
+
+/// An entity's translation, rotation and scale relative to its parent, or to
+/// the world if it has none.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: impl Into<Vector3<f32>>) -> Self {
+        Self {
+            translation: translation.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: Quaternion<f32>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: impl Into<Vector3<f32>>) -> Self {
+        self.scale = scale.into();
+        self
+    }
+
+    /// Scales, then rotates, then translates.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+/// The transform from an entity's local space to world space, computed from
+/// its own and its ancestors' `Transform`s by `propagate_transforms`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalTransform(pub Matrix4<f32>);
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        Self(Matrix4::one())
+    }
+}
+
+impl GlobalTransform {
+    pub fn translation(&self) -> Vector3<f32> {
+        self.0.w.truncate()
+    }
+
+    pub fn transform_point(&self, point: Point3<f32>) -> Point3<f32> {
+        cgmath::Transform::transform_point(&self.0, point)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Children(pub Vec<Entity>);
+
+/// Attaches `child` to `parent`, detaching it from its previous parent.
+///
+/// Panics if `parent` is `child` or one of its descendants.
+pub fn set_parent(world: &mut World, child: Entity, parent: Entity) {
+    let mut ancestor = Some(parent);
+    while let Some(entity) = ancestor {
+        assert!(
+            entity != child,
+            "{:?} cannot be parented to its descendant {:?}",
+            child,
+            parent
+        );
+        ancestor = world.get::<Parent>(entity).map(|parent| parent.0);
+    }
+
+    remove_parent(world, child);
+    world.insert(child, Parent(parent));
+    let mut children = world.remove::<Children>(parent).unwrap_or_default();
+    children.0.push(child);
+    world.insert(parent, children);
+}
+
+/// Detaches `child` from its parent, making it a root.
+pub fn remove_parent(world: &mut World, child: Entity) {
+    let parent = match world.remove::<Parent>(child) {
+        Some(Parent(parent)) => parent,
+        None => return,
+    };
+    if let Some(mut children) = world.get_mut::<Children>(parent) {
+        children.0.retain(|&entity| entity != child);
+    }
+}
+
+/// Despawns `entity` and all of its descendants.
+pub fn despawn_recursive(world: &mut World, entity: Entity) {
+    remove_parent(world, entity);
+    let mut stack = vec![entity];
+    while let Some(entity) = stack.pop() {
+        if let Some(children) = world.remove::<Children>(entity) {
+            stack.extend(children.0);
+        }
+        world.despawn(entity);
+    }
+}
+
+/// Computes the `GlobalTransform` of every entity with a `Transform`, walking
+/// down from the roots. Entities without a `GlobalTransform` get one once the
+/// system finishes. Entities without a `Transform` count as the identity, so
+/// their descendants still follow the nearest ancestors that have one.
+pub fn propagate_transforms() -> FnSystem<impl FnMut(&World)> {
+    system(
+        "propagate_transforms",
+        Access::new()
+            .read::<Transform>()
+            .read::<Parent>()
+            .read::<Children>()
+            .write::<GlobalTransform>(),
+        propagate,
+    )
+}
+
+fn propagate(world: &World) {
+    let transforms = match world.read::<Transform>() {
+        Some(transforms) => transforms,
+        None => return,
+    };
+    let children = world.read::<Children>();
+    let mut missing = Vec::new();
+    {
+        let mut globals = world.query::<&mut GlobalTransform>();
+        let mut stack = world
+            .query::<(&Transform, Without<Parent>)>()
+            .iter()
+            .map(|(entity, _)| (entity, Matrix4::one()))
+            .collect::<Vec<_>>();
+        stack.extend(
+            world
+                .query::<(&Children, Without<Parent>)>()
+                .iter()
+                .filter(|&(entity, _)| !transforms.contains(entity))
+                .map(|(entity, _)| (entity, Matrix4::one())),
+        );
+        while let Some((entity, parent_matrix)) = stack.pop() {
+            let matrix = match transforms.get(entity) {
+                Some(transform) => {
+                    let matrix = parent_matrix * transform.matrix();
+                    match globals.get(entity) {
+                        Some(global) => global.0 = matrix,
+                        None => missing.push((entity, GlobalTransform(matrix))),
+                    }
+                    matrix
+                }
+                None => parent_matrix,
+            };
+            let children = children.as_ref().and_then(|children| children.get(entity));
+            for &child in children.into_iter().flat_map(|children| &children.0) {
+                stack.push((child, matrix));
+            }
+        }
+    }
+    if !missing.is_empty() {
+        world.defer(move |world| {
+            for (entity, global) in missing {
+                if world.is_alive(entity) {
+                    world.insert(entity, global);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, InnerSpace, Rotation3};
+
+    use super::*;
+    use crate::ecs::Schedule;
+
+    fn propagate(world: &mut World) {
+        Schedule::new()
+            .with_system(propagate_transforms())
+            .run(world);
+    }
+
+    fn translation(world: &World, entity: Entity) -> Vector3<f32> {
+        world.get::<GlobalTransform>(entity).unwrap().translation()
+    }
+
+    fn assert_near(actual: Vector3<f32>, expected: [f32; 3]) {
+        let expected = Vector3::from(expected);
+        assert!(
+            (actual - expected).magnitude2() < 1e-8,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn propagates_through_several_levels() {
+        let mut world = World::new();
+        let root = world.spawn((Transform::from_translation([1.0, 0.0, 0.0])
+            .with_rotation(Quaternion::from_angle_z(Deg(90.0)))
+            .with_scale([2.0, 2.0, 2.0]),));
+        let child = world.spawn((Transform::from_translation([1.0, 0.0, 0.0]),));
+        let grandchild = world.spawn((Transform::from_translation([0.0, 1.0, 0.0]),));
+        set_parent(&mut world, child, root);
+        set_parent(&mut world, grandchild, child);
+
+        propagate(&mut world);
+        assert_near(translation(&world, root), [1.0, 0.0, 0.0]);
+        assert_near(translation(&world, child), [1.0, 2.0, 0.0]);
+        assert_near(translation(&world, grandchild), [-1.0, 2.0, 0.0]);
+
+        // Existing globals are updated in place
+        world.get_mut::<Transform>(root).unwrap().translation = Vector3::new(0.0, 0.0, 0.0);
+        propagate(&mut world);
+        assert_near(translation(&world, grandchild), [-2.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn propagates_through_parents_without_a_transform() {
+        let mut world = World::new();
+        let root = world.spawn((Transform::from_translation([1.0, 0.0, 0.0]),));
+        let group = world.spawn(());
+        let child = world.spawn((Transform::from_translation([0.0, 1.0, 0.0]),));
+        set_parent(&mut world, group, root);
+        set_parent(&mut world, child, group);
+
+        // A root without a transform counts as the identity too
+        let loose_group = world.spawn(());
+        let loose_child = world.spawn((Transform::from_translation([0.0, 0.0, 3.0]),));
+        set_parent(&mut world, loose_child, loose_group);
+
+        propagate(&mut world);
+        assert_near(translation(&world, child), [1.0, 1.0, 0.0]);
+        assert_near(translation(&world, loose_child), [0.0, 0.0, 3.0]);
+        assert!(!world.has::<GlobalTransform>(group));
+        assert!(!world.has::<GlobalTransform>(loose_group));
+
+        world.get_mut::<Transform>(root).unwrap().translation = Vector3::new(5.0, 0.0, 0.0);
+        propagate(&mut world);
+        assert_near(translation(&world, child), [5.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn reparenting_moves_the_child() {
+        let mut world = World::new();
+        let a = world.spawn((Transform::from_translation([1.0, 0.0, 0.0]),));
+        let b = world.spawn((Transform::from_translation([0.0, 1.0, 0.0]),));
+        let child = world.spawn((Transform::default(),));
+        set_parent(&mut world, child, a);
+        set_parent(&mut world, child, b);
+
+        assert_eq!(*world.get::<Parent>(child).unwrap(), Parent(b));
+        assert!(world.get::<Children>(a).unwrap().0.is_empty());
+        assert_eq!(world.get::<Children>(b).unwrap().0, vec![child]);
+        propagate(&mut world);
+        assert_near(translation(&world, child), [0.0, 1.0, 0.0]);
+
+        remove_parent(&mut world, child);
+        assert!(!world.has::<Parent>(child));
+        assert!(world.get::<Children>(b).unwrap().0.is_empty());
+        propagate(&mut world);
+        assert_near(translation(&world, child), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be parented to its descendant")]
+    fn parenting_to_a_descendant_panics() {
+        let mut world = World::new();
+        let root = world.spawn(());
+        let child = world.spawn(());
+        let grandchild = world.spawn(());
+        set_parent(&mut world, child, root);
+        set_parent(&mut world, grandchild, child);
+        set_parent(&mut world, root, grandchild);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be parented to its descendant")]
+    fn parenting_to_itself_panics() {
+        let mut world = World::new();
+        let entity = world.spawn(());
+        set_parent(&mut world, entity, entity);
+    }
+
+    #[test]
+    fn despawn_recursive_removes_the_subtree() {
+        let mut world = World::new();
+        let root = world.spawn(());
+        let child = world.spawn(());
+        let grandchildren = [world.spawn(()), world.spawn(())];
+        let sibling = world.spawn(());
+        set_parent(&mut world, child, root);
+        set_parent(&mut world, sibling, root);
+        for grandchild in grandchildren {
+            set_parent(&mut world, grandchild, child);
+        }
+
+        despawn_recursive(&mut world, child);
+        assert!(!world.is_alive(child));
+        assert!(grandchildren.iter().all(|&entity| !world.is_alive(entity)));
+        assert!(world.is_alive(root));
+        assert!(world.is_alive(sibling));
+        assert_eq!(world.get::<Children>(root).unwrap().0, vec![sibling]);
+    }
+}